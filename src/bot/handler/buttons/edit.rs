@@ -45,6 +45,7 @@ impl Handler {
             id,
             token,
             user,
+            channel_id,
             message,
             data,
             ..
@@ -70,7 +71,8 @@ impl Handler {
 
         let data = &self.data;
 
-        let guard = EngineGuard::lock(&data, user.id).await?;
+        let key = data.engine_key(user.id, channel_id).await;
+        let guard = EngineGuard::lock(data, key).await?;
         let mut engine = guard.engine().await.write().await;
 
         let (_, identifier, _) = match engine
@@ -87,24 +89,30 @@ impl Handler {
         };
         let channel = identifier.channel();
         let messages = identifier.messages();
+        let branch = engine.new_branch_position(&(message.id, message.channel_id).into());
 
         CreateInteractionResponse::Acknowledge
             .execute(&ctx.http, (id, &token))
             .await?;
 
         let out: anyhow::Result<(ChatMessage, MessageIdentifier)> = async {
+            // the context keeps the edit as it was written
+            let formatted = data.format_markdown(channel, content.clone()).await;
+
             misc::delete_message_batch(channel, &ctx.http, messages).await?;
 
             let messages = misc::chunk_message(
-                &content,
+                &formatted,
                 ButtonStates {
                     prev_disabled: false,
                     regen_or_next: misc::RegenOrNext::Regen,
+                    model: None,
+                    branch,
                 },
             )?;
 
             let ids = misc::send_message_batch(channel, &ctx.http, messages).await?;
-            let last_id = ids.last().ok_or(anyhow::anyhow!("no message ids"))?.clone();
+            let last_id = *ids.last().ok_or(anyhow::anyhow!("no message ids"))?;
 
             Ok((
                 ChatMessage::assistant(content),
@@ -116,11 +124,7 @@ impl Handler {
         match out {
             Ok((response, new_identifier)) => {
                 let identifier = (message.id, message.channel_id).into();
-                let messages = engine
-                    .find_mut(&identifier)
-                    .ok_or(anyhow::anyhow!("message not found in engine"))?;
-
-                messages.push(response); // pushes and selects
+                engine.push_branch(&identifier, response)?;
 
                 let message = ctx
                     .http
@@ -135,9 +139,12 @@ impl Handler {
                         async move {
                             let _ = recv.recv().await;
 
-                            let _ = message
-                                .edit(&ctx.http, EditMessage::new().components(vec![]))
-                                .await;
+                            let _ = misc::edit_message(
+                                &ctx.http,
+                                &mut message,
+                                EditMessage::new().components(vec![]),
+                            )
+                            .await;
 
                             drop(recv);
                         }
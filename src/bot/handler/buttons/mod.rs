@@ -4,6 +4,7 @@ use serenity::all::{
 };
 
 use super::Handler;
+use crate::utils::misc;
 
 mod delete;
 mod edit;
@@ -17,42 +18,42 @@ impl Handler {
         message: &mut Message,
         ctx: &Context,
     ) -> anyhow::Result<()> {
-        let buttons = CreateActionRow::Buttons(
-            message
-                .components
-                .iter()
-                .flat_map(|c| &c.components)
-                .filter_map(|c| match c {
-                    ActionRowComponent::Button(button) => Some(button),
-                    _ => None,
-                })
-                .filter_map(|button| {
-                    if let ButtonKind::NonLink { custom_id, style } = &button.data {
-                        Some(
-                            CreateButton::new(custom_id)
-                                .disabled(true)
-                                .label(
-                                    button
-                                        .label
-                                        .as_ref()
-                                        .map(|l| l.clone())
-                                        .unwrap_or("".to_string()),
-                                )
-                                .emoji(button.emoji.as_ref().map(|e| e.clone()).unwrap_or(
+        let buttons =
+            CreateActionRow::Buttons(
+                message
+                    .components
+                    .iter()
+                    .flat_map(|c| &c.components)
+                    .filter_map(|c| match c {
+                        ActionRowComponent::Button(button) => Some(button),
+                        _ => None,
+                    })
+                    .filter_map(|button| {
+                        if let ButtonKind::NonLink { custom_id, style } = &button.data {
+                            let label = button.label.clone().unwrap_or("".to_string());
+
+                            let create = CreateButton::new(custom_id).disabled(true).style(*style);
+
+                            // label-only buttons (like the model tag) don't get the fallback emoji
+                            Some(match (&button.emoji, label.is_empty()) {
+                                (None, false) => create.label(label),
+                                (emoji, _) => create.label(label).emoji(emoji.clone().unwrap_or(
                                     serenity::all::ReactionType::Unicode("🔄".to_string()),
-                                ))
-                                .style(*style),
-                        )
-                    } else {
-                        None
-                    }
-                })
-                .collect::<Vec<_>>(),
-        );
+                                )),
+                            })
+                        } else {
+                            None
+                        }
+                    })
+                    .collect::<Vec<_>>(),
+            );
 
-        message
-            .edit(&ctx.http, EditMessage::new().components(vec![buttons]))
-            .await?;
+        misc::edit_message(
+            &ctx.http,
+            message,
+            EditMessage::new().components(vec![buttons]),
+        )
+        .await?;
 
         Ok(())
     }
@@ -69,35 +70,37 @@ impl Handler {
             false => ("regen", '♻'),
         };
 
-        message
-            .edit(
-                http,
-                EditMessage::new()
-                    .content(message.content.clone())
-                    .button(
-                        CreateButton::new("prev")
-                            .label("")
-                            .emoji('⏪')
-                            .style(serenity::all::ButtonStyle::Secondary)
-                            .disabled(!backward),
-                    )
-                    .button(
-                        // regen if cant go fwd, else next
-                        CreateButton::new(can_go_fwd)
-                            .label("")
-                            .emoji(emoji)
-                            .style(serenity::all::ButtonStyle::Secondary)
-                            .disabled(false),
-                    )
-                    .button(
-                        CreateButton::new("edit")
-                            .label("")
-                            .emoji('✏')
-                            .style(serenity::all::ButtonStyle::Secondary)
-                            .disabled(false),
-                    ),
-            )
-            .await?;
+        let content = message.content.clone();
+
+        misc::edit_message(
+            http,
+            &mut message,
+            EditMessage::new()
+                .content(content)
+                .button(
+                    CreateButton::new("prev")
+                        .label("")
+                        .emoji('⏪')
+                        .style(serenity::all::ButtonStyle::Secondary)
+                        .disabled(!backward),
+                )
+                .button(
+                    // regen if cant go fwd, else next
+                    CreateButton::new(can_go_fwd)
+                        .label("")
+                        .emoji(emoji)
+                        .style(serenity::all::ButtonStyle::Secondary)
+                        .disabled(false),
+                )
+                .button(
+                    CreateButton::new("edit")
+                        .label("")
+                        .emoji('✏')
+                        .style(serenity::all::ButtonStyle::Secondary)
+                        .disabled(false),
+                ),
+        )
+        .await?;
 
         Ok(())
     }
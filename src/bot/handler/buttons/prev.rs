@@ -2,7 +2,7 @@ use anyhow::bail;
 use serenity::all::{ComponentInteraction, Context, EditMessage};
 
 use crate::{
-    chat::engine::EngineGuard,
+    chat::{context::Branches, engine::EngineGuard},
     utils::misc::{self, ButtonStates},
 };
 
@@ -10,7 +10,11 @@ use super::super::Handler;
 
 impl Handler {
     pub async fn prev(&self, component: ComponentInteraction, ctx: Context) -> anyhow::Result<()> {
-        let guard = EngineGuard::lock(&self.data, component.user.id).await?;
+        let key = self
+            .data
+            .engine_key(component.user.id, component.channel_id)
+            .await;
+        let guard = EngineGuard::lock(&self.data, key).await?;
         let mut engine = guard.engine().await.write().await;
 
         let (_, identifier, message) = engine
@@ -26,22 +30,26 @@ impl Handler {
         let channel = identifier.channel();
         let messages = identifier.messages();
         let content = backward.content();
+        let model = backward.model.clone();
         let button_states = ButtonStates {
             prev_disabled: !message.backward,
             regen_or_next: misc::RegenOrNext::Next,
+            model,
+            branch: Some(message.position()),
         };
 
         let typing = ctx.http.start_typing(channel);
 
         let result: anyhow::Result<()> = async {
             let content = content.ok_or(anyhow::anyhow!("Message does not have a content"))?;
+            let content = self.data.format_markdown(channel, content).await;
 
             misc::delete_message_batch(channel, &ctx.http, messages).await?;
 
             let messages = misc::chunk_message(&content, button_states)?;
 
             let ids = misc::send_message_batch(channel, &ctx.http, messages).await?;
-            let last_id = ids.last().ok_or(anyhow::anyhow!("no message ids"))?.clone();
+            let last_id = *ids.last().ok_or(anyhow::anyhow!("no message ids"))?;
 
             let mut message = ctx.http.get_message(channel, last_id).await?;
 
@@ -55,9 +63,12 @@ impl Handler {
                 async move {
                     let _ = recv.recv().await;
 
-                    let _ = message
-                        .edit(&ctx.http, EditMessage::new().components(vec![]))
-                        .await;
+                    let _ = misc::edit_message(
+                        &ctx.http,
+                        &mut message,
+                        EditMessage::new().components(vec![]),
+                    )
+                    .await;
 
                     drop(recv);
                 }
@@ -1,6 +1,12 @@
-use serenity::all::{ComponentInteraction, Context, EditMessage};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use serenity::all::{ComponentInteraction, Context, EditMessage, Http, UserId};
 
 use crate::{
+    bot::handler::framework::InnerData,
     chat::{
         ChatMessage,
         context::MessageIdentifier,
@@ -12,45 +18,102 @@ use crate::{
 use super::super::Handler;
 
 impl Handler {
+    /// Starts the user's regeneration cooldown, if one is configured. If it's still running
+    /// from the last one, returns how much of it is left instead.
+    pub async fn regen_cooldown(data: &InnerData, user: UserId) -> Option<Duration> {
+        let cooldown = data.config.read().await.discord.regen_cooldown_secs;
+        let cooldown = Duration::from_secs(cooldown.filter(|secs| *secs > 0)?);
+
+        let mut last_regens = data.last_regens.lock().await;
+        last_regens.retain(|_, last| last.elapsed() < cooldown);
+
+        match last_regens.get(&user) {
+            Some(last) => Some(cooldown.saturating_sub(last.elapsed())),
+            None => {
+                last_regens.insert(user, Instant::now());
+                None
+            }
+        }
+    }
+
+    /// Tells a user how long until they can regenerate again.
+    pub fn cooldown_notice(left: Duration) -> String {
+        format!(
+            "Slow down a little, you can regenerate again in {} seconds.",
+            left.as_secs() + 1
+        )
+    }
+
     pub async fn regen(&self, component: ComponentInteraction, ctx: Context) -> anyhow::Result<()> {
-        let guard = EngineGuard::lock(&self.data, component.user.id).await?;
+        let key = self
+            .data
+            .engine_key(component.user.id, component.channel_id)
+            .await;
+
+        Self::regenerate(
+            self.data.clone(),
+            key,
+            (component.message.id, component.message.channel_id).into(),
+            ctx.http.clone(),
+            None,
+            None,
+        )
+        .await
+    }
+
+    /// Regenerates the given reply as a new branch, optionally using one of the alternate models
+    /// or steered by a nudge.
+    pub async fn regenerate(
+        data: Arc<InnerData>,
+        key: UserId,
+        identifier: MessageIdentifier,
+        http: Arc<Http>,
+        model: Option<String>,
+        nudge: Option<String>,
+    ) -> anyhow::Result<()> {
+        let guard = EngineGuard::lock(&data, key).await?;
         let mut engine = guard.engine().await.write().await;
 
         // uses this to find the error before other things
-        let (_, identifier, _) = engine
-            .find_full_mut(&(component.message.id, component.message.channel_id).into())
+        let (_, found, _) = engine
+            .find_full_mut(&identifier)
             .ok_or(anyhow::anyhow!("Message not found in engine"))?;
-        let channel = identifier.channel();
-        let messages = identifier.messages();
+        let channel = found.channel();
+        let messages = found.messages();
 
-        let typing = ctx.http.start_typing(channel);
+        let branch = engine.new_branch_position(&identifier);
+
+        let typing = http.start_typing(channel);
 
         let out: anyhow::Result<(ChatMessage, MessageIdentifier)> = async {
-            let response = engine
-                .user_prompt(
-                    None,
-                    Some(ContextType::Regen(
-                        (component.message.id, component.message.channel_id).into(),
-                    )),
-                )
-                .await?;
+            let context = ContextType::Regen {
+                identifier: identifier.clone(),
+                model,
+                nudge,
+            };
+
+            let response = engine.user_prompt(None, None, Some(context)).await?;
 
             let content = response
                 .content()
                 .ok_or(anyhow::anyhow!("Message does not have a content"))?;
 
-            misc::delete_message_batch(channel, &ctx.http, messages).await?;
+            let content = data.format_markdown(channel, content).await;
+
+            misc::delete_message_batch(channel, &http, messages).await?;
 
             let messages = misc::chunk_message(
                 &content,
                 ButtonStates {
                     prev_disabled: false,
                     regen_or_next: misc::RegenOrNext::Regen,
+                    model: response.model.clone(),
+                    branch,
                 },
             )?;
 
-            let ids = misc::send_message_batch(channel, &ctx.http, messages).await?;
-            let last_id = ids.last().ok_or(anyhow::anyhow!("no message ids"))?.clone();
+            let ids = misc::send_message_batch(channel, &http, messages).await?;
+            let last_id = *ids.last().ok_or(anyhow::anyhow!("no message ids"))?;
 
             Ok((response, (last_id, channel, ids).into()))
         }
@@ -60,15 +123,9 @@ impl Handler {
 
         match out {
             Ok((message, new_identifier)) => {
-                let identifier = (component.message.id, component.message.channel_id).into();
-                let messages = engine
-                    .find_mut(&identifier)
-                    .ok_or(anyhow::anyhow!("message not found in engine"))?;
-
-                messages.push(message); // pushes and selects
+                engine.push_branch(&identifier, message)?;
 
-                let message = ctx
-                    .http
+                let message = http
                     .get_message(new_identifier.channel(), new_identifier.message())
                     .await;
 
@@ -76,13 +133,16 @@ impl Handler {
 
                 if let Ok(mut message) = message {
                     tokio::spawn({
-                        let mut recv = self.data.msg_channel.0.subscribe();
+                        let mut recv = data.msg_channel.0.subscribe();
                         async move {
                             let _ = recv.recv().await;
 
-                            let _ = message
-                                .edit(&ctx.http, EditMessage::new().components(vec![]))
-                                .await;
+                            let _ = misc::edit_message(
+                                &http,
+                                &mut message,
+                                EditMessage::new().components(vec![]),
+                            )
+                            .await;
 
                             drop(recv);
                         }
@@ -97,3 +157,64 @@ impl Handler {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::bot::handler::framework::Data;
+
+    use super::*;
+
+    async fn cooling_down(name: &str, secs: Option<u64>) -> Data {
+        let data = InnerData::scratch(name);
+        data.config.write().await.discord.regen_cooldown_secs = secs;
+        data
+    }
+
+    #[tokio::test]
+    async fn regenerating_again_waits_for_the_cooldown() {
+        let data = cooling_down("regen-cooldown", Some(5)).await;
+        let user = UserId::new(1751);
+
+        assert!(Handler::regen_cooldown(&data, user).await.is_none());
+
+        let left = Handler::regen_cooldown(&data, user).await.unwrap();
+        assert!(left > Duration::from_secs(4) && left <= Duration::from_secs(5));
+        assert_eq!(
+            Handler::cooldown_notice(left),
+            "Slow down a little, you can regenerate again in 5 seconds."
+        );
+
+        // others aren't held up by it
+        assert!(
+            Handler::regen_cooldown(&data, UserId::new(1752))
+                .await
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn the_cooldown_ends_with_its_window() {
+        let data = cooling_down("regen-cooldown-window", Some(5)).await;
+        let user = UserId::new(1753);
+
+        let started = Instant::now() - Duration::from_secs(4);
+        data.last_regens.lock().await.insert(user, started);
+        let left = Handler::regen_cooldown(&data, user).await.unwrap();
+        assert!(left <= Duration::from_secs(1));
+
+        let started = Instant::now() - Duration::from_secs(5);
+        data.last_regens.lock().await.insert(user, started);
+        assert!(Handler::regen_cooldown(&data, user).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn there_is_no_cooldown_unless_configured() {
+        for secs in [None, Some(0)] {
+            let data = cooling_down("regen-no-cooldown", secs).await;
+            let user = UserId::new(1754);
+
+            assert!(Handler::regen_cooldown(&data, user).await.is_none());
+            assert!(Handler::regen_cooldown(&data, user).await.is_none());
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use chrono::DateTime;
+use serenity::all::{Context, GetMessages, Message, UserId};
+
+use crate::{
+    chat::{ChatMessage, context::MessageRole, engine::EngineGuard},
+    utils::misc,
+};
+
+use super::super::Handler;
+
+impl Handler {
+    /// Seeds a fresh context with the channel's recent messages, so the bot doesn't start out
+    /// unaware of the conversation it's joining. Only the author's messages and the bot's own are
+    /// taken, or everyone's in group mode.
+    pub async fn backfill(&self, ctx: &Context, key: UserId, msg: &Message) -> anyhow::Result<()> {
+        let Some(count) = self.data.config.read().await.context.backfill else {
+            return Ok(());
+        };
+
+        let guard = EngineGuard::lock(&self.data, key).await?;
+        let mut engine = guard.engine().await.write().await;
+
+        // only contexts that haven't heard from anyone yet, a greeting doesn't count
+        if engine.latest_with_role(MessageRole::User).is_some() {
+            return Ok(());
+        }
+
+        let count = backfill_limit(count, engine.config.max_stm);
+        if count == 0 {
+            return Ok(());
+        }
+
+        let bot = ctx.cache.current_user().id;
+        let group_mode = engine.group_mode();
+
+        let history = msg
+            .channel_id
+            .messages(&ctx.http, GetMessages::new().before(msg.id).limit(count))
+            .await?;
+
+        let mut added = 0;
+        // history comes newest first
+        for message in history.iter().rev() {
+            if !relevant(message, bot, msg.author.id, group_mode)
+                || message.content.trim().is_empty()
+            {
+                continue;
+            }
+
+            let mut chat_message = match message.author.id == bot {
+                true => ChatMessage::assistant(message.content.clone()),
+                false => {
+                    let author = misc::author_name(
+                        &message.author,
+                        message.guild_id,
+                        message
+                            .member
+                            .as_ref()
+                            .and_then(|member| member.nick.as_deref()),
+                    );
+
+                    engine
+                        .prompt_builder()
+                        .content(Some(message.content.clone()))
+                        .author(engine.group_author(Some(author)))
+                        .build()?
+                        .try_into()?
+                }
+            };
+            if let Some(sent_at) = DateTime::from_timestamp(message.timestamp.unix_timestamp(), 0) {
+                chat_message.sent_at = sent_at;
+            }
+
+            engine.add_message(chat_message, (message.id, message.channel_id));
+            added += 1;
+        }
+
+        if added > 0 {
+            log::info!(
+                "backfilled {added} messages from {} into {key}",
+                msg.channel_id
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// How many messages to fetch, leaving room for the message that triggered the backfill so it
+/// doesn't drain right away. Discord hands out at most 100 at once.
+fn backfill_limit(count: usize, max_stm: usize) -> u8 {
+    count.min(max_stm.saturating_sub(1)).min(100) as u8
+}
+
+/// Whether a message from the channel's history belongs in the context: the bot's own, and the
+/// author's (or in group mode everyone's, other bots aside).
+fn relevant(message: &Message, bot: UserId, author: UserId, group_mode: bool) -> bool {
+    message.author.id == bot || (!message.author.bot && (group_mode || message.author.id == author))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from(id: u64, bot: bool) -> Message {
+        let mut message = Message::default();
+        message.author.id = UserId::new(id);
+        message.author.bot = bot;
+        message
+    }
+
+    #[test]
+    fn backfills_fit_in_the_context() {
+        assert_eq!(backfill_limit(20, 50), 20);
+        assert_eq!(backfill_limit(20, 10), 9);
+        assert_eq!(backfill_limit(500, 1000), 100);
+        assert_eq!(backfill_limit(20, 1), 0);
+        assert_eq!(backfill_limit(20, 0), 0);
+    }
+
+    #[test]
+    fn only_the_conversation_is_backfilled() {
+        let (bot, author) = (UserId::new(1), UserId::new(2));
+
+        assert!(relevant(&from(1, true), bot, author, false));
+        assert!(relevant(&from(2, false), bot, author, false));
+        assert!(!relevant(&from(3, false), bot, author, false));
+        assert!(!relevant(&from(4, true), bot, author, false));
+
+        // groups take in everyone but other bots
+        assert!(relevant(&from(3, false), bot, author, true));
+        assert!(!relevant(&from(4, true), bot, author, true));
+    }
+}
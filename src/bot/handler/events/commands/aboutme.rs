@@ -0,0 +1,110 @@
+use poise::CreateReply;
+
+use crate::bot::handler::events::HandlerResult;
+use crate::bot::handler::framework::Context;
+use crate::chat::engine::EngineGuard;
+
+/// Goes into every system prompt, so it's kept short.
+const MAX_ABOUT_LENGTH: usize = 1000;
+
+/// Sets, shows or resets what the bot knows about you
+pub async fn aboutme(
+    ctx: Context<'_>,
+    about: Option<String>,
+    reset: Option<bool>,
+) -> HandlerResult<'_, ()> {
+    let data = ctx.data().clone();
+
+    let result: anyhow::Result<()> = async {
+        let key = data.engine_key(ctx.author().id, ctx.channel_id()).await;
+
+        let content = if reset.unwrap_or(false) {
+            let user_about = {
+                let mut config = data.config.write().await;
+                config
+                    .save_preferences(key, |preferences| preferences.about = None)
+                    .await?;
+                config.context.system.user_about.clone()
+            };
+
+            let guard = EngineGuard::lock(&data, key).await?;
+            guard.engine().await.write().await.config.system.user_about = user_about;
+
+            "Reset your about to the default".to_string()
+        } else if let Some(about) = about {
+            let about = validate_about(&about)?;
+
+            data.config
+                .write()
+                .await
+                .save_preferences(key, |preferences| preferences.about = Some(about.clone()))
+                .await?;
+
+            // the running engine got its about when it was created, update it too
+            let guard = EngineGuard::lock(&data, key).await?;
+            guard.engine().await.write().await.config.system.user_about = Some(about);
+
+            "Successfully updated your about".to_string()
+        } else {
+            let guard = EngineGuard::lock(&data, key).await?;
+            let engine = guard.engine().await.read().await;
+
+            match &engine.config.system.user_about {
+                Some(about) => format!("Your about:\n```\n{about}\n```"),
+                None => "You don't have an about set".to_string(),
+            }
+        };
+
+        ctx.send(CreateReply::default().content(content).ephemeral(true))
+            .await?;
+
+        Ok(())
+    }
+    .await;
+
+    match result {
+        Ok(_) => HandlerResult::ok(()),
+        Err(why) => HandlerResult::err(why, ctx),
+    }
+}
+
+/// Trims the about, refusing empty and overly long ones.
+fn validate_about(about: &str) -> anyhow::Result<String> {
+    let about = about.trim().to_string();
+
+    if about.is_empty() {
+        return Err(anyhow::anyhow!(
+            "Your about can't be empty, use `reset` to go back to the default"
+        ));
+    }
+
+    let length = about.chars().count();
+    if length > MAX_ABOUT_LENGTH {
+        return Err(anyhow::anyhow!(
+            "Your about is {length} characters long, it can be at most {MAX_ABOUT_LENGTH}"
+        ));
+    }
+
+    Ok(about)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn abouts_are_trimmed() {
+        assert_eq!(validate_about("  I like tea \n").unwrap(), "I like tea");
+    }
+
+    #[test]
+    fn blank_abouts_are_refused() {
+        assert!(validate_about(" \n\t").is_err());
+    }
+
+    #[test]
+    fn abouts_are_limited_in_characters_not_bytes() {
+        assert!(validate_about(&"ü".repeat(MAX_ABOUT_LENGTH)).is_ok());
+        assert!(validate_about(&"ü".repeat(MAX_ABOUT_LENGTH + 1)).is_err());
+    }
+}
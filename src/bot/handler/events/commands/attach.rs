@@ -0,0 +1,54 @@
+use poise::CreateReply;
+use serenity::all::Attachment;
+
+use crate::bot::handler::events::HandlerResult;
+use crate::bot::handler::framework::Context;
+use crate::chat::engine::EngineGuard;
+
+/// Documents are sent along with every request, keep them reasonably small.
+const MAX_DOCUMENT_SIZE: u32 = 64 * 1024;
+
+/// Attaches a text document to your conversation
+pub async fn attach(ctx: Context<'_>, file: Attachment) -> HandlerResult<'_, ()> {
+    let data = ctx.data();
+
+    let result: anyhow::Result<()> = async {
+        if file.size > MAX_DOCUMENT_SIZE {
+            return Err(anyhow::anyhow!(
+                "\"{}\" is too big, documents can be at most {} KiB",
+                file.filename,
+                MAX_DOCUMENT_SIZE / 1024
+            ));
+        }
+
+        ctx.defer_ephemeral().await?;
+
+        let text = String::from_utf8(file.download().await?)
+            .map_err(|_| anyhow::anyhow!("\"{}\" is not a text file", file.filename))?;
+
+        let key = data.engine_key(ctx.author().id, ctx.channel_id()).await;
+        let guard = EngineGuard::lock(data, key).await?;
+        let mut engine = guard.engine().await.write().await;
+
+        engine.attach_document(file.filename.clone(), text);
+
+        ctx.send(
+            CreateReply::default()
+                .content(format!(
+                    "Attached `{}` to the conversation ({} documents attached)",
+                    file.filename,
+                    engine.documents().len()
+                ))
+                .ephemeral(true),
+        )
+        .await?;
+
+        Ok(())
+    }
+    .await;
+
+    match result {
+        Ok(_) => HandlerResult::ok(()),
+        Err(why) => HandlerResult::err(why, ctx),
+    }
+}
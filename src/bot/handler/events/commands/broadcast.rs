@@ -0,0 +1,60 @@
+use poise::CreateReply;
+use serenity::all::{ChannelId, CreateMessage};
+
+use crate::bot::handler::events::HandlerResult;
+use crate::bot::handler::framework::Context;
+use crate::chat::engine::EngineGuard;
+use crate::utils::misc;
+
+/// Has the bot announce something in character
+pub async fn broadcast(
+    ctx: Context<'_>,
+    channel: ChannelId,
+    prompt: String,
+) -> HandlerResult<'_, ()> {
+    let data = ctx.data();
+
+    let result: anyhow::Result<()> = async {
+        if prompt.trim().is_empty() {
+            anyhow::bail!("There is nothing to broadcast");
+        }
+
+        ctx.defer_ephemeral().await?;
+
+        // the configured persona, none of the owner's own customizations
+        let system = data.config.read().await.context.system.clone();
+
+        let key = data.engine_key(ctx.author().id, ctx.channel_id()).await;
+        let guard = EngineGuard::lock(data, key).await?;
+        let message = guard
+            .engine()
+            .await
+            .read()
+            .await
+            .broadcast(system, prompt.trim())
+            .await?;
+
+        // mentions are sanitized by the client's default allowed mentions, like any other reply
+        let message = data.format_markdown(channel, message).await;
+        let messages = misc::chunk_string(&message)
+            .into_iter()
+            .map(|chunk| CreateMessage::new().content(chunk))
+            .collect();
+        misc::send_message_batch(channel, ctx.http(), messages).await?;
+
+        ctx.send(
+            CreateReply::default()
+                .content(format!("Broadcast posted in <#{channel}>"))
+                .ephemeral(true),
+        )
+        .await?;
+
+        Ok(())
+    }
+    .await;
+
+    match result {
+        Ok(_) => HandlerResult::ok(()),
+        Err(why) => HandlerResult::err(why, ctx),
+    }
+}
@@ -0,0 +1,65 @@
+use poise::CreateReply;
+use serenity::all::CreateEmbed;
+
+use crate::bot::handler::events::HandlerResult;
+use crate::bot::handler::framework::Context;
+use crate::chat::client::EmbeddingCache;
+
+/// Shows how big the embedding cache is and how often it's hit
+pub async fn cache_stats(ctx: Context<'_>) -> HandlerResult<'_, ()> {
+    let result: anyhow::Result<()> = async {
+        let stats = EmbeddingCache::global().stats();
+
+        ctx.send(
+            CreateReply::default()
+                .embed(
+                    CreateEmbed::default()
+                        .title("Embedding cache")
+                        .field("Entries", stats.entries.to_string(), true)
+                        .field(
+                            "Hit rate",
+                            format!(
+                                "{:.1}% ({} hits, {} misses)",
+                                stats.hit_rate() * 100.0,
+                                stats.hits,
+                                stats.misses
+                            ),
+                            true,
+                        ),
+                )
+                .ephemeral(true),
+        )
+        .await?;
+
+        Ok(())
+    }
+    .await;
+
+    match result {
+        Ok(_) => HandlerResult::ok(()),
+        Err(why) => HandlerResult::err(why, ctx),
+    }
+}
+
+/// Empties the embedding cache
+pub async fn cache_clear(ctx: Context<'_>) -> HandlerResult<'_, ()> {
+    let result: anyhow::Result<()> = async {
+        let entries = EmbeddingCache::global().stats().entries;
+        EmbeddingCache::global().clear();
+
+        ctx.send(
+            CreateReply::default()
+                .content(format!("Cleared {entries} cached embeddings"))
+                .ephemeral(true),
+        )
+        .await?;
+
+        Ok(())
+    }
+    .await;
+
+    match result {
+        Ok(_) => HandlerResult::ok(()),
+        Err(why) => HandlerResult::err(why, ctx),
+    }
+}
@@ -0,0 +1,119 @@
+use poise::CreateReply;
+use serenity::all::{CreateMessage, ReactionType};
+
+use crate::bot::handler::Handler;
+use crate::bot::handler::events::HandlerResult;
+use crate::bot::handler::framework::Context;
+use crate::utils::misc;
+
+/// Talks to the bot, for channels that only allow commands
+pub async fn chat(ctx: Context<'_>, message: String) -> HandlerResult<'_, ()> {
+    let data = ctx.data();
+    let handler = Handler { data: data.clone() };
+
+    let result: anyhow::Result<()> = async {
+        data.msg_channel.0.send(message.clone()).unwrap();
+
+        let key = data.engine_key(ctx.author().id, ctx.channel_id()).await;
+        if data.paused.read().await.contains(&key) {
+            anyhow::bail!("The conversation is paused, use /resume to pick it back up");
+        }
+
+        if let Some(refusal) = handler.moderate(key, &message).await? {
+            ctx.send(CreateReply::default().content(refusal).ephemeral(true))
+                .await?;
+            return Ok(());
+        }
+
+        let triggered = handler.check_triggers(&message).await?;
+        // same name as a message would get, nickname included
+        let member = ctx.author_member().await;
+        let author = misc::author_name(
+            ctx.author(),
+            ctx.guild_id(),
+            member.as_ref().and_then(|member| member.nick.as_deref()),
+        );
+
+        // commands don't leave a message behind, so the prompt is quoted for the reply to answer
+        let quote = ctx
+            .say(quote(&author, &message))
+            .await?
+            .into_message()
+            .await?;
+        let channel = quote.channel_id;
+        let http = ctx.serenity_context().http.clone();
+
+        if let Some(reply) = triggered.reply {
+            let reply = misc::reply_to(vec![CreateMessage::new().content(reply)], &quote);
+            misc::send_message_batch(channel, &http, reply).await?;
+            return Ok(());
+        }
+
+        let typing = http.start_typing(channel);
+
+        let result = handler
+            .converse(
+                &http,
+                key,
+                (message.clone(), (quote.id, channel).into()),
+                (ctx.author().id, author),
+                triggered.system_note,
+                |messages| async {
+                    misc::send_message_batch(channel, &http, misc::reply_to(messages, &quote)).await
+                },
+            )
+            .await;
+
+        typing.stop();
+
+        for emoji in result? {
+            if let Err(why) = quote.react(&http, ReactionType::Unicode(emoji)).await {
+                log::warn!("failed to add the model's reaction: {why:?}");
+            }
+        }
+
+        Ok(())
+    }
+    .await;
+
+    match result {
+        Ok(_) => HandlerResult::ok(()),
+        Err(why) => HandlerResult::err(why, ctx),
+    }
+}
+
+/// How the prompt of a /chat is shown in the channel, for the reply to answer.
+fn quote(author: &str, message: &str) -> String {
+    format!("**{author}:** {message}")
+}
+
+#[cfg(test)]
+mod tests {
+    use serenity::all::{GuildId, Member, PartialMember, User};
+
+    use super::*;
+
+    #[test]
+    fn slash_prompts_name_their_author_like_messages() {
+        let mut user = User::default();
+        user.name = "alice_1".to_string();
+        user.global_name = Some("Alice".to_string());
+        let guild = Some(GuildId::new(1));
+
+        // a message carries a partial member, the command context a full one
+        let partial: PartialMember =
+            serde_json::from_value(serde_json::json!({ "roles": [], "nick": "Ally" })).unwrap();
+        let mut member = Member::default();
+        member.nick = Some("Ally".to_string());
+
+        let from_message = misc::author_name(&user, guild, partial.nick.as_deref());
+        let from_slash = misc::author_name(&user, guild, member.nick.as_deref());
+        assert_eq!(from_slash, from_message);
+        assert_eq!(quote(&from_slash, "hi"), "**Ally:** hi");
+    }
+
+    #[test]
+    fn prompts_are_quoted_with_their_author() {
+        assert_eq!(quote("Alice", "Hi there!"), "**Alice:** Hi there!");
+    }
+}
@@ -2,34 +2,40 @@ use poise::CreateReply;
 
 use tokio::sync::RwLock;
 
+use crate::bot::handler::Handler;
 use crate::bot::handler::events::HandlerResult;
 use crate::bot::handler::framework::Context;
 use crate::chat;
 use crate::utils::macros::config;
 
 /// Clears the current context window and reloads the engine
-pub async fn clear(ctx: Context<'_>) -> HandlerResult<()> {
+pub async fn clear(ctx: Context<'_>) -> HandlerResult<'_, ()> {
     let data = ctx.data().clone();
 
+    let key = data.engine_key(ctx.author().id, ctx.channel_id()).await;
+
     let mut user_map = data.user_map.write().await;
 
     let result: anyhow::Result<()> = async {
         let new_engine = {
             let config = config!(data);
-            let mut new_engine = chat::engine::ChatEngine::new(config, ctx.author().id).await?;
+            let mut new_engine = chat::engine::ChatEngine::new(config, key).await?;
             new_engine.clear_context();
             RwLock::new(new_engine)
         };
 
-        let author = ctx.author();
-
-        user_map.remove(&author.id);
-        user_map.insert(author.id, new_engine);
-
-        let mut freewill_map = data.freewill_map.write().await;
-        if let Some(handle) = freewill_map.remove(&author.id) {
-            handle.abort();
-        }
+        user_map.remove(&key);
+        user_map.insert(key, new_engine);
+
+        let restart = data
+            .config
+            .read()
+            .await
+            .freewill
+            .restart_after_clear
+            .unwrap_or(false)
+            .then(|| (ctx.channel_id(), ctx.serenity_context().http.clone()));
+        Handler::freewill_reset(&data, key, restart).await;
 
         ctx.send(
             CreateReply::default()
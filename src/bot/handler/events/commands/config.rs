@@ -33,6 +33,12 @@ pub enum KeyChoice {
     VectorSize,
     #[name = "Memory Similarity Threshold"]
     SimilarityThreshold,
+    #[name = "Max Memories Per User"]
+    MaxMemoriesPerUser,
+    #[name = "Memory Recall Boost"]
+    RecallBoost,
+    #[name = "Memory Recency Weight"]
+    RecencyWeight,
     #[name = "QDrant Host"]
     QdrantHost,
     #[name = "QDrant Port"]
@@ -59,6 +65,9 @@ impl Display for KeyChoice {
             Self::TopP => write!(f, "Top P"),
             Self::VectorSize => write!(f, "Vector Size"),
             Self::SimilarityThreshold => write!(f, "Memory Similarity Threshold"),
+            Self::MaxMemoriesPerUser => write!(f, "Max Memories Per User"),
+            Self::RecallBoost => write!(f, "Memory Recall Boost"),
+            Self::RecencyWeight => write!(f, "Memory Recency Weight"),
             Self::QdrantHost => write!(f, "QDrant Host"),
             Self::QdrantPort => write!(f, "QDrant Port"),
             Self::QdrantHttps => write!(f, "Use HTTPs for QDrant"),
@@ -66,7 +75,11 @@ impl Display for KeyChoice {
     }
 }
 
-pub async fn config(ctx: Context<'_>, key: KeyChoice, value: Option<String>) -> HandlerResult<()> {
+pub async fn config(
+    ctx: Context<'_>,
+    key: KeyChoice,
+    value: Option<String>,
+) -> HandlerResult<'_, ()> {
     let data = ctx.data().clone();
 
     let result: anyhow::Result<()> = async {
@@ -195,6 +208,40 @@ pub async fn config(ctx: Context<'_>, key: KeyChoice, value: Option<String>) ->
                             })?);
                     }
                 }
+                KeyChoice::MaxMemoriesPerUser => {
+                    if value.trim().is_empty() {
+                        config.llm.max_memories_per_user = None;
+                    } else {
+                        config.llm.max_memories_per_user =
+                            Some(value.parse::<u64>().map_err(|_| {
+                                anyhow::anyhow!(
+                                    "Invalid value \"{value}\", please provide a valid number"
+                                )
+                            })?);
+                    }
+                }
+                KeyChoice::RecallBoost => {
+                    if value.trim().is_empty() {
+                        config.llm.recall_boost = None;
+                    } else {
+                        config.llm.recall_boost = Some(value.parse::<f32>().map_err(|_| {
+                            anyhow::anyhow!(
+                                "Invalid value \"{value}\", please provide a valid number"
+                            )
+                        })?);
+                    }
+                }
+                KeyChoice::RecencyWeight => {
+                    if value.trim().is_empty() {
+                        config.llm.recency_weight = None;
+                    } else {
+                        config.llm.recency_weight = Some(value.parse::<f32>().map_err(|_| {
+                            anyhow::anyhow!(
+                                "Invalid value \"{value}\", please provide a valid number"
+                            )
+                        })?);
+                    }
+                }
                 KeyChoice::QdrantHost => {
                     config.llm.qdrant_host = value.clone();
                 }
@@ -224,6 +271,8 @@ pub async fn config(ctx: Context<'_>, key: KeyChoice, value: Option<String>) ->
             }
 
             config.async_save().await?;
+            drop(config);
+            data.check_credentials().await;
 
             ctx.send(
                 CreateReply::default()
@@ -289,6 +338,27 @@ pub async fn config(ctx: Context<'_>, key: KeyChoice, value: Option<String>) ->
                         .map(|similarity_threshold| similarity_threshold.to_string()),
                     false,
                 ),
+                KeyChoice::MaxMemoriesPerUser => (
+                    config
+                        .llm
+                        .max_memories_per_user
+                        .map(|max_memories_per_user| max_memories_per_user.to_string()),
+                    false,
+                ),
+                KeyChoice::RecallBoost => (
+                    config
+                        .llm
+                        .recall_boost
+                        .map(|recall_boost| recall_boost.to_string()),
+                    false,
+                ),
+                KeyChoice::RecencyWeight => (
+                    config
+                        .llm
+                        .recency_weight
+                        .map(|recency_weight| recency_weight.to_string()),
+                    false,
+                ),
                 KeyChoice::QdrantHost => (Some(config.llm.qdrant_host.clone()), false),
                 KeyChoice::QdrantPort => (
                     config
@@ -306,15 +376,13 @@ pub async fn config(ctx: Context<'_>, key: KeyChoice, value: Option<String>) ->
                 ),
             };
 
-            let value = if let Some(value) = value {
-                Some(if sensitive {
+            let value = value.map(|value| {
+                if sensitive {
                     format!("||`{}`||", value)
                 } else {
                     format!("`{}`", value)
-                })
-            } else {
-                None
-            };
+                }
+            });
 
             let content = match value {
                 Some(value) => format!("The value for {key} is {value}"),
@@ -0,0 +1,179 @@
+use std::time::Duration;
+
+use log::LevelFilter;
+use poise::CreateReply;
+use serenity::all::{CreateEmbed, UserId};
+
+use crate::bot::handler::events::HandlerResult;
+use crate::bot::handler::framework::Context;
+use crate::chat::engine::EngineGuard;
+use crate::utils::{log::Logger, time_to_string};
+
+/// Shows where the time and tokens of your last turn went
+pub async fn debug_last(ctx: Context<'_>) -> HandlerResult<'_, ()> {
+    let data = ctx.data();
+
+    let result: anyhow::Result<()> = async {
+        let key = data.engine_key(ctx.author().id, ctx.channel_id()).await;
+        let guard = EngineGuard::lock(data, key).await?;
+        let engine = guard.engine().await.read().await;
+
+        let metrics = engine
+            .client
+            .last_turn()
+            .ok_or(anyhow::anyhow!("There's no finished turn to show yet"))?;
+
+        let millis = |duration: Duration| format!("{} ms", duration.as_millis());
+
+        ctx.send(
+            CreateReply::default()
+                .embed(
+                    CreateEmbed::default()
+                        .title("Last turn")
+                        .description(format!(
+                            "Finished {} ago",
+                            time_to_string(chrono::Utc::now() - metrics.finished_at)
+                        ))
+                        .field("Total", millis(metrics.total), true)
+                        .field("Embedding", millis(metrics.embedding), true)
+                        .field("Recall", millis(metrics.recall), true)
+                        .field(
+                            "Completion",
+                            format!(
+                                "{} ({} request{})",
+                                millis(metrics.completion),
+                                metrics.completions,
+                                if metrics.completions == 1 { "" } else { "s" }
+                            ),
+                            true,
+                        )
+                        .field(
+                            "Tools",
+                            format!(
+                                "{} ({} call{})",
+                                millis(metrics.tools),
+                                metrics.tool_calls,
+                                if metrics.tool_calls == 1 { "" } else { "s" }
+                            ),
+                            true,
+                        )
+                        .field(
+                            "Tokens",
+                            format!("~{} in / ~{} out", metrics.tokens_in, metrics.tokens_out),
+                            true,
+                        ),
+                )
+                .ephemeral(true),
+        )
+        .await?;
+
+        Ok(())
+    }
+    .await;
+
+    match result {
+        Ok(_) => HandlerResult::ok(()),
+        Err(why) => HandlerResult::err(why, ctx),
+    }
+}
+
+/// Shows which of your memories a message would recall, and how closely they match
+pub async fn debug_recall(
+    ctx: Context<'_>,
+    text: String,
+    limit: Option<u64>,
+) -> HandlerResult<'_, ()> {
+    let data = ctx.data();
+
+    let result: anyhow::Result<()> = async {
+        ctx.defer_ephemeral().await?;
+
+        let key = data.engine_key(ctx.author().id, ctx.channel_id()).await;
+        let guard = EngineGuard::lock(data, key).await?;
+        let engine = guard.engine().await.read().await;
+
+        let (threshold, scored) = engine
+            .client
+            .preview_recall(&text, limit.unwrap_or(5).clamp(1, 25))
+            .await?;
+
+        let mut embed =
+            CreateEmbed::default()
+                .title("Recall preview")
+                .description(match scored.is_empty() {
+                    true => "There are no memories to recall".to_string(),
+                    false => format!("Memories need a score above {threshold:.3} to be recalled"),
+                });
+
+        for (score, memory) in scored {
+            let mut content = memory.content;
+            if content.chars().count() > 1000 {
+                content = content.chars().take(999).collect::<String>() + "…";
+            }
+
+            embed = embed.field(
+                format!(
+                    "{score:.3}{} · `{}`",
+                    if score > threshold {
+                        ""
+                    } else {
+                        " (not recalled)"
+                    },
+                    memory.id
+                ),
+                content,
+                false,
+            );
+        }
+
+        ctx.send(CreateReply::default().embed(embed).ephemeral(true))
+            .await?;
+
+        Ok(())
+    }
+    .await;
+
+    match result {
+        Ok(_) => HandlerResult::ok(()),
+        Err(why) => HandlerResult::err(why, ctx),
+    }
+}
+
+/// Turns verbose logging of a user's interactions on or off
+pub async fn debug_user(
+    ctx: Context<'_>,
+    user: UserId,
+    enabled: bool,
+    unredacted: Option<bool>,
+) -> HandlerResult<'_, ()> {
+    let result: anyhow::Result<()> = async {
+        // conversation content is only logged at trace
+        let level = match unredacted.unwrap_or(false) {
+            true => LevelFilter::Trace,
+            false => LevelFilter::Debug,
+        };
+
+        Logger::set_verbose(user, enabled.then_some(level));
+        log::info!(
+            "verbose logging of {user} turned {} by {}",
+            if enabled { "on" } else { "off" },
+            ctx.author().id
+        );
+
+        let content = match enabled {
+            true => format!("Logging <@{user}>'s interactions at {level}"),
+            false => format!("Logging <@{user}>'s interactions like everything else"),
+        };
+
+        ctx.send(CreateReply::default().content(content).ephemeral(true))
+            .await?;
+
+        Ok(())
+    }
+    .await;
+
+    match result {
+        Ok(_) => HandlerResult::ok(()),
+        Err(why) => HandlerResult::err(why, ctx),
+    }
+}
@@ -0,0 +1,93 @@
+use poise::CreateReply;
+
+use crate::bot::handler::events::HandlerResult;
+use crate::bot::handler::framework::Context;
+use crate::chat::{
+    audit::feedback::FeedbackRecord,
+    context::{MessageRole, UserPrompt},
+    engine::EngineGuard,
+};
+
+#[derive(Debug, poise::ChoiceParameter)]
+pub enum Rating {
+    #[name = "👍 Good"]
+    Good,
+    #[name = "👎 Bad"]
+    Bad,
+}
+
+impl Rating {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Rating::Good => "good",
+            Rating::Bad => "bad",
+        }
+    }
+}
+
+/// Rates the bot's last reply, to help improve it
+pub async fn feedback(
+    ctx: Context<'_>,
+    rating: Rating,
+    comment: Option<String>,
+) -> HandlerResult<'_, ()> {
+    let data = ctx.data();
+
+    let result: anyhow::Result<()> = async {
+        let path = match &data.config.read().await.feedback {
+            Some(feedback) if feedback.enabled => feedback.path.clone(),
+            _ => anyhow::bail!("Feedback isn't being collected"),
+        };
+
+        let key = data.engine_key(ctx.author().id, ctx.channel_id()).await;
+        let guard = EngineGuard::lock(data, key).await?;
+        let engine = guard.engine().await.read().await;
+
+        let reply = engine
+            .latest_with_role(MessageRole::Assistant)
+            .map(|messages| messages.selected().clone())
+            .ok_or(anyhow::anyhow!("There's no reply to rate yet"))?;
+        let prompt = engine
+            .latest_with_role(MessageRole::User)
+            .and_then(|messages| UserPrompt::try_from(messages.selected().clone()).ok())
+            .and_then(|prompt| prompt.content);
+
+        let record = FeedbackRecord {
+            timestamp: chrono::Utc::now(),
+            user_id: ctx.author().id.get(),
+            engine: key.get(),
+            rating: rating.as_str(),
+            comment,
+            prompt,
+            reply: reply.content().unwrap_or_default(),
+            model: reply
+                .model
+                .clone()
+                .unwrap_or_else(|| engine.client.model().to_string()),
+            temperature: engine.client.temperature(),
+            max_tokens: engine.client.max_tokens(),
+        };
+        record.append(&path).await?;
+
+        log::info!(
+            "recorded {} feedback from {}",
+            rating.as_str(),
+            ctx.author().id
+        );
+
+        ctx.send(
+            CreateReply::default()
+                .content("Thanks for the feedback!")
+                .ephemeral(true),
+        )
+        .await?;
+
+        Ok(())
+    }
+    .await;
+
+    match result {
+        Ok(_) => HandlerResult::ok(()),
+        Err(why) => HandlerResult::err(why, ctx),
+    }
+}
@@ -0,0 +1,107 @@
+use poise::CreateReply;
+use serenity::all::AutocompleteChoice;
+
+use crate::bot::handler::events::HandlerResult;
+use crate::bot::handler::framework::Context;
+use crate::chat::engine::EngineGuard;
+
+/// Discord caps autocomplete choice names at 100 characters, the id takes up to 24 of them.
+const PREVIEW_LENGTH: usize = 72;
+
+/// Deletes one of your long-term memories
+pub async fn forget(ctx: Context<'_>, memory: String) -> HandlerResult<'_, ()> {
+    let data = ctx.data();
+
+    let result: anyhow::Result<()> = async {
+        let id = memory.trim().parse::<u64>().map_err(|_| {
+            anyhow::anyhow!("Invalid memory \"{memory}\", please pick one of the suggestions")
+        })?;
+
+        let key = data.engine_key(ctx.author().id, ctx.channel_id()).await;
+        let guard = EngineGuard::lock(data, key).await?;
+        let engine = guard.engine().await.read().await;
+
+        engine.client.forget(id).await?;
+
+        ctx.send(
+            CreateReply::default()
+                .content(format!("Forgot memory `{id}`"))
+                .ephemeral(true),
+        )
+        .await?;
+
+        Ok(())
+    }
+    .await;
+
+    match result {
+        Ok(_) => HandlerResult::ok(()),
+        Err(why) => HandlerResult::err(why, ctx),
+    }
+}
+
+/// Suggests the invoking user's memories closest to what was typed so far (or the most
+/// recent ones), shown as truncated previews with the memory id as the value
+pub async fn autocomplete_memory(ctx: Context<'_>, partial: &str) -> Vec<AutocompleteChoice> {
+    let data = ctx.data();
+    let key = data.engine_key(ctx.author().id, ctx.channel_id()).await;
+
+    let Ok(guard) = EngineGuard::lock(data, key).await else {
+        return vec![];
+    };
+    let engine = guard.engine().await.read().await;
+
+    let memories = match engine.client.memories(partial, 25).await {
+        Ok(memories) => memories,
+        Err(why) => {
+            log::warn!("failed to look up memories for autocomplete: {why:?}");
+            return vec![];
+        }
+    };
+
+    memories
+        .into_iter()
+        .map(|memory| {
+            AutocompleteChoice::new(
+                format!("{} (#{})", preview(&memory.content), memory.id),
+                memory.id.to_string(),
+            )
+        })
+        .collect()
+}
+
+/// A single line of the memory, cut off after [PREVIEW_LENGTH] characters.
+fn preview(content: &str) -> String {
+    let content = content.replace('\n', " ");
+
+    match content.chars().count() > PREVIEW_LENGTH {
+        true => format!(
+            "{}…",
+            content.chars().take(PREVIEW_LENGTH).collect::<String>()
+        ),
+        false => content,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_memories_are_shown_on_one_line() {
+        assert_eq!(
+            preview("- likes tea\n- hates mornings"),
+            "- likes tea - hates mornings"
+        );
+    }
+
+    #[test]
+    fn long_memories_fit_discords_choice_names() {
+        let preview = preview(&"é".repeat(200));
+
+        assert_eq!(preview.chars().count(), PREVIEW_LENGTH + 1);
+        assert!(preview.ends_with('…'));
+        // with the longest id appended it still stays within the 100 character limit
+        assert!(format!("{preview} (#{})", u64::MAX).chars().count() <= 100);
+    }
+}
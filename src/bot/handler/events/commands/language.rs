@@ -0,0 +1,121 @@
+use poise::CreateReply;
+
+use crate::bot::handler::events::HandlerResult;
+use crate::bot::handler::framework::Context;
+use crate::chat::engine::{ChatEngine, EngineGuard};
+
+/// Sets or shows the language the bot speaks with you
+pub async fn language(ctx: Context<'_>, language: Option<String>) -> HandlerResult<'_, ()> {
+    let data = ctx.data().clone();
+
+    let result: anyhow::Result<()> = async {
+        let key = data.engine_key(ctx.author().id, ctx.channel_id()).await;
+
+        let content = if let Some(language) = language {
+            let languages = data
+                .config
+                .read()
+                .await
+                .context
+                .languages
+                .clone()
+                .unwrap_or_default();
+            let language = pick_language(&language, languages)?;
+
+            data.config
+                .write()
+                .await
+                .save_preferences(key, |preferences| {
+                    preferences.language = Some(language.clone())
+                })
+                .await?;
+
+            // the running engine got its language when it was created, update it too
+            let guard = EngineGuard::lock(&data, key).await?;
+            guard.engine().await.write().await.config.system.language =
+                ChatEngine::language(language.clone());
+
+            match language.as_str() {
+                "auto" => "The bot will now speak whatever language fits".to_string(),
+                language => format!("The bot will now speak `{language}`"),
+            }
+        } else {
+            let config = data.config.read().await;
+
+            let language = config
+                .preferences(key)
+                .language
+                .map(ChatEngine::language)
+                .unwrap_or(config.context.system.language.clone());
+
+            match language {
+                Some(language) => format!("The bot speaks `{language}` with you"),
+                None => "The bot speaks whatever language fits".to_string(),
+            }
+        };
+
+        ctx.send(CreateReply::default().content(content).ephemeral(true))
+            .await?;
+
+        Ok(())
+    }
+    .await;
+
+    match result {
+        Ok(_) => HandlerResult::ok(()),
+        Err(why) => HandlerResult::err(why, ctx),
+    }
+}
+
+/// Matches a choice against the allowed languages, giving back their spelling of it.
+fn pick_language(choice: &str, languages: Vec<String>) -> anyhow::Result<String> {
+    if languages.is_empty() {
+        anyhow::bail!("No languages to pick from are configured");
+    }
+
+    match choice.trim() {
+        choice if choice.eq_ignore_ascii_case("auto") => Ok("auto".to_string()),
+        choice => languages
+            .into_iter()
+            .find(|language| language.eq_ignore_ascii_case(choice))
+            .ok_or(anyhow::anyhow!(
+                "\"{choice}\" is not one of the allowed languages"
+            )),
+    }
+}
+
+/// Suggests the allowed languages containing what was typed so far
+pub async fn autocomplete_language(ctx: Context<'_>, partial: &str) -> Vec<String> {
+    let partial = partial.to_lowercase();
+    let languages = ctx.data().config.read().await.context.languages.clone();
+
+    std::iter::once("auto".to_string())
+        .chain(languages.into_iter().flatten())
+        .filter(|language| language.to_lowercase().contains(&partial))
+        .take(25)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn languages_are_picked_from_the_allowed_list() {
+        let languages = || vec!["English".to_string(), "Deutsch".to_string()];
+
+        assert_eq!(pick_language(" deutsch ", languages()).unwrap(), "Deutsch");
+        assert_eq!(pick_language("AUTO", languages()).unwrap(), "auto");
+        assert!(pick_language("Klingon", languages()).is_err());
+        assert!(pick_language("auto", vec![]).is_err());
+    }
+
+    #[test]
+    fn auto_leaves_the_language_open() {
+        assert_eq!(ChatEngine::language("Auto".to_string()), None);
+        assert_eq!(
+            ChatEngine::language("Deutsch".to_string()).as_deref(),
+            Some("Deutsch")
+        );
+    }
+}
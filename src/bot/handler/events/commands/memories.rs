@@ -0,0 +1,90 @@
+use poise::CreateReply;
+use serenity::all::{Attachment, CreateAttachment};
+
+use crate::bot::handler::events::HandlerResult;
+use crate::bot::handler::framework::Context;
+use crate::chat::{MemoryExport, engine::EngineGuard};
+
+/// Exports are mostly vectors, this leaves room for a few thousand memories.
+const MAX_IMPORT_SIZE: u32 = 24 * 1024 * 1024;
+
+/// Sends you all of your memories as a JSON file
+pub async fn memories_export(ctx: Context<'_>) -> HandlerResult<'_, ()> {
+    let data = ctx.data();
+
+    let result: anyhow::Result<()> = async {
+        ctx.defer_ephemeral().await?;
+
+        let key = data.engine_key(ctx.author().id, ctx.channel_id()).await;
+        let guard = EngineGuard::lock(data, key).await?;
+        let engine = guard.engine().await.read().await;
+
+        let export = engine.client.export_memories().await?;
+        let count = export.memories.len();
+        let json = serde_json::to_vec(&export)?;
+
+        ctx.send(
+            CreateReply::default()
+                .content(format!("Exported {count} memories"))
+                .attachment(CreateAttachment::bytes(json, "memories.json"))
+                .ephemeral(true),
+        )
+        .await?;
+
+        Ok(())
+    }
+    .await;
+
+    match result {
+        Ok(_) => HandlerResult::ok(()),
+        Err(why) => HandlerResult::err(why, ctx),
+    }
+}
+
+/// Adds the memories from an exported JSON file
+pub async fn memories_import(ctx: Context<'_>, file: Attachment) -> HandlerResult<'_, ()> {
+    let data = ctx.data();
+
+    let result: anyhow::Result<()> = async {
+        if file.size > MAX_IMPORT_SIZE {
+            return Err(anyhow::anyhow!(
+                "\"{}\" is too big, imports can be at most {} MiB",
+                file.filename,
+                MAX_IMPORT_SIZE / 1024 / 1024
+            ));
+        }
+
+        ctx.defer_ephemeral().await?;
+
+        let export: MemoryExport =
+            serde_json::from_slice(&file.download().await?).map_err(|why| {
+                anyhow::anyhow!("\"{}\" is not a memory export: {why}", file.filename)
+            })?;
+
+        let key = data.engine_key(ctx.author().id, ctx.channel_id()).await;
+        let guard = EngineGuard::lock(data, key).await?;
+        let engine = guard.engine().await.read().await;
+
+        let (count, reembedded) = engine.client.import_memories(export).await?;
+
+        ctx.send(
+            CreateReply::default()
+                .content(match reembedded {
+                    true => format!(
+                        "Imported {count} memories, they were re-embedded for the current model"
+                    ),
+                    false => format!("Imported {count} memories"),
+                })
+                .ephemeral(true),
+        )
+        .await?;
+
+        Ok(())
+    }
+    .await;
+
+    match result {
+        Ok(_) => HandlerResult::ok(()),
+        Err(why) => HandlerResult::err(why, ctx),
+    }
+}
@@ -0,0 +1,56 @@
+use poise::CreateReply;
+
+use tokio::sync::RwLock;
+
+use crate::bot::handler::events::HandlerResult;
+use crate::bot::handler::framework::Context;
+use crate::chat;
+use crate::utils::macros::config;
+
+/// Re-embeds the stored memories with the current embedding model and reloads the engine
+pub async fn migrate(ctx: Context<'_>) -> HandlerResult<'_, ()> {
+    let data = ctx.data();
+
+    let config = config!(&data);
+
+    let key = data.engine_key(ctx.author().id, ctx.channel_id()).await;
+
+    let result: anyhow::Result<()> = async {
+        ctx.defer_ephemeral().await?;
+
+        // re-embedding can take a while, the engine keeps serving the old memories meanwhile
+        let migrated = chat::client::CompletionAgent::migrate(
+            config.llm.clone(),
+            key,
+            &config.context.system.chatbot_name,
+        )
+        .await?;
+
+        let mut user_map = data.user_map.write().await;
+
+        match user_map.get(&key) {
+            Some(engine) => engine.write().await.reload(config).await?,
+            None => {
+                let engine = chat::engine::ChatEngine::new(config, key).await?;
+                user_map.insert(key, RwLock::new(engine));
+            }
+        }
+
+        ctx.send(
+            CreateReply::default()
+                .content(format!(
+                    "migrated {migrated} memories to the current embedding model and reloaded engine."
+                ))
+                .ephemeral(true),
+        )
+        .await?;
+
+        Ok(())
+    }
+    .await;
+
+    match result {
+        Ok(_) => HandlerResult::ok(()),
+        Err(why) => HandlerResult::err(why, ctx),
+    }
+}
@@ -1,7 +1,51 @@
+mod aboutme;
+mod attach;
+mod broadcast;
+mod cache;
+mod chat;
 mod clear;
 mod config;
+mod debug;
+mod feedback;
+mod forget;
+mod language;
+mod memories;
+mod migrate;
+mod pause;
+mod regenerate;
+mod register;
 mod reload;
+mod remember;
+mod resume;
+mod standing;
+mod stats;
+mod timezone;
+mod tools;
+mod tts;
+mod uptime;
 
+pub use aboutme::*;
+pub use attach::*;
+pub use broadcast::*;
+pub use cache::*;
+pub use chat::*;
 pub use clear::*;
 pub use config::*;
+pub use debug::*;
+pub use feedback::*;
+pub use forget::*;
+pub use language::*;
+pub use memories::*;
+pub use migrate::*;
+pub use pause::*;
+pub use regenerate::*;
+pub use register::*;
 pub use reload::*;
+pub use remember::*;
+pub use resume::*;
+pub use standing::*;
+pub use stats::*;
+pub use timezone::*;
+pub use tools::*;
+pub use tts::*;
+pub use uptime::*;
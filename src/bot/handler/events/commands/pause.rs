@@ -0,0 +1,29 @@
+use poise::CreateReply;
+
+use crate::bot::handler::events::HandlerResult;
+use crate::bot::handler::framework::Context;
+
+/// Stops replying to you until /resume, while still keeping up with the conversation
+pub async fn pause(ctx: Context<'_>) -> HandlerResult<'_, ()> {
+    let data = ctx.data();
+
+    let result: anyhow::Result<()> = async {
+        let key = data.engine_key(ctx.author().id, ctx.channel_id()).await;
+
+        let content = match data.paused.write().await.insert(key) {
+            true => "Paused, your messages will still be remembered. Use /resume to continue",
+            false => "Already paused, use /resume to continue",
+        };
+
+        ctx.send(CreateReply::default().content(content).ephemeral(true))
+            .await?;
+
+        Ok(())
+    }
+    .await;
+
+    match result {
+        Ok(_) => HandlerResult::ok(()),
+        Err(why) => HandlerResult::err(why, ctx),
+    }
+}
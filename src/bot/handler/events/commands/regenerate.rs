@@ -0,0 +1,96 @@
+use poise::CreateReply;
+
+use crate::bot::handler::Handler;
+use crate::bot::handler::events::HandlerResult;
+use crate::bot::handler::framework::Context;
+use crate::chat::{context::MessageRole, engine::EngineGuard};
+
+/// Regenerates the latest reply as a new branch, optionally with one of the alternate models
+/// or steered by a nudge that isn't stored
+pub async fn regenerate(
+    ctx: Context<'_>,
+    model: Option<String>,
+    nudge: Option<String>,
+) -> HandlerResult<'_, ()> {
+    let data = ctx.data().clone();
+
+    let result: anyhow::Result<()> = async {
+        if let Some(left) = Handler::regen_cooldown(&data, ctx.author().id).await {
+            ctx.send(
+                CreateReply::default()
+                    .content(Handler::cooldown_notice(left))
+                    .ephemeral(true),
+            )
+            .await?;
+
+            return Ok(());
+        }
+
+        ctx.defer_ephemeral().await?;
+
+        let key = data.engine_key(ctx.author().id, ctx.channel_id()).await;
+
+        let identifier = {
+            let guard = EngineGuard::lock(&data, key).await?;
+            let engine = guard.engine().await.read().await;
+
+            if let Some(model) = model
+                .as_ref()
+                .filter(|model| !engine.client.alternate_models().contains(model))
+            {
+                anyhow::bail!("{model} is not one of the alternate models");
+            }
+
+            match engine.latest_with_role_full(MessageRole::Assistant) {
+                Some((identifier, _)) if !identifier.random => identifier.clone(),
+                _ => anyhow::bail!("there is no reply to regenerate"),
+            }
+        };
+
+        Handler::regenerate(
+            data.clone(),
+            key,
+            identifier,
+            ctx.serenity_context().http.clone(),
+            model.clone(),
+            nudge,
+        )
+        .await?;
+
+        let content = match model {
+            Some(model) => format!("regenerated the latest reply with {model}."),
+            None => "regenerated the latest reply.".to_string(),
+        };
+
+        ctx.send(CreateReply::default().content(content).ephemeral(true))
+            .await?;
+
+        Ok(())
+    }
+    .await;
+
+    match result {
+        Ok(_) => HandlerResult::ok(()),
+        Err(why) => HandlerResult::err(why, ctx),
+    }
+}
+
+/// Suggests the alternate models of the caller's engine
+pub async fn autocomplete_model(ctx: Context<'_>, partial: &str) -> Vec<String> {
+    let data = ctx.data();
+    let key = data.engine_key(ctx.author().id, ctx.channel_id()).await;
+
+    let Ok(guard) = EngineGuard::lock(data, key).await else {
+        return vec![];
+    };
+    let engine = guard.engine().await.read().await;
+
+    let partial = partial.to_lowercase();
+    engine
+        .client
+        .alternate_models()
+        .into_iter()
+        .filter(|model| model.to_lowercase().contains(&partial))
+        .take(25)
+        .collect()
+}
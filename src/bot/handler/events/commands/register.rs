@@ -0,0 +1,39 @@
+use poise::CreateReply;
+
+use crate::bot::handler::events::HandlerResult;
+use crate::bot::handler::framework::{Context, register_commands, registration_guilds};
+
+/// Registers the bot's commands again, in the configured guilds or globally
+pub async fn register(ctx: Context<'_>) -> HandlerResult<'_, ()> {
+    let data = ctx.data().clone();
+
+    let result: anyhow::Result<()> = async {
+        let guilds = data.config.read().await.discord.command_guilds.clone();
+        let commands = &ctx.framework().options().commands;
+
+        register_commands(ctx.http(), commands, guilds.clone()).await?;
+
+        let content = match registration_guilds(guilds) {
+            Some(guilds) => format!(
+                "Registered {} commands in {} guild(s)",
+                commands.len(),
+                guilds.len()
+            ),
+            None => format!(
+                "Registered {} commands globally, they can take up to an hour to show up",
+                commands.len()
+            ),
+        };
+
+        ctx.send(CreateReply::default().content(content).ephemeral(true))
+            .await?;
+
+        Ok(())
+    }
+    .await;
+
+    match result {
+        Ok(_) => HandlerResult::ok(()),
+        Err(why) => HandlerResult::err(why, ctx),
+    }
+}
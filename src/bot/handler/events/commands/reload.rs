@@ -4,24 +4,33 @@ use tokio::sync::RwLock;
 
 use crate::bot::handler::events::HandlerResult;
 use crate::bot::handler::framework::Context;
-use crate::chat;
+use crate::chat::{self, client::MissingCredentials};
 use crate::utils::macros::config;
 
 /// Reloads the engine without clearing the context window
-pub async fn reload(ctx: Context<'_>) -> HandlerResult<()> {
+pub async fn reload(ctx: Context<'_>) -> HandlerResult<'_, ()> {
     let data = ctx.data();
 
     let config = config!(&data);
 
+    // the engine would just fail to build, the way it'll keep failing until there's a key
+    if !data.check_credentials().await {
+        let why = anyhow::Error::new(MissingCredentials::for_config(&config.llm));
+        return HandlerResult::err(why, ctx);
+    }
+
+    let key = data.engine_key(ctx.author().id, ctx.channel_id()).await;
+
     let mut user_map = data.user_map.write().await;
 
     let result: anyhow::Result<()> = async {
-        let author = ctx.author();
-        let engine = match user_map.remove(&author.id) {
-            Some(engine) => chat::engine::ChatEngine::reload(engine.into_inner(), config).await,
-            None => chat::engine::ChatEngine::new(config, ctx.author().id).await,
-        }?;
-        user_map.insert(author.id, RwLock::new(engine));
+        match user_map.get(&key) {
+            Some(engine) => engine.write().await.reload(config).await?,
+            None => {
+                let engine = chat::engine::ChatEngine::new(config, key).await?;
+                user_map.insert(key, RwLock::new(engine));
+            }
+        }
 
         ctx.send(
             CreateReply::default()
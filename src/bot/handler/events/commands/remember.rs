@@ -0,0 +1,42 @@
+use poise::CreateReply;
+
+use crate::bot::handler::events::HandlerResult;
+use crate::bot::handler::framework::Context;
+use crate::chat::engine::EngineGuard;
+
+/// Adds a long-term memory, as is
+pub async fn remember(
+    ctx: Context<'_>,
+    memory: String,
+    tag: Option<String>,
+) -> HandlerResult<'_, ()> {
+    let data = ctx.data();
+
+    let result: anyhow::Result<()> = async {
+        if memory.trim().is_empty() {
+            anyhow::bail!("There is nothing to remember");
+        }
+
+        let key = data.engine_key(ctx.author().id, ctx.channel_id()).await;
+        let guard = EngineGuard::lock(data, key).await?;
+        let engine = guard.engine().await.read().await;
+
+        let tag = tag.as_deref().map(str::trim).filter(|tag| !tag.is_empty());
+        let id = engine.client.remember(memory.trim(), tag).await?;
+
+        let content = match tag {
+            Some(tag) => format!("Remembered as memory `{id}`, tagged `{tag}`"),
+            None => format!("Remembered as memory `{id}`"),
+        };
+        ctx.send(CreateReply::default().content(content).ephemeral(true))
+            .await?;
+
+        Ok(())
+    }
+    .await;
+
+    match result {
+        Ok(_) => HandlerResult::ok(()),
+        Err(why) => HandlerResult::err(why, ctx),
+    }
+}
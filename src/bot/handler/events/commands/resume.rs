@@ -0,0 +1,92 @@
+use poise::CreateReply;
+use serenity::all::EditMessage;
+
+use crate::bot::handler::events::HandlerResult;
+use crate::bot::handler::framework::Context;
+use crate::chat::engine::{ContextType, EngineGuard};
+use crate::utils::misc::{self, ButtonStates};
+
+/// Resumes replying after /pause, optionally answering what came in meanwhile
+pub async fn resume(ctx: Context<'_>, catch_up: Option<bool>) -> HandlerResult<'_, ()> {
+    let data = ctx.data();
+
+    let result: anyhow::Result<()> = async {
+        let key = data.engine_key(ctx.author().id, ctx.channel_id()).await;
+
+        if !data.paused.write().await.remove(&key) {
+            ctx.send(CreateReply::default().content("Not paused").ephemeral(true))
+                .await?;
+
+            return Ok(());
+        }
+
+        ctx.send(CreateReply::default().content("Resumed").ephemeral(true))
+            .await?;
+
+        if !catch_up.unwrap_or(false) {
+            return Ok(());
+        }
+
+        let http = ctx.serenity_context().http.clone();
+        let channel = ctx.channel_id();
+
+        let guard = EngineGuard::lock(data, key).await?;
+        let mut engine = guard.engine().await.write().await;
+
+        let typing = http.start_typing(channel);
+
+        let result: anyhow::Result<_> = async {
+            let response = engine
+                .user_prompt(None, None, Some(ContextType::CatchUp))
+                .await?;
+
+            let content = response
+                .content()
+                .ok_or(anyhow::anyhow!("message does not have a content"))?;
+            let content = data.format_markdown(channel, content).await;
+
+            let messages = misc::chunk_message(
+                &content,
+                ButtonStates {
+                    prev_disabled: true,
+                    regen_or_next: misc::RegenOrNext::Regen,
+                    model: None,
+                    branch: None,
+                },
+            )?;
+
+            let ids = misc::send_message_batch(channel, &http, messages).await?;
+            let last_id = *ids.last().ok_or(anyhow::anyhow!("no message ids"))?;
+
+            engine.add_message(response, (last_id, channel, ids));
+
+            Ok(last_id)
+        }
+        .await;
+
+        typing.stop();
+
+        let mut message = http.get_message(channel, result?).await?;
+
+        tokio::spawn({
+            let mut recv = data.msg_channel.0.subscribe();
+            async move {
+                let _ = recv.recv().await;
+
+                let _ =
+                    misc::edit_message(&http, &mut message, EditMessage::new().components(vec![]))
+                        .await;
+
+                drop(recv);
+            }
+        });
+
+        Ok(())
+    }
+    .await;
+
+    match result {
+        Ok(_) => HandlerResult::ok(()),
+        Err(why) => HandlerResult::err(why, ctx),
+    }
+}
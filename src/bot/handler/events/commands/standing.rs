@@ -0,0 +1,119 @@
+use poise::CreateReply;
+
+use crate::bot::handler::events::HandlerResult;
+use crate::bot::handler::framework::Context;
+use crate::chat::engine::EngineGuard;
+
+/// Goes into every system prompt, so it's kept short.
+const MAX_STANDING_LENGTH: usize = 2000;
+
+/// Sets, shows or resets the context that survives /clear
+pub async fn standing(
+    ctx: Context<'_>,
+    context: Option<String>,
+    reset: Option<bool>,
+) -> HandlerResult<'_, ()> {
+    let data = ctx.data().clone();
+
+    let result: anyhow::Result<()> = async {
+        let key = data.engine_key(ctx.author().id, ctx.channel_id()).await;
+
+        let content = if reset.unwrap_or(false) {
+            let standing_context = {
+                let mut config = data.config.write().await;
+                config
+                    .save_preferences(key, |preferences| preferences.standing_context = None)
+                    .await?;
+                config.context.system.standing_context.clone()
+            };
+
+            let guard = EngineGuard::lock(&data, key).await?;
+            guard
+                .engine()
+                .await
+                .write()
+                .await
+                .config
+                .system
+                .standing_context = standing_context;
+
+            "Reset the standing context to the default".to_string()
+        } else if let Some(context) = context {
+            let context = checked_standing(&context)?;
+
+            data.config
+                .write()
+                .await
+                .save_preferences(key, |preferences| {
+                    preferences.standing_context = Some(context.clone())
+                })
+                .await?;
+
+            // kept in the preferences rather than the context, so /clear doesn't drop it
+            let guard = EngineGuard::lock(&data, key).await?;
+            guard
+                .engine()
+                .await
+                .write()
+                .await
+                .config
+                .system
+                .standing_context = Some(context);
+
+            "Successfully updated the standing context".to_string()
+        } else {
+            let guard = EngineGuard::lock(&data, key).await?;
+            let engine = guard.engine().await.read().await;
+
+            match &engine.config.system.standing_context {
+                Some(context) => format!("Standing context:\n```\n{context}\n```"),
+                None => "There is no standing context".to_string(),
+            }
+        };
+
+        ctx.send(CreateReply::default().content(content).ephemeral(true))
+            .await?;
+
+        Ok(())
+    }
+    .await;
+
+    match result {
+        Ok(_) => HandlerResult::ok(()),
+        Err(why) => HandlerResult::err(why, ctx),
+    }
+}
+
+/// Trims the standing context, refusing it when empty or too long for every prompt.
+fn checked_standing(context: &str) -> anyhow::Result<String> {
+    let context = context.trim().to_string();
+
+    if context.is_empty() {
+        anyhow::bail!("The standing context can't be empty, use `reset` to go back to the default");
+    }
+
+    let length = context.chars().count();
+    if length > MAX_STANDING_LENGTH {
+        anyhow::bail!(
+            "The standing context is {length} characters long, it can be at most {MAX_STANDING_LENGTH}"
+        );
+    }
+
+    Ok(context)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn standing_context_is_trimmed_and_bounded() {
+        assert_eq!(
+            checked_standing("  Always answer in rhymes.\n").unwrap(),
+            "Always answer in rhymes."
+        );
+        assert!(checked_standing(" \n ").is_err());
+        assert!(checked_standing(&"ä".repeat(MAX_STANDING_LENGTH)).is_ok());
+        assert!(checked_standing(&"ä".repeat(MAX_STANDING_LENGTH + 1)).is_err());
+    }
+}
@@ -0,0 +1,82 @@
+use poise::CreateReply;
+use serenity::all::CreateEmbed;
+
+use crate::bot::handler::events::HandlerResult;
+use crate::bot::handler::framework::Context;
+use crate::chat::{client::EmbeddingCache, engine::EngineGuard};
+use crate::utils::time_to_string;
+
+/// Shows how full the current context window is
+pub async fn stats(ctx: Context<'_>) -> HandlerResult<'_, ()> {
+    let data = ctx.data();
+
+    let result: anyhow::Result<()> = async {
+        let key = data.engine_key(ctx.author().id, ctx.channel_id()).await;
+        let guard = EngineGuard::lock(data, key).await?;
+        let engine = guard.engine().await.read().await;
+
+        let provider = data.config.read().await.llm.provider;
+        let cache = EmbeddingCache::global().stats();
+
+        let tokens = match engine.config.max_stm_tokens {
+            Some(max_tokens) => format!("~{} / {max_tokens}", engine.tokens()),
+            None => format!("~{}", engine.tokens()),
+        };
+
+        ctx.send(
+            CreateReply::default()
+                .embed(
+                    CreateEmbed::default()
+                        .title("Context stats")
+                        .field(
+                            "Messages",
+                            format!("{} / {}", engine.message_count(), engine.config.max_stm),
+                            true,
+                        )
+                        .field("Tokens", tokens, true)
+                        .field(
+                            "Last message",
+                            format!("{} ago", time_to_string(engine.time_since_last())),
+                            true,
+                        )
+                        .field(
+                            "Provider",
+                            format!("{provider} ({})", engine.client.capabilities()),
+                            false,
+                        )
+                        .field(
+                            "Memory recall",
+                            match (engine.client.memory_error(), engine.client.recalling()) {
+                                // embed fields hold at most 1024 characters
+                                (Some(why), _) => format!(
+                                    "Unavailable, chatting without it ({})",
+                                    why.chars().take(900).collect::<String>()
+                                ),
+                                (None, true) => "Automatic and by the model (tool)".to_string(),
+                                (None, false) => "Automatic only".to_string(),
+                            },
+                            false,
+                        )
+                        .field(
+                            "Embedding cache",
+                            format!(
+                                "{} entries, {:.1}% hit rate",
+                                cache.entries,
+                                cache.hit_rate() * 100.0
+                            ),
+                            false,
+                        ),
+                )
+                .ephemeral(true),
+        )
+        .await?;
+
+        Ok(())
+    }
+    .await;
+
+    match result {
+        Ok(_) => HandlerResult::ok(()),
+        Err(why) => HandlerResult::err(why, ctx),
+    }
+}
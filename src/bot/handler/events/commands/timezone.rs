@@ -0,0 +1,94 @@
+use chrono_tz::{TZ_VARIANTS, Tz};
+use poise::CreateReply;
+
+use crate::bot::handler::events::HandlerResult;
+use crate::bot::handler::framework::Context;
+use crate::chat::engine::EngineGuard;
+
+/// Sets or shows the timezone used for your conversation's time
+pub async fn timezone(ctx: Context<'_>, timezone: Option<String>) -> HandlerResult<'_, ()> {
+    let data = ctx.data().clone();
+
+    let result: anyhow::Result<()> = async {
+        let key = data.engine_key(ctx.author().id, ctx.channel_id()).await;
+
+        let content = if let Some(timezone) = timezone {
+            let timezone = timezone.trim().parse::<Tz>().map_err(|_| {
+                anyhow::anyhow!(
+                    "Invalid timezone \"{timezone}\", please provide a valid IANA timezone name (like America/Sao_Paulo)"
+                )
+            })?;
+
+            data.config
+                .write()
+                .await
+                .save_preferences(key, |preferences| preferences.timezone = Some(timezone))
+                .await?;
+
+            // the running engine got its timezone when it was created, update it too
+            let guard = EngineGuard::lock(&data, key).await?;
+            guard.engine().await.write().await.config.system.timezone = Some(timezone);
+
+            format!("Successfully updated your timezone to `{}`", timezone.name())
+        } else {
+            let config = data.config.read().await;
+
+            let timezone = config
+                .preferences(key)
+                .timezone
+                .or(config.context.system.timezone);
+
+            match timezone {
+                Some(timezone) => format!("Your timezone is `{}`", timezone.name()),
+                None => "No timezone is set, times are in UTC".to_string(),
+            }
+        };
+
+        ctx.send(CreateReply::default().content(content).ephemeral(true))
+            .await?;
+
+        Ok(())
+    }
+    .await;
+
+    match result {
+        Ok(_) => HandlerResult::ok(()),
+        Err(why) => HandlerResult::err(why, ctx),
+    }
+}
+
+/// Suggests timezone names containing what was typed so far
+pub async fn autocomplete_timezone(_ctx: Context<'_>, partial: &str) -> Vec<String> {
+    matching_timezones(partial)
+}
+
+/// Up to 25 (Discord's limit on choices) timezone names containing `partial`, ignoring case.
+fn matching_timezones(partial: &str) -> Vec<String> {
+    let partial = partial.to_lowercase();
+
+    TZ_VARIANTS
+        .iter()
+        .map(|timezone| timezone.name())
+        .filter(|name| name.to_lowercase().contains(&partial))
+        .take(25)
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timezones_are_matched_ignoring_case() {
+        let matches = matching_timezones("sao_PAULO");
+
+        assert_eq!(matches, ["America/Sao_Paulo"]);
+    }
+
+    #[test]
+    fn suggestions_stay_within_the_choice_limit() {
+        assert_eq!(matching_timezones("").len(), 25);
+        assert!(matching_timezones("not a timezone").is_empty());
+    }
+}
@@ -0,0 +1,54 @@
+use poise::CreateReply;
+use serenity::all::CreateEmbed;
+
+use crate::bot::handler::events::HandlerResult;
+use crate::bot::handler::framework::Context;
+use crate::chat::engine::EngineGuard;
+
+/// Lists the tools available to the model and how often they were used
+pub async fn tools(ctx: Context<'_>) -> HandlerResult<'_, ()> {
+    let data = ctx.data();
+
+    let result: anyhow::Result<()> = async {
+        let key = data.engine_key(ctx.author().id, ctx.channel_id()).await;
+        let guard = EngineGuard::lock(data, key).await?;
+        let engine = guard.engine().await.read().await;
+
+        let tools = engine.client.tools().await;
+
+        let description = match tools.is_empty() {
+            true => "No tools are registered.".to_string(),
+            false => tools
+                .into_iter()
+                .map(|(definition, usage)| {
+                    format!(
+                        "**{}** (used {usage} time{})\n{}",
+                        definition.name,
+                        if usage == 1 { "" } else { "s" },
+                        definition.description
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n\n"),
+        };
+
+        ctx.send(
+            CreateReply::default()
+                .embed(
+                    CreateEmbed::default()
+                        .title("Available tools")
+                        .description(description),
+                )
+                .ephemeral(true),
+        )
+        .await?;
+
+        Ok(())
+    }
+    .await;
+
+    match result {
+        Ok(_) => HandlerResult::ok(()),
+        Err(why) => HandlerResult::err(why, ctx),
+    }
+}
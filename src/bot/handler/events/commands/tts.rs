@@ -0,0 +1,62 @@
+use poise::CreateReply;
+
+use crate::bot::handler::events::HandlerResult;
+use crate::bot::handler::framework::Context;
+
+/// Turns reading your replies out loud on or off
+pub async fn tts(ctx: Context<'_>, enabled: Option<bool>) -> HandlerResult<'_, ()> {
+    let data = ctx.data().clone();
+
+    let result: anyhow::Result<()> = async {
+        if !data
+            .config
+            .read()
+            .await
+            .tts
+            .as_ref()
+            .is_some_and(|tts| tts.enabled)
+        {
+            anyhow::bail!("Text to speech is not enabled on this bot");
+        }
+
+        // audio is up to whoever listens, even when the conversation is the channel's
+        let key = ctx.author().id;
+
+        let content = if let Some(enabled) = enabled {
+            data.config
+                .write()
+                .await
+                .save_preferences(key, |preferences| preferences.tts = Some(enabled))
+                .await?;
+
+            match enabled {
+                true => "Replies will now come with audio",
+                false => "Replies will no longer come with audio",
+            }
+        } else {
+            let enabled = data
+                .config
+                .read()
+                .await
+                .preferences(key)
+                .tts
+                .unwrap_or(false);
+
+            match enabled {
+                true => "Replies come with audio",
+                false => "Replies don't come with audio, turn it on with `/tts true`",
+            }
+        };
+
+        ctx.send(CreateReply::default().content(content).ephemeral(true))
+            .await?;
+
+        Ok(())
+    }
+    .await;
+
+    match result {
+        Ok(_) => HandlerResult::ok(()),
+        Err(why) => HandlerResult::err(why, ctx),
+    }
+}
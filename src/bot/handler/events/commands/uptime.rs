@@ -0,0 +1,84 @@
+use std::sync::atomic::Ordering;
+
+use poise::CreateReply;
+use serenity::all::CreateEmbed;
+
+use crate::bot::handler::events::HandlerResult;
+use crate::bot::handler::framework::Context;
+use crate::utils::time_to_string;
+
+/// Shows how long the bot has been running and what it did since
+pub async fn uptime(ctx: Context<'_>) -> HandlerResult<'_, ()> {
+    let data = ctx.data();
+
+    let result: anyhow::Result<()> = async {
+        let uptime = chrono::Duration::from_std(data.started.elapsed())?;
+
+        let (engines, without_memory, busy) = {
+            let user_map = data.user_map.read().await;
+            let mut without_memory = 0;
+            let mut busy = 0;
+            // engines in the middle of a reply are skipped rather than waited on
+            for engine in user_map.values() {
+                match engine.try_read() {
+                    Ok(engine) if engine.client.memory_error().is_some() => without_memory += 1,
+                    Ok(_) => {}
+                    Err(_) => busy += 1,
+                }
+            }
+            (user_map.len(), without_memory, busy)
+        };
+
+        let memory = memory_status(engines, without_memory, busy);
+
+        ctx.send(
+            CreateReply::default()
+                .embed(
+                    CreateEmbed::default()
+                        .title("Uptime")
+                        .field("Running for", time_to_string(uptime), true)
+                        .field(
+                            "Messages handled",
+                            data.messages_handled.load(Ordering::Relaxed).to_string(),
+                            true,
+                        )
+                        .field("Active engines", engines.to_string(), true)
+                        .field("Long-term memory", memory, false),
+                )
+                .ephemeral(true),
+        )
+        .await?;
+
+        Ok(())
+    }
+    .await;
+
+    match result {
+        Ok(_) => HandlerResult::ok(()),
+        Err(why) => HandlerResult::err(why, ctx),
+    }
+}
+
+/// How long-term memory is doing across the engines, some of which couldn't be checked.
+fn memory_status(engines: usize, without_memory: usize, busy: usize) -> String {
+    match (without_memory, busy) {
+        (0, 0) => "Available".to_string(),
+        (0, busy) => format!("Available ({busy} engines busy, not checked)"),
+        (without, _) => format!("Unavailable for {without} of {engines} engines"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_status_counts_the_engines_without_it() {
+        assert_eq!(memory_status(0, 0, 0), "Available");
+        assert_eq!(
+            memory_status(3, 0, 2),
+            "Available (2 engines busy, not checked)"
+        );
+        assert_eq!(memory_status(3, 1, 1), "Unavailable for 1 of 3 engines");
+    }
+}
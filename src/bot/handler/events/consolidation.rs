@@ -0,0 +1,85 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::task::JoinHandle;
+
+use crate::{
+    bot::handler::framework::InnerData,
+    chat::{audit, engine::EngineGuard},
+    config::structure::MemoryConsolidationConfig,
+    utils::macros::config,
+};
+
+use super::super::Handler;
+
+/// How often to check whether consolidation got enabled while it's off.
+const DISABLED_POLL: Duration = Duration::from_secs(300);
+
+impl Handler {
+    /// Periodically consolidates the memories of every loaded engine in the background.
+    pub fn consolidation_spawn(data: Arc<InnerData>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                let consolidation = config!(data)
+                    .memory_consolidation
+                    .clone()
+                    .filter(|consolidation| consolidation.enabled);
+
+                let Some(consolidation) = consolidation else {
+                    tokio::time::sleep(DISABLED_POLL).await;
+                    continue;
+                };
+
+                tokio::time::sleep(Duration::from_secs(consolidation.interval_secs)).await;
+
+                Self::consolidate(data.clone(), &consolidation).await;
+            }
+        })
+    }
+
+    async fn consolidate(data: Arc<InnerData>, config: &MemoryConsolidationConfig) {
+        let keys = data
+            .user_map
+            .read()
+            .await
+            .keys()
+            .copied()
+            .collect::<Vec<_>>();
+
+        log::info!("consolidating memories of {} engines", keys.len());
+
+        for key in keys {
+            let result: anyhow::Result<()> = async {
+                // only hold the engine long enough to detach the consolidator
+                let consolidator = {
+                    let guard = EngineGuard::lock(&data, key).await?;
+                    let engine = guard.engine().await.read().await;
+                    engine.client.consolidator()
+                };
+
+                let Some(consolidator) = consolidator else {
+                    return Ok(());
+                };
+
+                let consolidations = consolidator
+                    .run(
+                        config.similarity_threshold,
+                        config.min_cluster_size.unwrap_or(2),
+                    )
+                    .await?;
+
+                if let Some(path) = &config.audit_log {
+                    for (merged, into) in &consolidations {
+                        audit::consolidation::record(path, key, merged, into).await?;
+                    }
+                }
+
+                Ok(())
+            }
+            .await;
+
+            if let Err(why) = result {
+                log::error!("failed to consolidate memories of {key}: {why:?}");
+            }
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use serenity::all::{ChannelId, MessageId};
+
+use super::super::Handler;
+
+impl Handler {
+    /// Removes deleted messages from every loaded context that has them. Deletions don't say who
+    /// sent the message, so there's no engine key to go by.
+    pub async fn on_delete(&self, channel: ChannelId, ids: Vec<MessageId>) {
+        let (forget, with_replies) = {
+            let config = self.data.config.read().await;
+            (
+                config.discord.forget_deleted.unwrap_or(false),
+                config.discord.forget_deleted_replies.unwrap_or(false),
+            )
+        };
+
+        if !forget {
+            return;
+        }
+
+        let user_map = self.data.user_map.read().await;
+        for (key, engine) in user_map.iter() {
+            let removed = engine
+                .write()
+                .await
+                .remove_deleted(channel, &ids, with_replies);
+
+            if removed > 0 {
+                log::info!("removed {removed} deleted messages from the context of {key}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serenity::all::UserId;
+    use tokio::sync::RwLock;
+
+    use crate::{bot::handler::framework::InnerData, chat::engine::replay::Replay};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn deleted_messages_are_only_forgotten_when_configured() {
+        let data = InnerData::scratch("forget-deleted");
+        let mut replay = Replay::new(1014, |_| {}).await.unwrap();
+        replay.model.reply("Hello!").reply("Fine, you?");
+        replay.say("hi").await.unwrap();
+        replay.say("how are you").await.unwrap();
+        let user = UserId::new(1014);
+        data.user_map
+            .write()
+            .await
+            .insert(user, RwLock::new(replay.engine));
+        let handler = Handler { data: data.clone() };
+        let count = || async {
+            data.user_map.read().await[&user]
+                .read()
+                .await
+                .message_count()
+        };
+
+        // the first exchange went into messages 1 and 2 of channel 1
+        let deleted = || vec![MessageId::new(1)];
+        handler.on_delete(ChannelId::new(1), deleted()).await;
+        assert_eq!(count().await, 4);
+
+        data.config.write().await.discord.forget_deleted = Some(true);
+        handler.on_delete(ChannelId::new(2), deleted()).await;
+        assert_eq!(count().await, 4);
+        handler.on_delete(ChannelId::new(1), deleted()).await;
+        assert_eq!(count().await, 3);
+    }
+}
@@ -3,7 +3,7 @@ use serenity::all::{Context, Message, MessageUpdateEvent};
 
 use crate::{
     chat::{ChatMessage, context::UserPrompt, engine::EngineGuard},
-    utils,
+    utils::misc,
 };
 
 use super::{super::Handler, error::HandlerResult};
@@ -15,7 +15,7 @@ impl Handler {
         _: Option<Message>,
         _: Option<Message>,
         event: MessageUpdateEvent,
-    ) -> HandlerResult<()> {
+    ) -> HandlerResult<'_, ()> {
         let author = if let Some(author) = event.author {
             author
         } else {
@@ -32,7 +32,9 @@ impl Handler {
             return HandlerResult::ok(());
         };
 
-        let guard = match EngineGuard::lock(&self.data, author.id).await {
+        let key = self.data.engine_key(author.id, event.channel_id).await;
+
+        let guard = match EngineGuard::lock(&self.data, key).await {
             Ok(guard) => guard,
             Err(why) => {
                 return HandlerResult::err(
@@ -49,14 +51,20 @@ impl Handler {
         let mut engine = guard.engine().await.write().await;
 
         let user_prompt = match async {
-            let mut user_prompt = UserPrompt {
-                content: Some(new_content),
-                current_time: engine.config.system.get_time(),
-                relevant_memories: vec![],
-                time_since: utils::time_to_string(engine.time_since_last()),
-                system_note: None,
-                freewill: false,
-            };
+            let mut user_prompt = engine
+                .prompt_builder()
+                .content(Some(new_content))
+                .author(
+                    engine.group_author(Some(misc::author_name(
+                        &author,
+                        event.guild_id,
+                        event
+                            .member
+                            .as_ref()
+                            .and_then(|member| member.as_ref()?.nick.as_deref()),
+                    ))),
+                )
+                .build()?;
             engine.client.rag_recall(&mut user_prompt).await?;
 
             Ok::<UserPrompt, anyhow::Error>(user_prompt)
@@ -1,27 +1,53 @@
-use std::sync::Arc;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use poise::CreateReply;
+use rig::completion::CompletionError;
 use serenity::all::{
     ChannelId, CreateActionRow, CreateButton, CreateEmbed, CreateMessage, Http, Message,
     MessageReference,
 };
 
-use crate::bot::handler::framework::Context;
+use crate::{
+    bot::handler::framework::{Context, InnerData},
+    chat::client::{CompletionTimeout, MissingCredentials},
+};
 
 use super::super::Handler;
 
+/// Errors relayed into the same channel within this long of each other only get logged.
+const ERROR_THROTTLE: Duration = Duration::from_secs(10);
+
 impl Handler {
     /// attempts to relay an error into a discord channel, still logging it
     /// if fails, still logs and logs the failure of the failure (lol)
-    pub async fn on_error(error: HandlerError<'_>) {
+    pub async fn on_error(data: &InnerData, error: HandlerError<'_>) {
         let HandlerError { error, location } = error;
 
         log::error!("handling error:\n\n{error:?}\n");
 
+        // command errors answer the command itself, everything else goes into the channel
+        let channel = match &location {
+            ErrorLocation::Context(_) => None,
+            ErrorLocation::Message((_, message)) => Some(message.channel_id),
+            ErrorLocation::Channel((_, channel_id, _)) => Some(*channel_id),
+        };
+        let throttled = match channel {
+            Some(channel) => Self::throttled(data, channel).await,
+            None => false,
+        };
+        if throttled {
+            log::warn!("not relaying error, one was relayed into the channel moments ago");
+            return;
+        }
+
+        let (title, description) = Self::describe(&error);
         let embed = CreateEmbed::default()
             .color(0xFF6961)
-            .title("Chatbot encountered an error")
-            .description(format!("```{}```", error.to_string()));
+            .title(title)
+            .description(description);
         let button = CreateButton::new("delete_error")
             .label("")
             .emoji('🗑')
@@ -48,7 +74,7 @@ impl Handler {
                         http,
                         CreateMessage::new()
                             .button(button)
-                            .reference_message(&message)
+                            .reference_message(&*message)
                             .embed(embed),
                     )
                     .await
@@ -69,41 +95,74 @@ impl Handler {
     }
 }
 
+impl Handler {
+    /// Whether an error was relayed into the channel too recently for another one, marking
+    /// the channel as just relayed into if it wasn't.
+    async fn throttled(data: &InnerData, channel: ChannelId) -> bool {
+        let mut last_errors = data.last_errors.lock().await;
+
+        let now = Instant::now();
+        last_errors.retain(|_, at| now.duration_since(*at) < ERROR_THROTTLE);
+
+        match last_errors.contains_key(&channel) {
+            true => true,
+            false => {
+                last_errors.insert(channel, now);
+                false
+            }
+        }
+    }
+
+    /// The title and description shown for an error, friendlier for the ones users run into most.
+    fn describe(error: &anyhow::Error) -> (&'static str, String) {
+        if let Some(timeout) = error.downcast_ref::<CompletionTimeout>() {
+            ("The model took too long", timeout.to_string())
+        } else if let Some(missing) = error.downcast_ref::<MissingCredentials>() {
+            ("The bot isn't set up yet", missing.to_string())
+        } else if let Some(why) = error.downcast_ref::<CompletionError>() {
+            (
+                "The model provider returned an error",
+                format!("```{why}```"),
+            )
+        } else {
+            ("Chatbot encountered an error", format!("```{error}```"))
+        }
+    }
+}
+
 pub enum ErrorLocation<'a> {
     Context(Context<'a>),
-    Message((Arc<Http>, Message)),
+    Message((Arc<Http>, Box<Message>)),
     Channel((Arc<Http>, ChannelId, Option<MessageReference>)),
 }
 
-impl<'a> Into<ErrorLocation<'a>> for Context<'a> {
-    fn into(self) -> ErrorLocation<'a> {
-        ErrorLocation::Context(self)
+impl<'a> From<Context<'a>> for ErrorLocation<'a> {
+    fn from(ctx: Context<'a>) -> Self {
+        ErrorLocation::Context(ctx)
     }
 }
 
-impl Into<ErrorLocation<'static>> for (Arc<Http>, Message) {
-    fn into(self) -> ErrorLocation<'static> {
-        ErrorLocation::Message(self)
+impl From<(Arc<Http>, Message)> for ErrorLocation<'static> {
+    fn from((http, message): (Arc<Http>, Message)) -> Self {
+        ErrorLocation::Message((http, Box::new(message)))
     }
 }
 
-impl Into<ErrorLocation<'static>> for (Arc<Http>, ChannelId) {
-    fn into(self) -> ErrorLocation<'static> {
-        let (http, channel_id) = self;
+impl From<(Arc<Http>, ChannelId)> for ErrorLocation<'static> {
+    fn from((http, channel_id): (Arc<Http>, ChannelId)) -> Self {
         ErrorLocation::Channel((http, channel_id, None))
     }
 }
 
-impl Into<ErrorLocation<'static>> for (Arc<Http>, ChannelId, MessageReference) {
-    fn into(self) -> ErrorLocation<'static> {
-        let (http, channel_id, reference) = self;
+impl From<(Arc<Http>, ChannelId, MessageReference)> for ErrorLocation<'static> {
+    fn from((http, channel_id, reference): (Arc<Http>, ChannelId, MessageReference)) -> Self {
         ErrorLocation::Channel((http, channel_id, Some(reference)))
     }
 }
 
-impl Into<ErrorLocation<'static>> for (Arc<Http>, ChannelId, Option<MessageReference>) {
-    fn into(self) -> ErrorLocation<'static> {
-        ErrorLocation::Channel(self)
+impl From<(Arc<Http>, ChannelId, Option<MessageReference>)> for ErrorLocation<'static> {
+    fn from(channel: (Arc<Http>, ChannelId, Option<MessageReference>)) -> Self {
+        ErrorLocation::Channel(channel)
     }
 }
 
@@ -129,3 +188,37 @@ impl<'a, T> HandlerResult<'a, T> {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn errors_in_quick_succession_are_only_relayed_once() {
+        let data = InnerData::scratch("error-throttle");
+        let (channel, other) = (ChannelId::new(1), ChannelId::new(2));
+
+        assert!(!Handler::throttled(&data, channel).await);
+        assert!(Handler::throttled(&data, channel).await);
+        assert!(!Handler::throttled(&data, other).await);
+
+        let relayed = Instant::now() - ERROR_THROTTLE;
+        data.last_errors.lock().await.insert(channel, relayed);
+        assert!(!Handler::throttled(&data, channel).await);
+    }
+
+    #[test]
+    fn common_failures_get_their_own_titles() {
+        let (title, description) = Handler::describe(&CompletionTimeout(30).into());
+        assert_eq!(title, "The model took too long");
+        assert!(description.contains("30 seconds"));
+
+        let provider = CompletionError::ProviderError("rate limited".to_string());
+        let (title, description) = Handler::describe(&provider.into());
+        assert_eq!(title, "The model provider returned an error");
+        assert!(description.contains("rate limited"));
+
+        let (title, _) = Handler::describe(&anyhow::anyhow!("something else"));
+        assert_eq!(title, "Chatbot encountered an error");
+    }
+}
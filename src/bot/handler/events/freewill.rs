@@ -1,21 +1,24 @@
 use std::sync::Arc;
 
-use rand::Rng;
+use chrono::NaiveTime;
 use serenity::all::{ChannelId, EditMessage, Http, MessageId, UserId};
 use tokio::{task::JoinHandle, time};
 
 use crate::{
     bot::handler::framework::InnerData,
     chat::engine::{ChatEngine, ContextType, EngineGuard},
+    config::structure::QuietHours,
     utils::{
         macros::config,
         misc::{self, ButtonStates},
+        random,
     },
 };
 
 use super::super::Handler;
 
 impl Handler {
+    /// Starts free will for an engine, unless it's already waiting for its turn.
     pub async fn freewill_dispatch(&self, user: UserId, channel: ChannelId, http: Arc<Http>) {
         let mut freewill_map = self.data.freewill_map.write().await;
         freewill_map
@@ -23,12 +26,7 @@ impl Handler {
             .and_modify(|handle| {
                 if handle.is_finished() {
                     log::info!("freewill was finished, dispatching again");
-                    *handle = Self::freewill_spawn(
-                        self.data.clone(),
-                        user,
-                        channel.clone(),
-                        http.clone(),
-                    );
+                    *handle = Self::freewill_spawn(self.data.clone(), user, channel, http.clone());
                 } else {
                     // freewill is already running
                     log::trace!("freewill is already running");
@@ -41,6 +39,29 @@ impl Handler {
             });
     }
 
+    /// Cancels an engine's free will, then starts a fresh one if given where to send it.
+    /// Both happen under the map's lock, so an engine never ends up with more than one.
+    pub async fn freewill_reset(
+        data: &Arc<InnerData>,
+        user: UserId,
+        restart: Option<(ChannelId, Arc<Http>)>,
+    ) {
+        let mut freewill_map = data.freewill_map.write().await;
+
+        if let Some(handle) = freewill_map.remove(&user) {
+            log::info!("cancelling freewill of {user}");
+            handle.abort();
+        }
+
+        if let Some((channel, http)) = restart {
+            log::info!("restarting freewill of {user}");
+            freewill_map.insert(
+                user,
+                Self::freewill_spawn(data.clone(), user, channel, http),
+            );
+        }
+    }
+
     pub fn freewill_spawn(
         data: Arc<InnerData>,
         user: UserId,
@@ -54,13 +75,23 @@ impl Handler {
                     // let min = 0; debbuging stuff
                     let max = 120;
                     // let max = 5; debbuging stuff
-                    let interval = time::Duration::from_secs(rand::random_range(min..max));
+                    // no jitter in deterministic mode
+                    let interval = time::Duration::from_secs(match random::is_seeded() {
+                        true => min,
+                        false => random::random_range(min..max),
+                    });
 
                     tokio::time::sleep(interval).await;
 
+                    if let Some(left) = Self::quiet_for(&data, user).await {
+                        log::info!("quiet hours for {user}, holding freewill off for {left:?}");
+                        tokio::time::sleep(left).await;
+                        continue;
+                    }
+
                     if Self::should_freewill(data.clone(), user).await {
                         let did_freewill =
-                            Self::freewill(data.clone(), user, channel.clone(), http.clone()).await;
+                            Self::freewill(data.clone(), user, channel, http.clone()).await;
                         log::info!("freewill done");
                         if did_freewill {
                             return;
@@ -92,26 +123,31 @@ impl Handler {
             Self::freewill_memory_store(&engine).await?;
 
             let mut response = engine
-                .user_prompt(None, Some(ContextType::Freewill))
+                .user_prompt(None, None, Some(ContextType::Freewill))
                 .await?;
             response.freewill = true;
 
-            log::info!("freewill response:\n{:?}", response);
+            log::trace!("freewill response:\n{:?}", response);
 
             // todo add chunking here
 
+            let content = response
+                .content()
+                .ok_or(anyhow::anyhow!("message does not have a content"))?;
+            let content = data.format_markdown(channel, content).await;
+
             let messages = misc::chunk_message(
-                &response
-                    .content()
-                    .ok_or(anyhow::anyhow!("message does not have a content"))?,
+                &content,
                 ButtonStates {
                     prev_disabled: true,
                     regen_or_next: misc::RegenOrNext::Regen,
+                    model: None,
+                    branch: None,
                 },
             )?;
 
             let ids = misc::send_message_batch(channel, &http, messages).await?;
-            let last_id = ids.last().ok_or(anyhow::anyhow!("no message ids"))?.clone();
+            let last_id = *ids.last().ok_or(anyhow::anyhow!("no message ids"))?;
 
             engine.add_message(response, (last_id, channel, ids));
 
@@ -130,9 +166,12 @@ impl Handler {
                         async move {
                             let _ = recv.recv().await;
 
-                            let _ = message
-                                .edit(&http, EditMessage::new().components(vec![]))
-                                .await;
+                            let _ = misc::edit_message(
+                                &http,
+                                &mut message,
+                                EditMessage::new().components(vec![]),
+                            )
+                            .await;
 
                             drop(recv);
                         }
@@ -146,12 +185,16 @@ impl Handler {
             }
             Err(why) => {
                 log::error!("Error sending message: {why:?}");
-                return false;
+                false
             }
         }
     }
 
     pub async fn should_freewill(data: Arc<InnerData>, user: UserId) -> bool {
+        if data.paused.read().await.contains(&user) {
+            return false;
+        }
+
         let guard = if let Ok(engine) = EngineGuard::lock(&data, user).await {
             engine
         } else {
@@ -160,10 +203,14 @@ impl Handler {
 
         let engine = guard.engine().await.read().await;
 
+        if engine.freewill_exhausted() {
+            log::debug!("freewill of {user} went unanswered too often, waiting for them");
+            return false;
+        }
+
         let time_since_last = engine.time_since_last().num_seconds() as f64;
 
         let config = config!(data);
-        let mut rng = rand::rng();
         let threshold = exponential_probability(
             time_since_last,
             0,
@@ -172,9 +219,17 @@ impl Handler {
             config.freewill.steepness,
         );
 
-        let bool = rng.random_bool(threshold);
+        random::random_bool(threshold)
+    }
 
-        bool
+    /// How long the quiet hours of an engine's user still last, `None` outside of them.
+    async fn quiet_for(data: &Arc<InnerData>, user: UserId) -> Option<time::Duration> {
+        let quiet_hours = config!(data).freewill.quiet_hours?;
+
+        let guard = EngineGuard::lock(data, user).await.ok()?;
+        let now = guard.engine().await.read().await.config.system.local_time();
+
+        quiet_remaining(&quiet_hours, now)
     }
 
     // todo: post freewill, index context as a memory to simulate human-like behavior
@@ -193,6 +248,24 @@ impl Handler {
     }
 }
 
+/// How long until the quiet hours end, `None` if `now` is outside of them.
+pub fn quiet_remaining(quiet_hours: &QuietHours, now: NaiveTime) -> Option<time::Duration> {
+    let QuietHours { start, end } = *quiet_hours;
+
+    let quiet = match start <= end {
+        true => start <= now && now < end,
+        // spans midnight
+        false => now >= start || now < end,
+    };
+    if !quiet {
+        return None;
+    }
+
+    // wraps around past midnight when the end is on the next day
+    let left = (end - now + chrono::Duration::days(1)).num_seconds() % 86400;
+    Some(time::Duration::from_secs(left as u64))
+}
+
 /// Calculate exponential probability between `z` and `y`
 /// - `value`: Input value (must be between `x` and `y`)
 /// - `x`: Start of the range (probability = 0)
@@ -220,3 +293,83 @@ pub fn exponential_probability(value: f64, x: u64, z: u64, y: u64, steepness: f6
 
     prob.clamp(0.0, 1.0)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(time: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(time, "%H:%M").unwrap()
+    }
+
+    fn quiet(start: &str, end: &str) -> QuietHours {
+        QuietHours {
+            start: at(start),
+            end: at(end),
+        }
+    }
+
+    fn minutes(minutes: u64) -> Option<time::Duration> {
+        Some(time::Duration::from_secs(minutes * 60))
+    }
+
+    #[test]
+    fn quiet_hours_within_a_day() {
+        let hours = quiet("13:00", "15:00");
+
+        assert_eq!(quiet_remaining(&hours, at("12:59")), None);
+        assert_eq!(quiet_remaining(&hours, at("13:00")), minutes(120));
+        assert_eq!(quiet_remaining(&hours, at("14:30")), minutes(30));
+        assert_eq!(quiet_remaining(&hours, at("15:00")), None);
+    }
+
+    #[test]
+    fn quiet_hours_spanning_midnight() {
+        let hours = quiet("22:00", "08:00");
+
+        assert_eq!(quiet_remaining(&hours, at("21:59")), None);
+        assert_eq!(quiet_remaining(&hours, at("22:00")), minutes(600));
+        assert_eq!(quiet_remaining(&hours, at("23:30")), minutes(510));
+        assert_eq!(quiet_remaining(&hours, at("00:00")), minutes(480));
+        assert_eq!(quiet_remaining(&hours, at("07:59")), minutes(1));
+        assert_eq!(quiet_remaining(&hours, at("08:00")), None);
+        assert_eq!(quiet_remaining(&hours, at("12:00")), None);
+    }
+
+    #[test]
+    fn empty_quiet_hours_are_never_quiet() {
+        let hours = quiet("03:00", "03:00");
+
+        assert_eq!(quiet_remaining(&hours, at("03:00")), None);
+        assert_eq!(quiet_remaining(&hours, at("15:00")), None);
+    }
+
+    #[tokio::test]
+    async fn resets_cancel_the_pending_freewill() {
+        let data = InnerData::scratch("freewill-reset");
+        let user = UserId::new(1);
+        let pending = Handler::freewill_spawn(
+            data.clone(),
+            user,
+            ChannelId::new(1),
+            Arc::new(Http::new("")),
+        );
+        let aborted = pending.abort_handle();
+        data.freewill_map.write().await.insert(user, pending);
+
+        Handler::freewill_reset(&data, user, None).await;
+        assert!(data.freewill_map.read().await.is_empty());
+        tokio::task::yield_now().await;
+        assert!(aborted.is_finished());
+
+        // restarting leaves exactly one waiting for its turn
+        let http = Arc::new(Http::new(""));
+        Handler::freewill_reset(&data, user, Some((ChannelId::new(1), http.clone()))).await;
+        Handler::freewill_reset(&data, user, Some((ChannelId::new(1), http))).await;
+
+        let freewill_map = data.freewill_map.read().await;
+        assert_eq!(freewill_map.len(), 1);
+        assert!(!freewill_map[&user].is_finished());
+        freewill_map[&user].abort();
+    }
+}
@@ -1,4 +1,6 @@
-use serenity::all::{Context, Interaction};
+use serenity::all::{
+    Context, CreateInteractionResponse, CreateInteractionResponseMessage, Interaction,
+};
 
 use super::{
     super::Handler,
@@ -10,7 +12,7 @@ impl Handler {
         &self,
         ctx: Context,
         interaction: Interaction,
-    ) -> HandlerResult<()> {
+    ) -> HandlerResult<'_, ()> {
         match interaction.kind() {
             serenity::all::InteractionType::Component => {
                 return self.on_component(ctx, interaction).await;
@@ -24,11 +26,28 @@ impl Handler {
         HandlerResult::ok(())
     }
 
-    async fn on_component(&self, ctx: Context, interaction: Interaction) -> HandlerResult<()> {
+    async fn on_component(&self, ctx: Context, interaction: Interaction) -> HandlerResult<'_, ()> {
         if let Some(mut component) = interaction.into_message_component() {
+            let cooldown = match component.data.custom_id.as_str() {
+                "regen" => Self::regen_cooldown(&self.data, component.user.id).await,
+                _ => None,
+            };
+            if let Some(left) = cooldown {
+                let notice = CreateInteractionResponse::Message(
+                    CreateInteractionResponseMessage::new()
+                        .content(Self::cooldown_notice(left))
+                        .ephemeral(true),
+                );
+
+                return match component.create_response(&ctx.http, notice).await {
+                    Ok(_) => HandlerResult::ok(()),
+                    Err(why) => HandlerResult::err(why, (ctx.http, *component.message)),
+                };
+            }
+
             let result = match component.data.custom_id.as_str() {
                 id @ ("regen" | "prev" | "next") => {
-                    if let Err(why) = self.disable_buttons(&mut *component.message, &ctx).await {
+                    if let Err(why) = self.disable_buttons(&mut component.message, &ctx).await {
                         log::error!("error editing message: {why:?}");
                         return HandlerResult::err(why, (ctx.http, *component.message));
                     };
@@ -66,7 +85,11 @@ impl Handler {
         }
     }
 
-    async fn on_modal_submit(&self, ctx: Context, interaction: Interaction) -> HandlerResult<()> {
+    async fn on_modal_submit(
+        &self,
+        ctx: Context,
+        interaction: Interaction,
+    ) -> HandlerResult<'_, ()> {
         if let Some(modal) = interaction.into_modal_submit() {
             let result = match modal.data.custom_id.as_str() {
                 custom_id if custom_id.starts_with("edit_") => {
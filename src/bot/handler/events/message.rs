@@ -1,7 +1,20 @@
-use serenity::all::{ChannelId, Context, EditMessage, Message, MessageId};
+use std::{
+    collections::HashSet,
+    sync::{Arc, atomic::Ordering},
+};
+
+use serenity::all::{
+    Context, CreateAttachment, CreateMessage, EditMessage, Http, Message, MessageId, ReactionType,
+    UserId,
+};
 
 use crate::{
-    chat::engine::{ContextType, EngineGuard},
+    chat::{
+        client::{InlineOverrides, synthesize},
+        context::MessageIdentifier,
+        engine::{ContextType, EngineGuard},
+    },
+    config::structure::{ChatBotConfigInner, DiscordConfig, MemoryCitations, TtsConfig},
     utils::misc::ButtonStates,
 };
 
@@ -9,77 +22,447 @@ use super::{super::Handler, error::HandlerResult};
 use crate::utils::misc;
 
 impl Handler {
-    pub async fn on_message(&self, ctx: Context, msg: Message) -> HandlerResult<()> {
-        if msg.author.bot {
+    pub async fn on_message(&self, ctx: Context, msg: Message) -> HandlerResult<'_, ()> {
+        if self.ignorable(ctx.cache.current_user().id, &msg) {
             return HandlerResult::ok(());
         } else {
             self.data.msg_channel.0.send(msg.content.clone()).unwrap();
         }
 
-        self.freewill_dispatch(msg.author.id, msg.channel_id, ctx.http.clone())
+        let triggered = match self.check_triggers(&msg.content).await {
+            Ok(triggered) => triggered,
+            Err(why) => return HandlerResult::err(why, (ctx.http, msg)),
+        };
+
+        for tag in &triggered.tags {
+            log::info!(
+                "message {} by {} in {} tagged \"{tag}\"",
+                msg.id,
+                msg.author.name,
+                msg.channel_id
+            );
+        }
+
+        let (reply_to_message, seen_emoji) = {
+            let config = self.data.config.read().await;
+            (
+                config.discord.reply_to_message.unwrap_or(false),
+                Self::seen_emoji(&config.discord),
+            )
+        };
+
+        // fixed replies skip the engine entirely
+        if let Some(reply) = triggered.reply {
+            let mut reply = vec![CreateMessage::new().content(reply)];
+            if reply_to_message {
+                reply = misc::reply_to(reply, &msg);
+            }
+
+            return match misc::send_message_batch(msg.channel_id, &ctx.http, reply).await {
+                Ok(_) => HandlerResult::ok(()),
+                Err(why) => HandlerResult::err(why, (ctx.http, msg)),
+            };
+        }
+
+        let key = self.data.engine_key(msg.author.id, msg.channel_id).await;
+        let author = misc::author_name(
+            &msg.author,
+            msg.guild_id,
+            msg.member
+                .as_ref()
+                .and_then(|member| member.nick.as_deref()),
+        );
+
+        // refused messages never reach the context
+        match self.moderate(key, &msg.content).await {
+            Ok(None) => {}
+            Ok(Some(refusal)) => {
+                let mut refusal = vec![CreateMessage::new().content(refusal)];
+                if reply_to_message {
+                    refusal = misc::reply_to(refusal, &msg);
+                }
+
+                return match misc::send_message_batch(msg.channel_id, &ctx.http, refusal).await {
+                    Ok(_) => HandlerResult::ok(()),
+                    Err(why) => HandlerResult::err(why, (ctx.http, msg)),
+                };
+            }
+            Err(why) => return HandlerResult::err(why, (ctx.http, msg)),
+        }
+
+        if let Err(why) = self.backfill(&ctx, key, &msg).await {
+            log::warn!("failed to backfill the context of {key}: {why:?}");
+        }
+
+        // paused engines keep up with the conversation, but don't reply (or speak up on their own)
+        if self.data.paused.read().await.contains(&key) {
+            let result: anyhow::Result<()> = async {
+                let guard = EngineGuard::lock(&self.data, key).await?;
+                let mut engine = guard.engine().await.write().await;
+
+                let expired = engine.expire_idle().await?;
+
+                let prompt = engine
+                    .prompt_builder()
+                    .content(Some(msg.content.clone()))
+                    .system_note(merge_notes(expired, triggered.system_note.clone()))
+                    .author(engine.group_author(Some(author.clone())))
+                    .build()?;
+
+                engine.add_user_message(prompt, (msg.id, msg.channel_id))
+            }
+            .await;
+
+            return match result {
+                Ok(_) => HandlerResult::ok(()),
+                Err(why) => HandlerResult::err(why, (ctx.http, msg)),
+            };
+        }
+
+        self.freewill_dispatch(key, msg.channel_id, ctx.http.clone())
             .await;
 
+        // let them know the message got through, even if the reply takes a while
+        let seen = match seen_emoji {
+            Some(emoji) => match msg.react(&ctx.http, ReactionType::Unicode(emoji)).await {
+                Ok(reaction) => Some(reaction.emoji),
+                Err(why) => {
+                    log::warn!("failed to add the seen reaction: {why:?}");
+                    None
+                }
+            },
+            None => None,
+        };
+
         let typing = ctx.http.start_typing(msg.channel_id);
 
-        let result: anyhow::Result<(MessageId, ChannelId)> = async {
-            let guard = EngineGuard::lock(&self.data, msg.author.id).await?;
+        let result = self
+            .converse(
+                &ctx.http,
+                key,
+                (msg.content.clone(), (msg.id, msg.channel_id).into()),
+                (msg.author.id, author),
+                triggered.system_note.clone(),
+                |messages| async {
+                    let messages = match reply_to_message {
+                        true => misc::reply_to(messages, &msg),
+                        false => messages,
+                    };
+
+                    misc::send_message_batch(msg.channel_id, &ctx.http, messages).await
+                },
+            )
+            .await;
+
+        typing.stop();
+
+        if let Some(seen) = seen {
+            match msg.delete_reaction(&ctx.http, None, seen).await {
+                Ok(()) => {}
+                Err(why) => log::warn!("failed to remove the seen reaction: {why:?}"),
+            }
+        }
+
+        match result {
+            Ok(reactions) => {
+                for emoji in reactions {
+                    if let Err(why) = msg.react(&ctx.http, ReactionType::Unicode(emoji)).await {
+                        log::warn!("failed to add the model's reaction: {why:?}");
+                    }
+                }
+
+                HandlerResult::ok(())
+            }
+            Err(why) => HandlerResult::err(why, (ctx.http, msg)),
+        }
+    }
+
+    /// Whether a message can be left alone without ever touching its engine, which would
+    /// otherwise get created (and its models probed) before the bot decides not to answer.
+    fn ignorable(&self, bot: UserId, msg: &Message) -> bool {
+        msg.author.bot
+            || msg.content.trim().is_empty()
+            // prefix commands are invoked by mentioning the bot, poise answers those
+            || invokes_command(&msg.content, bot, &self.data.command_names)
+    }
+
+    /// Runs a message through the engine and hands the reply to `send`, which returns the ids of
+    /// the discord messages it ended up in (in the prompt's channel). Shared by regular messages and /chat, returns the
+    /// reactions the model asked for.
+    pub async fn converse<F, Fut>(
+        &self,
+        http: &Arc<Http>,
+        key: UserId,
+        prompt: (String, MessageIdentifier),
+        author: (UserId, String),
+        system_note: Option<String>,
+        send: F,
+    ) -> anyhow::Result<Vec<String>>
+    where
+        F: FnOnce(Vec<CreateMessage>) -> Fut,
+        Fut: Future<Output = anyhow::Result<Vec<MessageId>>>,
+    {
+        let (memory_citations, inline_overrides, tts) = {
+            let config = self.data.config.read().await;
+            (
+                config.llm.memory_citations,
+                config.llm.inline_overrides.unwrap_or(false),
+                Self::tts_config(&config, author.0),
+            )
+        };
+        let channel = prompt.1.channel();
+        self.data.messages_handled.fetch_add(1, Ordering::Relaxed);
+
+        let (last_id, reactions) = {
+            let guard = EngineGuard::lock(&self.data, key).await?;
             let mut engine = guard.engine().await.write().await;
 
+            // reactions asked for during regenerations or freewill have nothing to go on
+            engine.client.take_reactions();
+
+            let expired = engine.expire_idle().await?;
+
+            // set right before the turn, which is what clears them again
+            let mut prompt = prompt;
+            if inline_overrides {
+                let (overrides, content) = InlineOverrides::parse(&prompt.0);
+                engine.client.set_overrides(overrides);
+                prompt.0 = content;
+            }
+
+            let preview = tokio::spawn(misc::preview_reply(
+                http.clone(),
+                channel,
+                engine.client.watch_progress(),
+            ));
+
             let response = engine
                 .user_prompt(
-                    Some((msg.content.clone(), (msg.id, msg.channel_id).into())),
-                    Some(ContextType::User),
+                    Some(prompt),
+                    Some(author.1),
+                    Some(ContextType::User {
+                        system_note: merge_notes(expired, system_note),
+                    }),
                 )
-                .await?;
+                .await;
+
+            engine.client.stop_progress();
+            let deleted = match preview.await {
+                Ok(Some(preview)) => Some(preview.delete(http).await),
+                _ => None,
+            };
+            if let Some(Err(why)) = deleted {
+                log::warn!("failed to delete reply preview: {why:?}");
+            }
+            let response = response?;
+
+            let mut content = response
+                .content()
+                .ok_or(anyhow::anyhow!("message does not have a content"))?;
+
+            // only shown, the footnote never makes it into the context
+            if memory_citations == Some(MemoryCitations::Footnote)
+                && !response.cited_memories.is_empty()
+            {
+                content.push_str(&misc::memory_footnote(&response.cited_memories));
+            }
+            let content = self.data.format_markdown(channel, content).await;
 
-            let messages = misc::chunk_message(
-                &response
-                    .content()
-                    .ok_or(anyhow::anyhow!("message does not have a content"))?,
+            let mut messages = misc::chunk_message(
+                &content,
                 ButtonStates {
                     prev_disabled: true,
                     regen_or_next: misc::RegenOrNext::Regen,
+                    model: None,
+                    branch: None,
                 },
             )?;
 
-            let ids = misc::send_message_batch(msg.channel_id, &ctx.http, messages).await?;
-            let last_id = ids.last().ok_or(anyhow::anyhow!("no message ids"))?.clone();
+            // the text is what matters, audio is only a bonus
+            if let Some(tts) = &tts {
+                let spoken = response.content().unwrap_or_default();
+                match synthesize(tts, &spoken).await {
+                    Ok(audio) => {
+                        if let Some(last) = messages.pop() {
+                            let name = format!("reply.{}", tts.format.as_deref().unwrap_or("mp3"));
+                            messages.push(last.add_file(CreateAttachment::bytes(audio, name)));
+                        }
+                    }
+                    Err(why) => log::warn!("text to speech failed, sending text only: {why:?}"),
+                }
+            }
 
-            engine.add_message(response, (last_id, msg.channel_id, ids));
+            let ids = send(messages).await?;
+            let last_id = *ids.last().ok_or(anyhow::anyhow!("no message ids"))?;
 
-            Ok((last_id, msg.channel_id))
-        }
-        .await;
+            let reacted = match memory_citations == Some(MemoryCitations::React)
+                && !response.cited_memories.is_empty()
+            {
+                true => Some(channel.create_reaction(http, last_id, '🧠').await),
+                false => None,
+            };
+            if let Some(Err(why)) = reacted {
+                log::warn!("failed to add the memory reaction: {why:?}");
+            }
 
-        typing.stop();
+            let reactions = engine.client.take_reactions();
 
-        match result {
-            Ok((msg_id, chan_id)) => {
-                let message = ctx.http.get_message(chan_id, msg_id).await;
+            engine.add_message(response, (last_id, channel, ids));
 
-                if let Ok(mut message) = message {
-                    let mut recv = self.data.msg_channel.0.subscribe();
-                    tokio::spawn({
-                        async move {
-                            let _ = recv.recv().await;
+            (last_id, reactions)
+        };
 
-                            let _ = message
-                                .edit(&ctx.http, EditMessage::new().components(vec![]))
-                                .await;
+        let mut message = http
+            .get_message(channel, last_id)
+            .await
+            .map_err(|_| anyhow::anyhow!("could not fetch discord message"))?;
 
-                            drop(recv);
-                        }
-                    });
-
-                    HandlerResult::ok(())
-                } else {
-                    HandlerResult::err(
-                        anyhow::anyhow!("could not fetch discord message"),
-                        (ctx.http, msg),
-                    )
-                }
+        let mut recv = self.data.msg_channel.0.subscribe();
+        tokio::spawn({
+            let http = http.clone();
+            async move {
+                let _ = recv.recv().await;
+
+                let _ =
+                    misc::edit_message(&http, &mut message, EditMessage::new().components(vec![]))
+                        .await;
+
+                drop(recv);
             }
-            Err(why) => HandlerResult::err(why, (ctx.http, msg)),
+        });
+
+        Ok(reactions)
+    }
+
+    /// How to read the reply to `author` out loud, if they asked for it. Kept by user even in
+    /// group mode, the audio is up to whoever listens.
+    fn tts_config(config: &ChatBotConfigInner, author: UserId) -> Option<TtsConfig> {
+        let wants_tts = config.preferences(author).tts.unwrap_or(false);
+        config.tts.clone().filter(|tts| tts.enabled && wants_tts)
+    }
+
+    /// The emoji to react to incoming messages with until they're answered, if any.
+    fn seen_emoji(config: &DiscordConfig) -> Option<String> {
+        match config.seen_reaction.unwrap_or(false) {
+            true => Some(config.seen_emoji.clone().unwrap_or("👀".to_string())),
+            false => None,
         }
     }
 }
+
+/// Puts the auto-clear note in front of whatever the triggers had to say.
+fn merge_notes(expired: Option<String>, note: Option<String>) -> Option<String> {
+    match (expired, note) {
+        (Some(expired), Some(note)) => Some(format!("{expired}\n{note}")),
+        (expired, note) => expired.or(note),
+    }
+}
+
+/// Whether a message mentions the bot followed by one of its command names.
+fn invokes_command(content: &str, bot: UserId, command_names: &HashSet<String>) -> bool {
+    let content = content.trim_start();
+
+    [format!("<@{bot}>"), format!("<@!{bot}>")]
+        .iter()
+        .find_map(|mention| content.strip_prefix(mention.as_str()))
+        .and_then(|rest| rest.split_whitespace().next())
+        .is_some_and(|name| command_names.contains(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{
+        bot::handler::framework::InnerData,
+        config::{store::ChatBotConfig, structure::UserPreferences},
+    };
+
+    use super::*;
+
+    #[test]
+    fn the_seen_reaction_is_opt_in() {
+        let mut config = DiscordConfig::default();
+        assert_eq!(Handler::seen_emoji(&config), None);
+
+        config.seen_reaction = Some(true);
+        assert_eq!(Handler::seen_emoji(&config).as_deref(), Some("👀"));
+
+        config.seen_emoji = Some("⏳".to_string());
+        assert_eq!(Handler::seen_emoji(&config).as_deref(), Some("⏳"));
+    }
+
+    #[test]
+    fn tts_is_kept_by_user_in_group_mode() {
+        let (alice, channel) = (UserId::new(1), UserId::new(2));
+        let mut config = ChatBotConfigInner {
+            tts: Some(TtsConfig {
+                enabled: true,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        config.context.group_mode = Some(true);
+        config.context.user_preferences = Some(HashMap::from([(
+            channel.to_string(),
+            UserPreferences {
+                tts: Some(true),
+                ..Default::default()
+            },
+        )]));
+
+        // the channel's conversation turning it on doesn't read replies to alice out loud
+        assert_eq!(Handler::tts_config(&config, alice), None);
+        assert!(Handler::tts_config(&config, channel).is_some());
+
+        config.tts.as_mut().unwrap().enabled = false;
+        assert_eq!(Handler::tts_config(&config, channel), None);
+    }
+
+    #[test]
+    fn the_auto_clear_note_goes_first() {
+        let note = |text: &str| Some(text.to_string());
+
+        assert_eq!(
+            merge_notes(note("Reset."), note("Keyword.")),
+            note("Reset.\nKeyword.")
+        );
+        assert_eq!(merge_notes(note("Reset."), None), note("Reset."));
+        assert_eq!(merge_notes(None, note("Keyword.")), note("Keyword."));
+        assert_eq!(merge_notes(None, None), None);
+    }
+
+    #[test]
+    fn bots_blank_messages_and_commands_are_ignorable() {
+        let dir = std::env::temp_dir().join("chatbot-tests").join("ignorable");
+        let _ = std::fs::remove_dir_all(&dir);
+        let config = ChatBotConfig::read(dir.join("config.toml")).unwrap();
+        let handler = Handler {
+            data: Arc::new(InnerData::new(config, HashSet::from(["clear".to_string()]))),
+        };
+        let bot = UserId::new(42);
+        let message = |content: &str, from_bot: bool| {
+            let mut message = Message::default();
+            message.content = content.to_string();
+            message.author.bot = from_bot;
+            message
+        };
+
+        assert!(handler.ignorable(bot, &message("hi", true)));
+        assert!(handler.ignorable(bot, &message(" \n", false)));
+        assert!(handler.ignorable(bot, &message("<@42> clear", false)));
+        assert!(!handler.ignorable(bot, &message("hi", false)));
+    }
+
+    #[test]
+    fn prefix_commands_are_left_to_poise() {
+        let bot = UserId::new(42);
+        let commands = HashSet::from(["clear".to_string(), "stats".to_string()]);
+
+        assert!(invokes_command("<@42> clear", bot, &commands));
+        assert!(invokes_command("  <@!42>stats now", bot, &commands));
+        assert!(!invokes_command("<@42> hello", bot, &commands));
+        assert!(!invokes_command("<@7> clear", bot, &commands));
+        assert!(!invokes_command("clear", bot, &commands));
+    }
+}
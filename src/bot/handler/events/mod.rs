@@ -1,8 +1,14 @@
+mod backfill;
 pub mod commands;
+mod consolidation;
+mod delete;
 mod edit;
 mod error;
 mod freewill;
 mod interaction;
 mod message;
+mod moderation;
+mod triggers;
+mod welcome;
 
 pub use error::HandlerResult;
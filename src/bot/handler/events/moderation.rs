@@ -0,0 +1,101 @@
+use regex::Regex;
+use serenity::all::UserId;
+
+use crate::chat::engine::EngineGuard;
+
+use super::super::Handler;
+
+/// What refused messages get as a reply when no refusal is configured.
+const DEFAULT_REFUSAL: &str = "Sorry, I can't respond to that message.";
+
+impl Handler {
+    /// Checks a message against the moderation rules, returning the refusal to send if it's flagged.
+    pub async fn moderate(&self, key: UserId, content: &str) -> anyhow::Result<Option<String>> {
+        let config = self.data.config.read().await.moderation.clone();
+
+        let Some(config) = config.filter(|config| config.enabled) else {
+            return Ok(None);
+        };
+
+        // keywords are cheap, so they go first
+        let mut flagged = match blocked_keyword(config.blocked_keywords.iter().flatten(), content)?
+        {
+            Some(keyword) => {
+                log::debug!("message contains blocked keyword \"{keyword}\"");
+                true
+            }
+            None => false,
+        };
+
+        if let Some(rules) = config.rules.as_deref().filter(|_| !flagged) {
+            let verdict = async {
+                let guard = EngineGuard::lock(&self.data, key).await?;
+                let engine = guard.engine().await.read().await;
+                engine.client.moderate(content, rules).await
+            }
+            .await;
+
+            flagged = match verdict {
+                Ok(verdict) => verdict,
+                Err(why) => {
+                    let fail_closed = config.fail_closed.unwrap_or(false);
+                    log::warn!(
+                        "moderation check failed, {} the message: {why:?}",
+                        match fail_closed {
+                            true => "refusing",
+                            false => "allowing",
+                        }
+                    );
+                    fail_closed
+                }
+            };
+        }
+
+        if !flagged {
+            return Ok(None);
+        }
+
+        match config.log_flagged.unwrap_or(false) {
+            true => log::info!("refused message from {key}: {content}"),
+            false => log::info!("refused message from {key}"),
+        }
+
+        Ok(Some(
+            config
+                .refusal
+                .unwrap_or_else(|| DEFAULT_REFUSAL.to_string()),
+        ))
+    }
+}
+
+/// The first of `keywords` found in `content`, as a whole word in any case.
+fn blocked_keyword<'a>(
+    keywords: impl IntoIterator<Item = &'a String>,
+    content: &str,
+) -> anyhow::Result<Option<&'a String>> {
+    for keyword in keywords {
+        let regex = Regex::new(&format!(r"(?i)\b{}\b", regex::escape(keyword)))?;
+        if regex.is_match(content) {
+            return Ok(Some(keyword));
+        }
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keywords_block_whole_words_in_any_case() {
+        let keywords = vec!["spam".to_string(), "scam".to_string()];
+
+        assert_eq!(
+            blocked_keyword(&keywords, "this is a SCAM").unwrap(),
+            Some(&keywords[1])
+        );
+        assert_eq!(blocked_keyword(&keywords, "spammer scams").unwrap(), None);
+        assert_eq!(blocked_keyword(&[], "spam").unwrap(), None);
+    }
+}
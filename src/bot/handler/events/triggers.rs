@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+
+use regex::Regex;
+
+use crate::config::structure::TriggerAction;
+
+use super::super::Handler;
+
+/// The configured keyword triggers, compiled into case-insensitive word-boundary patterns.
+pub struct Triggers {
+    triggers: Vec<(String, Regex, TriggerAction)>,
+}
+
+/// What a message set off, with all system notes merged and only the first fixed reply kept.
+#[derive(Default)]
+pub struct TriggerOutcome {
+    pub system_note: Option<String>,
+    pub reply: Option<String>,
+    pub tags: Vec<String>,
+}
+
+impl Triggers {
+    /// A keyword that's one of the bot's `names` (its name and aliases) matches all of them.
+    pub fn new(
+        triggers: &HashMap<String, TriggerAction>,
+        names: &[String],
+    ) -> anyhow::Result<Self> {
+        let mut triggers = triggers
+            .iter()
+            .map(|(keyword, action)| {
+                let is_name = names.iter().any(|name| name.eq_ignore_ascii_case(keyword));
+                let pattern = match is_name {
+                    true => names
+                        .iter()
+                        .filter(|name| !name.is_empty())
+                        .map(|name| word_pattern(name))
+                        .collect::<Vec<_>>()
+                        .join("|"),
+                    false => word_pattern(keyword),
+                };
+
+                let regex = Regex::new(&format!(r"(?i)(?:{pattern})"))?;
+                Ok((keyword.clone(), regex, action.clone()))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        // hash maps don't keep an order, sort so the same message always ends up the same way
+        triggers.sort_by(|(a, ..), (b, ..)| a.cmp(b));
+
+        Ok(Self { triggers })
+    }
+
+    pub fn check(&self, content: &str) -> TriggerOutcome {
+        let mut outcome = TriggerOutcome::default();
+        let mut notes = vec![];
+
+        for (keyword, regex, action) in &self.triggers {
+            if !regex.is_match(content) {
+                continue;
+            }
+
+            log::debug!("message triggered keyword \"{keyword}\"");
+
+            match action {
+                TriggerAction::SystemNote { note } => notes.push(note.clone()),
+                TriggerAction::Reply { content } => {
+                    outcome.reply.get_or_insert_with(|| content.clone());
+                }
+                TriggerAction::Tag { tag } => outcome.tags.push(tag.clone()),
+            }
+        }
+
+        if !notes.is_empty() {
+            outcome.system_note = Some(notes.join("\n"));
+        }
+
+        outcome
+    }
+}
+
+/// Matches `word` as a whole word. `\b` needs a word character beside it, so keywords like
+/// "c++" only get it on their word ends.
+fn word_pattern(word: &str) -> String {
+    let edge = |c: Option<char>| match c {
+        Some(c) if c.is_alphanumeric() || c == '_' => r"\b",
+        _ => "",
+    };
+
+    format!(
+        "{}{}{}",
+        edge(word.chars().next()),
+        regex::escape(word),
+        edge(word.chars().last())
+    )
+}
+
+impl Handler {
+    /// Checks a message against the configured keyword triggers.
+    pub async fn check_triggers(&self, content: &str) -> anyhow::Result<TriggerOutcome> {
+        let config = self.data.config.read().await;
+
+        match &config.triggers {
+            Some(triggers) if !triggers.is_empty() => {
+                let system = &config.context.system;
+                let names = std::iter::once(&system.chatbot_name)
+                    .chain(system.chatbot_aliases.iter().flatten())
+                    .cloned()
+                    .collect::<Vec<_>>();
+
+                Ok(Triggers::new(triggers, &names)?.check(content))
+            }
+            _ => Ok(TriggerOutcome::default()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn triggers(entries: &[(&str, TriggerAction)]) -> Triggers {
+        let entries = entries
+            .iter()
+            .map(|(keyword, action)| (keyword.to_string(), action.clone()))
+            .collect();
+
+        Triggers::new(&entries, &[]).unwrap()
+    }
+
+    fn note(note: &str) -> TriggerAction {
+        TriggerAction::SystemNote {
+            note: note.to_string(),
+        }
+    }
+
+    fn reply(content: &str) -> TriggerAction {
+        TriggerAction::Reply {
+            content: content.to_string(),
+        }
+    }
+
+    #[test]
+    fn keywords_match_whole_words_in_any_case() {
+        let triggers = triggers(&[("cat", reply("meow"))]);
+
+        assert_eq!(
+            triggers.check("I love my CAT!").reply.as_deref(),
+            Some("meow")
+        );
+        assert_eq!(triggers.check("concatenate").reply, None);
+    }
+
+    #[test]
+    fn keywords_are_matched_literally() {
+        let triggers = triggers(&[("c++", reply("templates"))]);
+
+        assert!(triggers.check("learning c++ today").reply.is_some());
+        assert!(triggers.check("learning c today").reply.is_none());
+    }
+
+    #[test]
+    fn the_bots_name_matches_its_aliases_too() {
+        let entries = HashMap::from([("Botty".to_string(), reply("that's me"))]);
+        let names = ["Botty".to_string(), "Bot".to_string(), String::new()];
+        let triggers = Triggers::new(&entries, &names).unwrap();
+
+        assert!(triggers.check("hey botty").reply.is_some());
+        assert!(triggers.check("hey bot, you there?").reply.is_some());
+        assert!(triggers.check("about bottles").reply.is_none());
+    }
+
+    #[test]
+    fn notes_are_merged_and_the_first_reply_wins() {
+        let triggers = triggers(&[
+            ("beta", note("second")),
+            ("alpha", note("first")),
+            ("zulu", reply("z")),
+            ("yankee", reply("y")),
+            (
+                "tagged",
+                TriggerAction::Tag {
+                    tag: "review".to_string(),
+                },
+            ),
+        ]);
+
+        let outcome = triggers.check("alpha beta yankee zulu tagged");
+
+        assert_eq!(outcome.system_note.as_deref(), Some("first\nsecond"));
+        assert_eq!(outcome.reply.as_deref(), Some("y"));
+        assert_eq!(outcome.tags, ["review"]);
+    }
+
+    #[test]
+    fn untriggered_messages_change_nothing() {
+        let outcome = triggers(&[("alpha", note("first"))]).check("nothing here");
+
+        assert_eq!(outcome.system_note, None);
+        assert_eq!(outcome.reply, None);
+        assert!(outcome.tags.is_empty());
+    }
+}
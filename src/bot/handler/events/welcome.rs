@@ -0,0 +1,133 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use chrono::TimeDelta;
+use serenity::all::{ChannelId, Context, CreateMessage, GuildId, Member, Mentionable};
+
+use crate::{chat::prompt::SystemPromptBuilder, config::structure::WelcomeConfig};
+
+use super::super::Handler;
+
+impl Handler {
+    /// Greets a member who just joined, in the configured channel or their DMs.
+    pub async fn on_member_join(&self, ctx: Context, member: Member) {
+        if member.user.bot {
+            return;
+        }
+
+        let (welcome, system) = {
+            let config = self.data.config.read().await;
+            match &config.welcome {
+                Some(welcome) if welcome.enabled => {
+                    (welcome.clone(), config.context.system.clone())
+                }
+                _ => return,
+            }
+        };
+
+        // the bot can be in many guilds, only welcome members of the one the channel is in
+        if let Some(channel) = welcome.channel {
+            let guild = ChannelId::new(channel)
+                .to_channel(&ctx)
+                .await
+                .ok()
+                .and_then(|channel| channel.guild())
+                .map(|channel| channel.guild_id);
+            if guild.is_some_and(|guild| guild != member.guild_id) {
+                return;
+            }
+        }
+
+        let cooldown = Duration::from_secs(welcome.cooldown_secs.unwrap_or(10));
+        let mut welcomed = self.data.welcomed.lock().await;
+        if !cooldown_over(&mut welcomed, member.guild_id, cooldown, Instant::now()) {
+            log::info!("not welcoming {}, one was sent moments ago", member.user.id);
+            return;
+        }
+        drop(welcomed);
+
+        let message = CreateMessage::new().content(welcome_message(system, &welcome, &member));
+        let result = match welcome.channel {
+            Some(channel) => {
+                ChannelId::new(channel)
+                    .send_message(&ctx.http, message)
+                    .await
+            }
+            None => member.user.direct_message(&ctx.http, message).await,
+        };
+
+        match result {
+            Ok(_) => log::info!("welcomed {}", member.user.id),
+            Err(why) => log::error!("failed to welcome {}: {why:?}", member.user.id),
+        }
+    }
+}
+
+/// Fills in the welcome template, `{user}` mentions the new member.
+fn welcome_message(
+    mut system: SystemPromptBuilder,
+    welcome: &WelcomeConfig,
+    member: &Member,
+) -> String {
+    system.user_name = member.mention().to_string();
+    system.substitute(&welcome.template, TimeDelta::zero())
+}
+
+/// Whether a guild's last welcome is at least `cooldown` ago, noting `now` as the latest if so.
+fn cooldown_over(
+    welcomed: &mut HashMap<GuildId, Instant>,
+    guild: GuildId,
+    cooldown: Duration,
+    now: Instant,
+) -> bool {
+    match welcomed.get(&guild) {
+        Some(last) if now.duration_since(*last) < cooldown => false,
+        _ => {
+            welcomed.insert(guild, now);
+            true
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_guild_has_its_own_cooldown() {
+        let mut welcomed = HashMap::new();
+        let (first, second) = (GuildId::new(1), GuildId::new(2));
+        let cooldown = Duration::from_secs(10);
+        let start = Instant::now();
+        let after = |secs| start + Duration::from_secs(secs);
+
+        assert!(cooldown_over(&mut welcomed, first, cooldown, start));
+        assert!(!cooldown_over(&mut welcomed, first, cooldown, after(9)));
+        // a raid on one guild doesn't keep the others from being welcomed
+        assert!(cooldown_over(&mut welcomed, second, cooldown, after(9)));
+
+        assert!(cooldown_over(&mut welcomed, first, cooldown, after(10)));
+        assert!(!cooldown_over(&mut welcomed, first, cooldown, after(19)));
+    }
+
+    #[test]
+    fn templates_mention_the_new_member() {
+        let system = SystemPromptBuilder {
+            chatbot_name: "Botty".to_string(),
+            ..Default::default()
+        };
+        let welcome = WelcomeConfig {
+            template: "Welcome {user}, I'm {bot}!".to_string(),
+            ..Default::default()
+        };
+        let mut member = Member::default();
+        member.user.id = serenity::all::UserId::new(42);
+
+        assert_eq!(
+            welcome_message(system, &welcome, &member),
+            "Welcome <@42>, I'm Botty!"
+        );
+    }
+}
@@ -0,0 +1,22 @@
+use super::{Context, Error};
+use crate::bot::handler::{
+    Handler,
+    events::{HandlerResult, commands},
+};
+
+/// Sets or shows what the bot knows about you
+///
+/// In group mode the conversation is the channel's, so this is shared by everyone in it.
+#[poise::command(slash_command, prefix_command)]
+pub(super) async fn aboutme(
+    ctx: Context<'_>,
+    #[description = "A few words about yourself (if not provided, will print your current about)"]
+    about: Option<String>,
+    #[description = "Go back to the default about"] reset: Option<bool>,
+) -> Result<(), Error> {
+    if let HandlerResult::Err(why) = commands::aboutme(ctx, about, reset).await {
+        Handler::on_error(ctx.data(), why).await;
+    }
+
+    Ok(())
+}
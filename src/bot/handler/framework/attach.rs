@@ -0,0 +1,20 @@
+use serenity::all::Attachment;
+
+use super::{Context, Error};
+use crate::bot::handler::{
+    Handler,
+    events::{HandlerResult, commands},
+};
+
+/// Attaches a text document to your conversation, cleared along with it
+#[poise::command(slash_command, prefix_command)]
+pub(super) async fn attach(
+    ctx: Context<'_>,
+    #[description = "Text document to hand to the model"] file: Attachment,
+) -> Result<(), Error> {
+    if let HandlerResult::Err(why) = commands::attach(ctx, file).await {
+        Handler::on_error(ctx.data(), why).await;
+    }
+
+    Ok(())
+}
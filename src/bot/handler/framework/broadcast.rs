@@ -0,0 +1,21 @@
+use serenity::all::ChannelId;
+
+use super::{Context, Error};
+use crate::bot::handler::{
+    Handler,
+    events::{HandlerResult, commands},
+};
+
+/// Has the bot announce something in character
+#[poise::command(slash_command, prefix_command, owners_only)]
+pub(super) async fn broadcast(
+    ctx: Context<'_>,
+    #[description = "Where to post it"] channel: ChannelId,
+    #[description = "What the message should be about"] prompt: String,
+) -> Result<(), Error> {
+    if let HandlerResult::Err(why) = commands::broadcast(ctx, channel, prompt).await {
+        Handler::on_error(ctx.data(), why).await;
+    }
+
+    Ok(())
+}
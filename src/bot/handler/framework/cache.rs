@@ -0,0 +1,36 @@
+use super::{Context, Error};
+use crate::bot::handler::{
+    Handler,
+    events::{HandlerResult, commands},
+};
+
+/// Manages the embedding cache
+#[poise::command(
+    slash_command,
+    prefix_command,
+    owners_only,
+    subcommands("stats", "clear")
+)]
+pub(super) async fn cache(_: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Shows how big the embedding cache is and how often it's hit
+#[poise::command(slash_command, prefix_command, owners_only)]
+async fn stats(ctx: Context<'_>) -> Result<(), Error> {
+    if let HandlerResult::Err(why) = commands::cache_stats(ctx).await {
+        Handler::on_error(ctx.data(), why).await;
+    }
+
+    Ok(())
+}
+
+/// Empties the embedding cache
+#[poise::command(slash_command, prefix_command, owners_only)]
+async fn clear(ctx: Context<'_>) -> Result<(), Error> {
+    if let HandlerResult::Err(why) = commands::cache_clear(ctx).await {
+        Handler::on_error(ctx.data(), why).await;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,18 @@
+use super::{Context, Error};
+use crate::bot::handler::{
+    Handler,
+    events::{HandlerResult, commands},
+};
+
+/// Talks to the bot, for channels that only allow commands
+#[poise::command(slash_command, prefix_command)]
+pub(super) async fn chat(
+    ctx: Context<'_>,
+    #[description = "What to say"] message: String,
+) -> Result<(), Error> {
+    if let HandlerResult::Err(why) = commands::chat(ctx, message).await {
+        Handler::on_error(ctx.data(), why).await;
+    }
+
+    Ok(())
+}
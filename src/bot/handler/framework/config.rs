@@ -15,7 +15,7 @@ pub(super) async fn config(
     >,
 ) -> Result<(), Error> {
     if let HandlerResult::Err(why) = commands::config(ctx, key, value).await {
-        Handler::on_error(why).await;
+        Handler::on_error(ctx.data(), why).await;
     }
 
     Ok(())
@@ -0,0 +1,54 @@
+use serenity::all::UserId;
+
+use super::{Context, Error};
+use crate::bot::handler::{
+    Handler,
+    events::{HandlerResult, commands},
+};
+
+/// Debugging tools
+#[poise::command(slash_command, prefix_command, subcommands("last", "recall", "user"))]
+pub(super) async fn debug(_: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Shows where the time and tokens of your last turn went
+#[poise::command(slash_command, prefix_command)]
+async fn last(ctx: Context<'_>) -> Result<(), Error> {
+    if let HandlerResult::Err(why) = commands::debug_last(ctx).await {
+        Handler::on_error(ctx.data(), why).await;
+    }
+
+    Ok(())
+}
+
+/// Shows which of your memories a message would recall, and how closely they match
+#[poise::command(slash_command, prefix_command)]
+async fn recall(
+    ctx: Context<'_>,
+    #[description = "The message to recall for"] text: String,
+    #[description = "How many memories to show, 5 by default"] limit: Option<u64>,
+) -> Result<(), Error> {
+    if let HandlerResult::Err(why) = commands::debug_recall(ctx, text, limit).await {
+        Handler::on_error(ctx.data(), why).await;
+    }
+
+    Ok(())
+}
+
+/// Logs a user's interactions more verbosely, without raising the log level of everything else
+#[poise::command(slash_command, prefix_command, owners_only)]
+async fn user(
+    ctx: Context<'_>,
+    #[description = "Whose interactions to log"] user: UserId,
+    #[description = "Whether to log them verbosely"] enabled: bool,
+    #[description = "Include what they said and what the bot replied, left out by default"]
+    #[rename = "unsafe"]
+    unredacted: Option<bool>,
+) -> Result<(), Error> {
+    if let HandlerResult::Err(why) = commands::debug_user(ctx, user, enabled, unredacted).await {
+        Handler::on_error(ctx.data(), why).await;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,19 @@
+use super::{Context, Error};
+use crate::bot::handler::{
+    Handler,
+    events::{HandlerResult, commands},
+};
+
+/// Rates the bot's last reply, to help improve it
+#[poise::command(slash_command, prefix_command)]
+pub(super) async fn feedback(
+    ctx: Context<'_>,
+    #[description = "Was the reply good or bad?"] rating: commands::Rating,
+    #[description = "What was good or bad about it"] comment: Option<String>,
+) -> Result<(), Error> {
+    if let HandlerResult::Err(why) = commands::feedback(ctx, rating, comment).await {
+        Handler::on_error(ctx.data(), why).await;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,20 @@
+use super::{Context, Error};
+use crate::bot::handler::{
+    Handler,
+    events::{HandlerResult, commands},
+};
+
+/// Deletes one of your long-term memories
+#[poise::command(slash_command, prefix_command)]
+pub(super) async fn forget(
+    ctx: Context<'_>,
+    #[description = "Memory to forget, search by typing"]
+    #[autocomplete = "commands::autocomplete_memory"]
+    memory: String,
+) -> Result<(), Error> {
+    if let HandlerResult::Err(why) = commands::forget(ctx, memory).await {
+        Handler::on_error(ctx.data(), why).await;
+    }
+
+    Ok(())
+}
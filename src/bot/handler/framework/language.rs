@@ -0,0 +1,22 @@
+use super::{Context, Error};
+use crate::bot::handler::{
+    Handler,
+    events::{HandlerResult, commands},
+};
+
+/// Sets or shows the language the bot speaks with you
+///
+/// In group mode the conversation is the channel's, and so is its language.
+#[poise::command(slash_command, prefix_command)]
+pub(super) async fn language(
+    ctx: Context<'_>,
+    #[description = "One of the allowed languages, or auto (if not provided, will print your current language)"]
+    #[autocomplete = "commands::autocomplete_language"]
+    language: Option<String>,
+) -> Result<(), Error> {
+    if let HandlerResult::Err(why) = commands::language(ctx, language).await {
+        Handler::on_error(ctx.data(), why).await;
+    }
+
+    Ok(())
+}
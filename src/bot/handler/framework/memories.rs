@@ -0,0 +1,36 @@
+use serenity::all::Attachment;
+
+use super::{Context, Error};
+use crate::bot::handler::{
+    Handler,
+    events::{HandlerResult, commands},
+};
+
+/// Moves your long-term memories in and out of the bot
+#[poise::command(slash_command, prefix_command, subcommands("export", "import"))]
+pub(super) async fn memories(_: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Sends you all of your memories as a JSON file
+#[poise::command(slash_command, prefix_command)]
+async fn export(ctx: Context<'_>) -> Result<(), Error> {
+    if let HandlerResult::Err(why) = commands::memories_export(ctx).await {
+        Handler::on_error(ctx.data(), why).await;
+    }
+
+    Ok(())
+}
+
+/// Adds the memories from an exported JSON file
+#[poise::command(slash_command, prefix_command)]
+async fn import(
+    ctx: Context<'_>,
+    #[description = "A file made by /memories export"] file: Attachment,
+) -> Result<(), Error> {
+    if let HandlerResult::Err(why) = commands::memories_import(ctx, file).await {
+        Handler::on_error(ctx.data(), why).await;
+    }
+
+    Ok(())
+}
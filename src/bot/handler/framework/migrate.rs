@@ -0,0 +1,15 @@
+use super::{Context, Error};
+use crate::bot::handler::{
+    Handler,
+    events::{HandlerResult, commands},
+};
+
+/// Re-embeds your stored memories after switching embedding models
+#[poise::command(slash_command, prefix_command)]
+pub(super) async fn migrate(ctx: Context<'_>) -> Result<(), Error> {
+    if let HandlerResult::Err(why) = commands::migrate(ctx).await {
+        Handler::on_error(ctx.data(), why).await;
+    }
+
+    Ok(())
+}
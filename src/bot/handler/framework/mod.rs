@@ -1,46 +1,239 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    time::Instant,
+};
 
-use serenity::all::{Framework, UserId};
+use serenity::all::{ChannelId, Framework, GuildId, Http, ShardManager, UserId};
 
 use tokio::{
     sync::{
-        RwLock,
+        Mutex, RwLock,
         broadcast::{Receiver, Sender},
     },
     task::JoinHandle,
 };
 
-use crate::{chat::engine::ChatEngine, config::store::ChatBotConfig};
+use crate::{
+    chat::{
+        client::{format_markdown, missing_key},
+        engine::ChatEngine,
+    },
+    config::store::ChatBotConfig,
+};
 
 pub type Error = Box<dyn std::error::Error + Send + Sync>;
 pub type Context<'a> = poise::Context<'a, Data, Error>;
 
+mod aboutme;
+mod attach;
+mod broadcast;
+mod cache;
+mod chat;
 mod clear;
 mod config;
+mod debug;
+mod feedback;
+mod forget;
+mod language;
+mod memories;
+mod migrate;
+mod pause;
+mod regenerate;
+mod register;
 mod reload;
+mod remember;
+mod resume;
+mod standing;
+mod stats;
+mod timezone;
+mod tools;
+mod tts;
+mod uptime;
 
+/// Shared by every shard. Engines are keyed by user (or channel in group mode) rather than by
+/// shard, so a conversation gets the same engine whichever shard its events come in on.
 pub struct InnerData {
     pub config: RwLock<ChatBotConfig>,
     pub user_map: RwLock<HashMap<UserId, RwLock<ChatEngine>>>,
     pub freewill_map: RwLock<HashMap<UserId, JoinHandle<()>>>,
-    pub context: RwLock<Option<Arc<serenity::client::Context>>>,
+    /// Engines that keep taking in messages but don't reply, paused with /pause.
+    pub paused: RwLock<HashSet<UserId>>,
+    /// When each guild last had a member welcomed, raids shouldn't turn into a wall of greetings.
+    pub welcomed: Mutex<HashMap<GuildId, Instant>>,
+    /// When each user last regenerated a reply, every regeneration costs a request.
+    pub last_regens: Mutex<HashMap<UserId, Instant>>,
+    /// When each channel last got an error relayed into it, so a burst of them only shows once.
+    pub last_errors: Mutex<HashMap<ChannelId, Instant>>,
+    /// Runs every shard, set once the client is built. Used to take them all offline on shutdown.
+    pub shard_manager: RwLock<Option<Arc<ShardManager>>>,
     pub msg_channel: (Sender<String>, Receiver<String>),
+    /// When the bot started, for /uptime.
+    pub started: Instant,
+    /// Messages the bot replied to since it started.
+    pub messages_handled: AtomicU64,
+    /// Names of the registered commands, so their prefix invocations aren't chatted with.
+    pub command_names: HashSet<String>,
+    /// Whether there's no usable API key, checked on startup and whenever the config changes.
+    /// Engines aren't built until there is one, users get the setup message instead.
+    pub missing_credentials: AtomicBool,
 }
 pub type Data = Arc<InnerData>;
 
+/// Key of the engine a channel shares in group mode. Engines are keyed by user ids, which never
+/// collide with channel ids as both are snowflakes.
+pub fn channel_key(channel: ChannelId) -> UserId {
+    UserId::new(channel.get())
+}
+
+fn log_missing_credentials(config: &ChatBotConfig) {
+    log::error!(
+        "NO CREDENTIALS: `api_key` for {} is missing or a placeholder, set it in the config. Users are getting the setup message until then.",
+        config.llm.provider
+    );
+}
+
+impl InnerData {
+    pub fn new(config: ChatBotConfig, command_names: HashSet<String>) -> Self {
+        let missing_credentials = AtomicBool::new(missing_key(&config.llm));
+        if missing_credentials.load(Ordering::Relaxed) {
+            log_missing_credentials(&config);
+        }
+
+        Self {
+            config: RwLock::new(config),
+            user_map: RwLock::new(HashMap::new()),
+            freewill_map: RwLock::new(HashMap::new()),
+            paused: RwLock::new(HashSet::new()),
+            welcomed: Mutex::new(HashMap::new()),
+            last_regens: Mutex::new(HashMap::new()),
+            last_errors: Mutex::new(HashMap::new()),
+            msg_channel: tokio::sync::broadcast::channel(100),
+            shard_manager: RwLock::new(None),
+            started: Instant::now(),
+            messages_handled: AtomicU64::new(0),
+            command_names,
+            missing_credentials,
+        }
+    }
+
+    /// Checks the API key again after the config changed, returns whether it's usable.
+    pub async fn check_credentials(&self) -> bool {
+        let config = self.config.read().await;
+        let missing = missing_key(&config.llm);
+
+        let was_missing = self.missing_credentials.swap(missing, Ordering::Relaxed);
+        if missing && !was_missing {
+            log_missing_credentials(&config);
+        }
+
+        !missing
+    }
+
+    /// Data on a default config, saved under the temp directory in a folder of its own.
+    #[cfg(test)]
+    pub fn scratch(name: &str) -> Data {
+        let dir = std::env::temp_dir().join("chatbot-tests").join(name);
+        let _ = std::fs::remove_dir_all(&dir);
+        let config = ChatBotConfig::read(dir.join("config.toml")).expect("config can be created");
+
+        Arc::new(Self::new(config, HashSet::new()))
+    }
+
+    /// Returns the key of the engine a conversation belongs to. That's normally its author,
+    /// but in group mode everyone in a channel shares a single engine, keyed by the channel id.
+    pub async fn engine_key(&self, user: UserId, channel: ChannelId) -> UserId {
+        let config = self.config.read().await;
+
+        match config.context.group_mode.unwrap_or(false) {
+            true => channel_key(channel),
+            false => user,
+        }
+    }
+
+    /// Strips or escapes the markdown of a reply, as configured for the channel it's sent to.
+    pub async fn format_markdown(&self, channel: ChannelId, text: String) -> String {
+        let mode = self
+            .config
+            .read()
+            .await
+            .discord
+            .channel_markdown
+            .as_ref()
+            .and_then(|channels| channels.get(&channel.to_string()).copied())
+            .unwrap_or_default();
+
+        format_markdown(text, mode)
+    }
+}
+
+/// The guilds to register commands in, `None` for registering them globally.
+pub fn registration_guilds(guilds: Option<Vec<u64>>) -> Option<Vec<GuildId>> {
+    guilds
+        .filter(|guilds| !guilds.is_empty())
+        .map(|guilds| guilds.into_iter().map(GuildId::new).collect())
+}
+
+/// Registers the commands in each of `guilds`, or globally when there are none.
+pub async fn register_commands(
+    http: &Http,
+    commands: &[poise::Command<Data, Error>],
+    guilds: Option<Vec<u64>>,
+) -> Result<(), serenity::Error> {
+    match registration_guilds(guilds) {
+        Some(guilds) => {
+            for guild in guilds {
+                poise::builtins::register_in_guild(http, commands, guild).await?;
+            }
+        }
+        None => poise::builtins::register_globally(http, commands).await?,
+    }
+
+    Ok(())
+}
+
 pub async fn framework(config: ChatBotConfig) -> (impl Framework + 'static, Data) {
-    let data = Arc::new(InnerData {
-        config: RwLock::new(config),
-        user_map: RwLock::new(HashMap::new()),
-        freewill_map: RwLock::new(HashMap::new()),
-        msg_channel: tokio::sync::broadcast::channel(100),
-        context: RwLock::new(None),
-    });
+    let commands = vec![
+        clear::clear(),
+        reload::reload(),
+        config::config(),
+        migrate::migrate(),
+        tools::tools(),
+        regenerate::regenerate(),
+        stats::stats(),
+        timezone::timezone(),
+        forget::forget(),
+        attach::attach(),
+        aboutme::aboutme(),
+        pause::pause(),
+        resume::resume(),
+        remember::remember(),
+        debug::debug(),
+        cache::cache(),
+        chat::chat(),
+        memories::memories(),
+        feedback::feedback(),
+        broadcast::broadcast(),
+        uptime::uptime(),
+        tts::tts(),
+        language::language(),
+        standing::standing(),
+        register::register(),
+    ];
+
+    let command_names = commands
+        .iter()
+        .map(|command| command.name.clone())
+        .collect();
+    let data = Arc::new(InnerData::new(config, command_names));
 
     (
         poise::Framework::builder()
             .options(poise::FrameworkOptions {
-                commands: vec![clear::clear(), reload::reload(), config::config()],
+                commands,
                 ..Default::default()
             })
             .setup({
@@ -48,7 +241,8 @@ pub async fn framework(config: ChatBotConfig) -> (impl Framework + 'static, Data
                 move |ctx, _ready, framework| {
                     Box::pin({
                         async move {
-                            poise::builtins::register_globally(ctx, &framework.options().commands)
+                            let guilds = data.config.read().await.discord.command_guilds.clone();
+                            register_commands(&ctx.http, &framework.options().commands, guilds)
                                 .await?;
                             Ok(data)
                         }
@@ -59,3 +253,59 @@ pub async fn framework(config: ChatBotConfig) -> (impl Framework + 'static, Data
         data,
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::chat::{client::MissingCredentials, engine::EngineGuard};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn engines_wait_for_credentials() {
+        let data = InnerData::scratch("missing-credentials");
+        data.config.write().await.llm.setup_message = Some("Ask Alice for a key.".to_string());
+        assert!(data.missing_credentials.load(Ordering::Relaxed));
+
+        let Err(why) = EngineGuard::lock(&data, UserId::new(1)).await else {
+            panic!("there is no key to build an engine with");
+        };
+        assert_eq!(
+            why.downcast_ref::<MissingCredentials>().unwrap().0,
+            "Ask Alice for a key."
+        );
+        assert!(data.user_map.read().await.is_empty());
+
+        data.config.write().await.llm.api_key = "sk-test".to_string();
+        assert!(data.check_credentials().await);
+        assert!(!data.missing_credentials.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn commands_are_registered_globally_without_guilds() {
+        assert_eq!(registration_guilds(None), None);
+        assert_eq!(registration_guilds(Some(vec![])), None);
+        assert_eq!(
+            registration_guilds(Some(vec![1, 2])),
+            Some(vec![GuildId::new(1), GuildId::new(2)])
+        );
+    }
+
+    #[tokio::test]
+    async fn markdown_is_formatted_per_channel() {
+        let data = InnerData::scratch("channel-markdown");
+        data.config.write().await.discord.channel_markdown = Some(HashMap::from([(
+            "2".to_string(),
+            crate::config::structure::MarkdownMode::Escape,
+        )]));
+
+        let text = || "*hi*".to_string();
+        assert_eq!(
+            data.format_markdown(ChannelId::new(1), text()).await,
+            "*hi*"
+        );
+        assert_eq!(
+            data.format_markdown(ChannelId::new(2), text()).await,
+            "\\*hi\\*"
+        );
+    }
+}
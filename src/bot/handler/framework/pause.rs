@@ -0,0 +1,15 @@
+use super::{Context, Error};
+use crate::bot::handler::{
+    Handler,
+    events::{HandlerResult, commands},
+};
+
+/// Stops replying to you without forgetting what you say, until /resume
+#[poise::command(slash_command, prefix_command)]
+pub(super) async fn pause(ctx: Context<'_>) -> Result<(), Error> {
+    if let HandlerResult::Err(why) = commands::pause(ctx).await {
+        Handler::on_error(ctx.data(), why).await;
+    }
+
+    Ok(())
+}
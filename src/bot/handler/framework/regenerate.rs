@@ -0,0 +1,21 @@
+use super::{Context, Error};
+use crate::bot::handler::{
+    Handler,
+    events::{HandlerResult, commands},
+};
+
+/// Regenerates the latest reply, optionally with a different model or a nudge
+#[poise::command(slash_command, prefix_command)]
+pub(super) async fn regenerate(
+    ctx: Context<'_>,
+    #[description = "Model to regenerate the reply with"]
+    #[autocomplete = "commands::autocomplete_model"]
+    model: Option<String>,
+    #[description = "How the new reply should differ, like \"shorter\""] nudge: Option<String>,
+) -> Result<(), Error> {
+    if let HandlerResult::Err(why) = commands::regenerate(ctx, model, nudge).await {
+        Handler::on_error(ctx.data(), why).await;
+    }
+
+    Ok(())
+}
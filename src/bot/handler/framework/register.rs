@@ -0,0 +1,15 @@
+use super::{Context, Error};
+use crate::bot::handler::{
+    Handler,
+    events::{HandlerResult, commands},
+};
+
+/// Registers the bot's commands with discord again
+#[poise::command(slash_command, prefix_command, owners_only)]
+pub(super) async fn register(ctx: Context<'_>) -> Result<(), Error> {
+    if let HandlerResult::Err(why) = commands::register(ctx).await {
+        Handler::on_error(ctx.data(), why).await;
+    }
+
+    Ok(())
+}
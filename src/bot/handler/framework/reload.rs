@@ -8,7 +8,7 @@ use crate::bot::handler::{
 #[poise::command(slash_command, prefix_command)]
 pub(super) async fn reload(ctx: Context<'_>) -> Result<(), Error> {
     if let HandlerResult::Err(why) = commands::reload(ctx).await {
-        Handler::on_error(why).await;
+        Handler::on_error(ctx.data(), why).await;
     }
 
     Ok(())
@@ -0,0 +1,19 @@
+use super::{Context, Error};
+use crate::bot::handler::{
+    Handler,
+    events::{HandlerResult, commands},
+};
+
+/// Adds a long-term memory, as is
+#[poise::command(slash_command, prefix_command)]
+pub(super) async fn remember(
+    ctx: Context<'_>,
+    #[description = "What to remember"] memory: String,
+    #[description = "Topic or session to file it under"] tag: Option<String>,
+) -> Result<(), Error> {
+    if let HandlerResult::Err(why) = commands::remember(ctx, memory, tag).await {
+        Handler::on_error(ctx.data(), why).await;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,18 @@
+use super::{Context, Error};
+use crate::bot::handler::{
+    Handler,
+    events::{HandlerResult, commands},
+};
+
+/// Resumes replying to you after /pause
+#[poise::command(slash_command, prefix_command)]
+pub(super) async fn resume(
+    ctx: Context<'_>,
+    #[description = "Reply to the messages sent while paused"] catch_up: Option<bool>,
+) -> Result<(), Error> {
+    if let HandlerResult::Err(why) = commands::resume(ctx, catch_up).await {
+        Handler::on_error(ctx.data(), why).await;
+    }
+
+    Ok(())
+}
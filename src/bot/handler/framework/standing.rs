@@ -0,0 +1,22 @@
+use super::{Context, Error};
+use crate::bot::handler::{
+    Handler,
+    events::{HandlerResult, commands},
+};
+
+/// Sets or shows context the bot keeps in mind even after /clear
+///
+/// In group mode the conversation is the channel's, and so is its standing context.
+#[poise::command(slash_command, prefix_command)]
+pub(super) async fn standing(
+    ctx: Context<'_>,
+    #[description = "Rules or instructions that always apply (if not provided, will print the current ones)"]
+    context: Option<String>,
+    #[description = "Go back to the default standing context"] reset: Option<bool>,
+) -> Result<(), Error> {
+    if let HandlerResult::Err(why) = commands::standing(ctx, context, reset).await {
+        Handler::on_error(ctx.data(), why).await;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,15 @@
+use super::{Context, Error};
+use crate::bot::handler::{
+    Handler,
+    events::{HandlerResult, commands},
+};
+
+/// Shows how full the current context window is
+#[poise::command(slash_command, prefix_command)]
+pub(super) async fn stats(ctx: Context<'_>) -> Result<(), Error> {
+    if let HandlerResult::Err(why) = commands::stats(ctx).await {
+        Handler::on_error(ctx.data(), why).await;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,22 @@
+use super::{Context, Error};
+use crate::bot::handler::{
+    Handler,
+    events::{HandlerResult, commands},
+};
+
+/// Sets or shows the timezone used for your conversation
+///
+/// In group mode the conversation is the channel's, and so is its timezone.
+#[poise::command(slash_command, prefix_command)]
+pub(super) async fn timezone(
+    ctx: Context<'_>,
+    #[description = "IANA timezone name (if not provided, will print your current timezone)"]
+    #[autocomplete = "commands::autocomplete_timezone"]
+    timezone: Option<String>,
+) -> Result<(), Error> {
+    if let HandlerResult::Err(why) = commands::timezone(ctx, timezone).await {
+        Handler::on_error(ctx.data(), why).await;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,15 @@
+use super::{Context, Error};
+use crate::bot::handler::{
+    Handler,
+    events::{HandlerResult, commands},
+};
+
+/// Lists the tools the bot can use and how often it used them
+#[poise::command(slash_command, prefix_command)]
+pub(super) async fn tools(ctx: Context<'_>) -> Result<(), Error> {
+    if let HandlerResult::Err(why) = commands::tools(ctx).await {
+        Handler::on_error(ctx.data(), why).await;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,19 @@
+use super::{Context, Error};
+use crate::bot::handler::{
+    Handler,
+    events::{HandlerResult, commands},
+};
+
+/// Turns reading your replies out loud on or off
+#[poise::command(slash_command, prefix_command)]
+pub(super) async fn tts(
+    ctx: Context<'_>,
+    #[description = "Whether replies should come with audio (if not provided, shows the current setting)"]
+    enabled: Option<bool>,
+) -> Result<(), Error> {
+    if let HandlerResult::Err(why) = commands::tts(ctx, enabled).await {
+        Handler::on_error(ctx.data(), why).await;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,15 @@
+use super::{Context, Error};
+use crate::bot::handler::{
+    Handler,
+    events::{HandlerResult, commands},
+};
+
+/// Shows how long the bot has been running and what it did since
+#[poise::command(slash_command, prefix_command)]
+pub(super) async fn uptime(ctx: Context<'_>) -> Result<(), Error> {
+    if let HandlerResult::Err(why) = commands::uptime(ctx).await {
+        Handler::on_error(ctx.data(), why).await;
+    }
+
+    Ok(())
+}
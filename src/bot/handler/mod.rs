@@ -7,13 +7,19 @@ use std::{
 use events::HandlerResult;
 pub use framework::Data;
 use serenity::{
-    all::{Context, EventHandler, Interaction, Message, MessageUpdateEvent, Ready, UserId},
+    all::{
+        ChannelId, Context, EventHandler, GuildId, Interaction, Member, Message, MessageId,
+        MessageUpdateEvent, Ready, UserId,
+    },
     async_trait,
 };
 use tokio::sync::RwLock;
 use tokio::task::JoinHandle;
 
-use crate::{chat::engine::ChatEngine, utils::macros::config};
+use crate::{
+    chat::engine::ChatEngine,
+    utils::{log::Logger, macros::config},
+};
 
 mod buttons;
 mod events;
@@ -26,6 +32,8 @@ impl Handler {
     pub fn new(data: Data) -> (Arc<Self>, JoinHandle<()>) {
         let handler = Arc::new(Self { data });
 
+        Self::consolidation_spawn(handler.data.clone());
+
         let handle = tokio::spawn({
             let handler = handler.clone();
             let shutdown_rx = setup_ctrlc_handler();
@@ -67,7 +75,7 @@ fn setup_ctrlc_handler() -> mpsc::Receiver<()> {
                     let _ = sender.send(());
                 },
                 _ = int_signal.recv() => {
-                    println!("");
+                    println!();
                     log::info!("SIGINT received, shutting down...");
                     let _ = sender.send(());
                 },
@@ -97,7 +105,15 @@ fn setup_ctrlc_handler() -> mpsc::Receiver<()> {
 #[async_trait]
 impl EventHandler for Handler {
     async fn ready(&self, ctx: Context, ready: Ready) {
-        log::info!("{} is connected!", ready.user.name);
+        match ready.shard {
+            Some(shard) => log::info!(
+                "{} is connected on shard {}/{}!",
+                ready.user.name,
+                shard.id.0 + 1,
+                shard.total
+            ),
+            None => log::info!("{} is connected!", ready.user.name),
+        }
 
         ctx.set_presence(None, serenity::all::OnlineStatus::Online);
 
@@ -144,7 +160,12 @@ impl EventHandler for Handler {
 
                         let mut user_map = self.data.user_map.write().await;
                         let user = UserId::new(id);
-                        let engine = ChatEngine::new(config.clone(), user.clone()).await?;
+
+                        // every shard gets a ready, and so does a reconnect, load each only once
+                        if user_map.contains_key(&user) {
+                            continue;
+                        }
+                        let engine = ChatEngine::new(config.clone(), user).await?;
 
                         user_map.insert(user, RwLock::new(engine));
                     }
@@ -158,19 +179,28 @@ impl EventHandler for Handler {
         if let Err(why) = result {
             log::error!("failed to load saved contexts: {why:?}");
         }
-
-        self.data.context.write().await.replace(Arc::new(ctx));
     }
 
     async fn message(&self, ctx: Context, msg: Message) {
-        if let HandlerResult::Err(error) = self.on_message(ctx, msg).await {
-            Self::on_error(error).await;
+        let user = msg.author.id;
+        if let HandlerResult::Err(error) = Logger::scope(user, self.on_message(ctx, msg)).await {
+            Self::on_error(&self.data, error).await;
         }
     }
 
     async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
-        if let HandlerResult::Err(error) = self.on_interaction(ctx, interaction).await {
-            Self::on_error(error).await;
+        let user = match &interaction {
+            Interaction::Component(component) => Some(component.user.id),
+            Interaction::Modal(modal) => Some(modal.user.id),
+            _ => None,
+        };
+
+        let result = match user {
+            Some(user) => Logger::scope(user, self.on_interaction(ctx, interaction)).await,
+            None => self.on_interaction(ctx, interaction).await,
+        };
+        if let HandlerResult::Err(error) = result {
+            Self::on_error(&self.data, error).await;
         }
     }
 
@@ -182,16 +212,41 @@ impl EventHandler for Handler {
         event: MessageUpdateEvent,
     ) {
         if let HandlerResult::Err(error) = self.on_edit(ctx, old_if_available, new, event).await {
-            Self::on_error(error).await;
+            Self::on_error(&self.data, error).await;
         }
     }
+
+    async fn guild_member_addition(&self, ctx: Context, new_member: Member) {
+        self.on_member_join(ctx, new_member).await;
+    }
+
+    async fn message_delete(
+        &self,
+        _: Context,
+        channel_id: ChannelId,
+        deleted_message_id: MessageId,
+        _: Option<GuildId>,
+    ) {
+        self.on_delete(channel_id, vec![deleted_message_id]).await;
+    }
+
+    async fn message_delete_bulk(
+        &self,
+        _: Context,
+        channel_id: ChannelId,
+        multiple_deleted_messages_ids: Vec<MessageId>,
+        _: Option<GuildId>,
+    ) {
+        self.on_delete(channel_id, multiple_deleted_messages_ids)
+            .await;
+    }
 }
 
 impl Handler {
     async fn shutdown(&self) -> anyhow::Result<()> {
         log::info!("Shutdown signal received, waiting for locks and shutting down...");
         let user_map = self.data.user_map.write().await;
-        let context = self.data.context.write().await;
+        let shard_manager = self.data.shard_manager.write().await;
 
         self.data.msg_channel.0.send("shutdown".to_string())?;
 
@@ -212,10 +267,14 @@ impl Handler {
             engine.write().await.shutdown().await?
         }
 
-        if let Some(context) = context.as_ref() {
-            context.set_presence(None, serenity::all::OnlineStatus::Offline);
+        if let Some(shard_manager) = shard_manager.as_ref() {
+            for runner in shard_manager.runners.lock().await.values() {
+                runner
+                    .runner_tx
+                    .set_presence(None, serenity::all::OnlineStatus::Offline);
+            }
 
-            context.shard.shutdown_clean();
+            shard_manager.shutdown_all().await;
         }
 
         log::info!("Graceful shutdown complete!");
@@ -223,3 +282,56 @@ impl Handler {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::OnceLock;
+
+    use serenity::{
+        all::{Cache, GatewayIntents, Http, ShardManager, ShardManagerOptions},
+        prelude::TypeMap,
+    };
+    use tokio::sync::Mutex;
+
+    use crate::{bot::handler::framework::InnerData, chat::engine::replay::Replay};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn shutdown_signals_tasks_and_takes_the_shards_down() {
+        let data = InnerData::scratch("shutdown");
+        let (shard_manager, _) = ShardManager::new(ShardManagerOptions {
+            data: Arc::new(RwLock::new(TypeMap::new())),
+            event_handlers: vec![],
+            raw_event_handlers: vec![],
+            framework: Arc::new(OnceLock::new()),
+            shard_index: 0,
+            shard_init: 2,
+            shard_total: 2,
+            ws_url: Arc::new(Mutex::new(String::new())),
+            cache: Arc::new(Cache::new()),
+            http: Arc::new(Http::new("")),
+            intents: GatewayIntents::empty(),
+            presence: None,
+        });
+        data.shard_manager.write().await.replace(shard_manager);
+
+        let replay = Replay::new(1015, |_| {}).await.unwrap();
+        data.user_map
+            .write()
+            .await
+            .insert(UserId::new(1015), RwLock::new(replay.engine));
+
+        // stands in for the tasks waiting on a message to clean up after, like button removal
+        let mut listener = data.msg_channel.0.subscribe();
+        let listener = tokio::spawn(async move { listener.recv().await });
+
+        let handler = Handler { data };
+        tokio::time::timeout(Duration::from_secs(5), handler.shutdown())
+            .await
+            .expect("shutdown doesn't hang")
+            .unwrap();
+
+        assert_eq!(listener.await.unwrap().unwrap(), "shutdown");
+    }
+}
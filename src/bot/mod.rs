@@ -1,9 +1,12 @@
 use anyhow::Result;
 use handler::Handler;
-use serenity::{Client, all::GatewayIntents};
+use serenity::{
+    Client,
+    all::{ClientBuilder, CreateAllowedMentions, GatewayIntents, HttpBuilder},
+};
 use tokio::task::JoinHandle;
 
-use crate::config::store::ChatBotConfig;
+use crate::config::{store::ChatBotConfig, structure::DiscordConfig};
 pub use handler::Data;
 
 pub mod handler;
@@ -11,32 +14,120 @@ pub mod handler;
 pub struct ChatBot {
     client: Client,
     handle: JoinHandle<()>,
+    sharding: Sharding,
+}
+
+/// How the gateway connection is split up, see `shards`.
+#[derive(Debug, PartialEq)]
+enum Sharding {
+    Single,
+    Auto,
+    Fixed(u32),
+}
+
+impl Sharding {
+    fn new(shards: Option<u32>) -> Self {
+        match shards {
+            None | Some(1) => Self::Single,
+            Some(0) => Self::Auto,
+            Some(shards) => Self::Fixed(shards),
+        }
+    }
 }
 
 impl ChatBot {
     pub async fn new(config: ChatBotConfig) -> Result<Self> {
-        let builder = serenity::Client::builder(&config.discord.token, GatewayIntents::all());
+        let http = HttpBuilder::new(&config.discord.token)
+            .default_allowed_mentions(Self::allowed_mentions(&config.discord))
+            .build();
+
+        let builder = ClientBuilder::new_with_http(http, GatewayIntents::all());
+        let sharding = Sharding::new(config.discord.shards);
 
         let (framework, data) = handler::framework::framework(config).await;
-        let (handler, handle) = Handler::new(data);
+        let (handler, handle) = Handler::new(data.clone());
 
         let client = builder
             .event_handler_arc(handler)
             .framework(framework)
             .await?;
 
-        Ok(Self { client, handle })
+        // every shard shares the same data, shutting down has to reach all of them
+        data.shard_manager
+            .write()
+            .await
+            .replace(client.shard_manager.clone());
+
+        Ok(Self {
+            client,
+            handle,
+            sharding,
+        })
+    }
+
+    /// The model can write anything, so never let it ping everyone.
+    fn allowed_mentions(config: &DiscordConfig) -> CreateAllowedMentions {
+        CreateAllowedMentions::new()
+            .everyone(false)
+            .all_users(config.allow_user_mentions.unwrap_or(true))
+            .all_roles(config.allow_role_mentions.unwrap_or(true))
+            .replied_user(true)
     }
 
     pub async fn run(self) {
-        let ChatBot { mut client, handle } = self;
+        let ChatBot {
+            mut client,
+            handle,
+            sharding,
+        } = self;
 
         client.shard_manager.shutdown_all().await;
 
-        if let Err(why) = client.start().await {
+        let started = match sharding {
+            Sharding::Single => client.start().await,
+            Sharding::Auto => client.start_autosharded().await,
+            Sharding::Fixed(shards) => client.start_shards(shards).await,
+        };
+
+        if let Err(why) = started {
             log::error!("Client error: {why:?}");
         }
 
         handle.await.unwrap();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+
+    use super::*;
+
+    fn parsed(config: &DiscordConfig) -> Value {
+        serde_json::to_value(ChatBot::allowed_mentions(config)).unwrap()["parse"].clone()
+    }
+
+    #[test]
+    fn everyone_pings_are_never_allowed() {
+        assert_eq!(parsed(&DiscordConfig::default()), json!(["users", "roles"]));
+    }
+
+    #[test]
+    fn user_and_role_pings_can_be_turned_off() {
+        let config = DiscordConfig {
+            allow_user_mentions: Some(false),
+            allow_role_mentions: Some(false),
+            ..Default::default()
+        };
+
+        assert_eq!(parsed(&config), json!([]));
+    }
+
+    #[test]
+    fn zero_shards_lets_discord_decide() {
+        assert_eq!(Sharding::new(None), Sharding::Single);
+        assert_eq!(Sharding::new(Some(1)), Sharding::Single);
+        assert_eq!(Sharding::new(Some(0)), Sharding::Auto);
+        assert_eq!(Sharding::new(Some(4)), Sharding::Fixed(4));
+    }
+}
@@ -0,0 +1,42 @@
+use async_trait::async_trait;
+use serenity::all::UserId;
+
+use super::storage::Memory;
+
+/// Where long-term memories and their embeddings are kept, separately for every user.
+/// Ranking, eviction and the like happen on top of it, in [super::storage::MemoryStorage].
+#[async_trait]
+pub trait MemoryBackend: Send + Sync {
+    /// Fails if the backend is unreachable, or holds vectors of another size than the
+    /// embedding model produces.
+    async fn health_check(&self, user_id: UserId) -> anyhow::Result<()>;
+    async fn count(&self, user_id: UserId) -> anyhow::Result<u64>;
+    /// Inserts memories along with their embeddings, overwriting the ones with the same id.
+    async fn store(&self, user_id: UserId, memories: Vec<(Memory, Vec<f32>)>)
+    -> anyhow::Result<()>;
+    /// The memories most similar (by cosine similarity) to the embedding with their scores,
+    /// most similar first. Only the ones filed under `tag` if given.
+    async fn search(
+        &self,
+        user_id: UserId,
+        embedding: Vec<f32>,
+        limit: u64,
+        tag: Option<&str>,
+    ) -> anyhow::Result<Vec<(f32, Memory)>>;
+    /// Every memory of the user, along with its embedding if asked for.
+    async fn list(
+        &self,
+        user_id: UserId,
+        with_vectors: bool,
+    ) -> anyhow::Result<Vec<(Memory, Option<Vec<f32>>)>>;
+    /// Saves the memory's recall count and last recall.
+    async fn record_recall(&self, user_id: UserId, memory: &Memory) -> anyhow::Result<()>;
+    async fn delete(&self, user_id: UserId, ids: Vec<u64>) -> anyhow::Result<()>;
+    /// Swaps all of the user's memories for the given ones, which may have another vector size.
+    /// Leaves the old ones untouched if it fails before the swap.
+    async fn replace(
+        &self,
+        user_id: UserId,
+        memories: Vec<(Memory, Vec<f32>)>,
+    ) -> anyhow::Result<()>;
+}
@@ -0,0 +1,91 @@
+/// Cosine similarity between two embeddings, 0 if either of them is all zeroes.
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot = a.iter().zip(b).map(|(a, b)| a * b).sum::<f32>();
+    let norm_a = a.iter().map(|a| a * a).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|b| b * b).sum::<f32>().sqrt();
+
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+
+    dot / (norm_a * norm_b)
+}
+
+/// Greedily groups embeddings that are at least `threshold` similar to the first (oldest
+/// unclaimed) member of their group. Returns the indices of every group with at least
+/// `min_size` members, groups smaller than that are left alone.
+pub fn cluster(vectors: &[Vec<f32>], threshold: f32, min_size: usize) -> Vec<Vec<usize>> {
+    let mut claimed = vec![false; vectors.len()];
+    let mut clusters = vec![];
+
+    for seed in 0..vectors.len() {
+        if claimed[seed] {
+            continue;
+        }
+
+        let members = (seed..vectors.len())
+            .filter(|&i| {
+                !claimed[i]
+                    && (i == seed || cosine_similarity(&vectors[seed], &vectors[i]) >= threshold)
+            })
+            .collect::<Vec<_>>();
+
+        if members.len() >= min_size.max(2) {
+            for &i in &members {
+                claimed[i] = true;
+            }
+            clusters.push(members);
+        }
+    }
+
+    clusters
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn similarity_ignores_length_but_not_direction() {
+        assert_eq!(cosine_similarity(&[1.0, 0.0], &[5.0, 0.0]), 1.0);
+        assert_eq!(cosine_similarity(&[1.0, 0.0], &[0.0, 2.0]), 0.0);
+        assert_eq!(cosine_similarity(&[1.0, 0.0], &[0.0, 0.0]), 0.0);
+    }
+
+    #[test]
+    fn memories_right_on_the_threshold_are_clustered() {
+        // exactly 0.6 similar
+        let vectors = vec![vec![1.0, 0.0], vec![3.0, 4.0]];
+
+        assert_eq!(cluster(&vectors, 0.6, 2), [[0, 1]]);
+        assert!(cluster(&vectors, 0.61, 2).is_empty());
+    }
+
+    #[test]
+    fn clusters_below_the_minimum_size_are_left_alone() {
+        let vectors = vec![
+            vec![1.0, 0.0],
+            vec![1.0, 0.1],
+            vec![0.0, 1.0],
+            vec![0.1, 1.0],
+            vec![0.0, 1.1],
+        ];
+
+        assert_eq!(cluster(&vectors, 0.9, 3), [vec![2, 3, 4]]);
+        assert_eq!(cluster(&vectors, 0.9, 2), [vec![0, 1], vec![2, 3, 4]]);
+        // single memories never count as a cluster
+        assert_eq!(cluster(&vectors, 0.9, 0), cluster(&vectors, 0.9, 2));
+    }
+
+    #[test]
+    fn clusters_are_seeded_from_the_oldest_memory() {
+        // the middle one is close to both neighbours, which aren't close to each other
+        let vectors = vec![vec![1.0, 0.0], vec![0.8, 0.6], vec![0.6, 0.8]];
+
+        assert_eq!(cluster(&vectors, 0.7, 2), [[0, 1]]);
+
+        // had the middle one been the oldest, it would've taken all three
+        let vectors = vec![vectors[1].clone(), vectors[0].clone(), vectors[2].clone()];
+        assert_eq!(cluster(&vectors, 0.7, 2), [[0, 1, 2]]);
+    }
+}
@@ -0,0 +1,383 @@
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, Mutex, MutexGuard},
+};
+
+use async_trait::async_trait;
+use indexmap::IndexMap;
+use serenity::all::UserId;
+
+use super::{backend::MemoryBackend, storage::Memory};
+
+/// Keyed by namespace and user, see `memory_scope`.
+type Collections = HashMap<(Option<String>, UserId), IndexMap<u64, (Memory, Vec<f32>)>>;
+
+/// Shared by every engine, they get their own backend each but should see the same memories.
+static COLLECTIONS: LazyLock<Mutex<Collections>> = LazyLock::new(Default::default);
+
+/// Keeps memories in the bot's own memory, losing them on restart. For tests and deployments
+/// too small to be worth running Qdrant for, searches go through every memory of the user.
+pub struct InMemoryBackend {
+    vector_size: u64,
+    namespace: Option<String>,
+}
+
+impl InMemoryBackend {
+    pub fn new(vector_size: u64, namespace: Option<String>) -> Self {
+        Self {
+            vector_size,
+            namespace,
+        }
+    }
+
+    fn key(&self, user_id: UserId) -> (Option<String>, UserId) {
+        (self.namespace.clone(), user_id)
+    }
+
+    fn collections() -> MutexGuard<'static, Collections> {
+        COLLECTIONS.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot = a.iter().zip(b).map(|(a, b)| a * b).sum::<f32>();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+
+    match norm(a) * norm(b) {
+        0.0 => 0.0,
+        norms => dot / norms,
+    }
+}
+
+#[async_trait]
+impl MemoryBackend for InMemoryBackend {
+    async fn health_check(&self, user_id: UserId) -> anyhow::Result<()> {
+        let collections = Self::collections();
+
+        let mismatch = collections.get(&self.key(user_id)).and_then(|memories| {
+            memories
+                .values()
+                .map(|(_, vector)| vector.len() as u64)
+                .find(|size| *size != self.vector_size)
+        });
+
+        match mismatch {
+            Some(vector_size) => Err(anyhow::anyhow!(
+                "vector size mismatch for {user_id}, the stored memories have {vector_size}-dimensional vectors but the embedding model produces {}, run /migrate to re-embed them with the current model",
+                self.vector_size,
+            )),
+            None => Ok(()),
+        }
+    }
+
+    async fn count(&self, user_id: UserId) -> anyhow::Result<u64> {
+        Ok(Self::collections()
+            .get(&self.key(user_id))
+            .map(|memories| memories.len() as u64)
+            .unwrap_or(0))
+    }
+
+    async fn store(
+        &self,
+        user_id: UserId,
+        memories: Vec<(Memory, Vec<f32>)>,
+    ) -> anyhow::Result<()> {
+        let mut collections = Self::collections();
+        let collection = collections.entry(self.key(user_id)).or_default();
+
+        for (memory, vector) in memories {
+            if vector.len() as u64 != self.vector_size {
+                anyhow::bail!(
+                    "expected a {}-dimensional vector, got {}",
+                    self.vector_size,
+                    vector.len()
+                );
+            }
+
+            collection.insert(memory.id, (memory, vector));
+        }
+
+        Ok(())
+    }
+
+    async fn search(
+        &self,
+        user_id: UserId,
+        embedding: Vec<f32>,
+        limit: u64,
+        tag: Option<&str>,
+    ) -> anyhow::Result<Vec<(f32, Memory)>> {
+        let collections = Self::collections();
+        let Some(memories) = collections.get(&self.key(user_id)) else {
+            return Ok(vec![]);
+        };
+
+        let mut scored = memories
+            .values()
+            .filter(|(memory, _)| tag.is_none_or(|tag| memory.tag.as_deref() == Some(tag)))
+            .map(|(memory, vector)| (cosine_similarity(&embedding, vector), memory.clone()))
+            .collect::<Vec<_>>();
+        scored.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+        scored.truncate(limit as usize);
+
+        Ok(scored)
+    }
+
+    async fn list(
+        &self,
+        user_id: UserId,
+        with_vectors: bool,
+    ) -> anyhow::Result<Vec<(Memory, Option<Vec<f32>>)>> {
+        Ok(Self::collections()
+            .get(&self.key(user_id))
+            .map(|memories| {
+                memories
+                    .values()
+                    .map(|(memory, vector)| (memory.clone(), with_vectors.then(|| vector.clone())))
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    async fn record_recall(&self, user_id: UserId, memory: &Memory) -> anyhow::Result<()> {
+        if let Some((stored, _)) = Self::collections()
+            .get_mut(&self.key(user_id))
+            .and_then(|memories| memories.get_mut(&memory.id))
+        {
+            stored.recall_count = memory.recall_count;
+            stored.last_recalled = memory.last_recalled;
+        }
+
+        Ok(())
+    }
+
+    async fn delete(&self, user_id: UserId, ids: Vec<u64>) -> anyhow::Result<()> {
+        if let Some(memories) = Self::collections().get_mut(&self.key(user_id)) {
+            memories.retain(|id, _| !ids.contains(id));
+        }
+
+        Ok(())
+    }
+
+    async fn replace(
+        &self,
+        user_id: UserId,
+        memories: Vec<(Memory, Vec<f32>)>,
+    ) -> anyhow::Result<()> {
+        if let Some((_, vector)) = memories
+            .iter()
+            .find(|(_, vector)| vector.len() as u64 != self.vector_size)
+        {
+            anyhow::bail!(
+                "expected a {}-dimensional vector, got {}",
+                self.vector_size,
+                vector.len()
+            );
+        }
+
+        // built aside and swapped in whole, so nobody sees a half replaced collection
+        let collection = memories
+            .into_iter()
+            .map(|(memory, vector)| (memory.id, (memory, vector)))
+            .collect();
+
+        Self::collections().insert(self.key(user_id), collection);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the collections are shared by the whole process, so every test has users of its own
+
+    #[test]
+    fn cosine_similarity_ignores_magnitude() {
+        assert_eq!(cosine_similarity(&[1.0, 0.0], &[3.0, 0.0]), 1.0);
+        assert_eq!(cosine_similarity(&[1.0, 0.0], &[0.0, 2.0]), 0.0);
+        assert_eq!(cosine_similarity(&[1.0, 0.0], &[-1.0, 0.0]), -1.0);
+        assert!((cosine_similarity(&[1.0, 1.0], &[1.0, 0.0]) - 0.5f32.sqrt()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn cosine_similarity_of_a_zero_vector_is_zero() {
+        assert_eq!(cosine_similarity(&[0.0, 0.0], &[1.0, 0.0]), 0.0);
+        assert_eq!(cosine_similarity(&[0.0, 0.0], &[0.0, 0.0]), 0.0);
+    }
+
+    #[tokio::test]
+    async fn searches_rank_the_most_similar_first() {
+        let backend = InMemoryBackend::new(2, None);
+        let user = UserId::new(1661);
+
+        backend
+            .store(
+                user,
+                vec![
+                    (Memory::new("away".into()), vec![-1.0, 0.0]),
+                    (Memory::new("close".into()), vec![1.0, 0.2]),
+                    (Memory::new("exact".into()), vec![2.0, 0.0]),
+                    (Memory::new("sideways".into()), vec![0.0, 1.0]),
+                ],
+            )
+            .await
+            .unwrap();
+
+        let found = backend.search(user, vec![1.0, 0.0], 3, None).await.unwrap();
+        let found = found
+            .iter()
+            .map(|(_, memory)| memory.content.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(found, ["exact", "close", "sideways"]);
+
+        // other users' memories stay out of it
+        assert!(
+            backend
+                .search(UserId::new(1662), vec![1.0, 0.0], 10, None)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn vectors_of_another_size_are_refused() {
+        let backend = InMemoryBackend::new(3, None);
+        let user = UserId::new(1663);
+
+        assert!(
+            backend
+                .store(user, vec![(Memory::new("short".into()), vec![1.0, 0.0])])
+                .await
+                .is_err()
+        );
+        assert_eq!(backend.count(user).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn recalls_and_deletes_are_kept() {
+        let backend = InMemoryBackend::new(1, None);
+        let user = UserId::new(1664);
+        let (mut kept, gone) = (Memory::new("kept".into()), Memory::new("gone".into()));
+
+        backend
+            .store(
+                user,
+                vec![(kept.clone(), vec![1.0]), (gone.clone(), vec![1.0])],
+            )
+            .await
+            .unwrap();
+
+        kept.recall_count = 3;
+        backend.record_recall(user, &kept).await.unwrap();
+        backend.delete(user, vec![gone.id]).await.unwrap();
+
+        let listed = backend.list(user, false).await.unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].0.recall_count, 3);
+        assert_eq!(listed[0].1, None);
+
+        // a backend of another size flags the stored vectors
+        assert!(backend.health_check(user).await.is_ok());
+        assert!(
+            InMemoryBackend::new(2, None)
+                .health_check(user)
+                .await
+                .is_err()
+        );
+
+        backend.replace(user, vec![]).await.unwrap();
+        assert_eq!(backend.count(user).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn searches_can_be_limited_to_a_tag() {
+        let backend = InMemoryBackend::new(2, None);
+        let user = UserId::new(1665);
+        backend
+            .store(
+                user,
+                vec![
+                    (
+                        Memory::new("trip to Rome".into()).with_tag(Some("travel".into())),
+                        vec![1.0, 0.0],
+                    ),
+                    (Memory::new("likes tea".into()), vec![1.0, 0.1]),
+                ],
+            )
+            .await
+            .unwrap();
+
+        let found = backend
+            .search(user, vec![1.0, 0.0], 10, Some("travel"))
+            .await
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].1.content, "trip to Rome");
+
+        let all = backend
+            .search(user, vec![1.0, 0.0], 10, None)
+            .await
+            .unwrap();
+        assert_eq!(all.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn namespaces_keep_memories_apart() {
+        let user = UserId::new(1666);
+        let shared = InMemoryBackend::new(1, None);
+        let botty = InMemoryBackend::new(1, Some("botty".to_string()));
+
+        botty
+            .store(user, vec![(Memory::new("told botty".into()), vec![1.0])])
+            .await
+            .unwrap();
+
+        assert_eq!(botty.count(user).await.unwrap(), 1);
+        assert_eq!(shared.count(user).await.unwrap(), 0);
+        assert_eq!(
+            InMemoryBackend::new(1, Some("botty".to_string()))
+                .count(user)
+                .await
+                .unwrap(),
+            1
+        );
+
+        shared.replace(user, vec![]).await.unwrap();
+        assert_eq!(botty.count(user).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn replacing_swaps_in_another_vector_size() {
+        let user = UserId::new(1667);
+        let old = Memory::new("old".into());
+        InMemoryBackend::new(1, None)
+            .store(user, vec![(old.clone(), vec![1.0])])
+            .await
+            .unwrap();
+
+        let migrated = InMemoryBackend::new(2, None);
+        // vectors of the wrong size are refused before anything is touched
+        assert!(
+            migrated
+                .replace(user, vec![(old.clone(), vec![1.0])])
+                .await
+                .is_err()
+        );
+        assert_eq!(
+            migrated.list(user, true).await.unwrap()[0].1,
+            Some(vec![1.0])
+        );
+
+        migrated
+            .replace(user, vec![(old.clone(), vec![0.0, 1.0])])
+            .await
+            .unwrap();
+        let listed = migrated.list(user, true).await.unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].1, Some(vec![0.0, 1.0]));
+        assert!(migrated.health_check(user).await.is_ok());
+    }
+}
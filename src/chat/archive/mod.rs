@@ -1,2 +1,6 @@
+pub mod backend;
+pub mod consolidation;
+pub mod in_memory;
+pub mod qdrant;
 /// memory archival module
 pub mod storage;
@@ -0,0 +1,445 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use chrono::{TimeZone, Utc};
+use qdrant_client::{
+    Payload, Qdrant,
+    qdrant::{
+        Condition, CountPointsBuilder, CreateAliasBuilder, CreateCollectionBuilder,
+        DeletePointsBuilder, Distance, Filter, PointStruct, PointsIdsList, ScrollPointsBuilder,
+        SearchPointsBuilder, SetPayloadPointsBuilder, UpsertPointsBuilder, Value,
+        VectorParamsBuilder, point_id::PointIdOptions, vectors_config::Config,
+        vectors_output::VectorsOptions,
+    },
+};
+use serenity::all::UserId;
+
+use crate::config::structure::LLMConfig;
+
+use super::{backend::MemoryBackend, storage::Memory};
+
+impl Memory {
+    pub fn into(self) -> Payload {
+        let mut payload = HashMap::from([
+            ("content".to_string(), Value::from(self.content)),
+            (
+                "date".to_string(),
+                Value::from(self.date.timestamp_millis()),
+            ),
+            (
+                "recall_count".to_string(),
+                Value::from(self.recall_count as i64),
+            ),
+        ]);
+        if let Some(last_recalled) = self.last_recalled {
+            payload.insert(
+                "last_recalled".to_string(),
+                Value::from(last_recalled.timestamp_millis()),
+            );
+        }
+        if let Some(tag) = self.tag {
+            payload.insert("tag".to_string(), Value::from(tag));
+        }
+
+        Payload::from(payload)
+    }
+    pub fn try_from(id: u64, payload: HashMap<String, Value>) -> Option<Self> {
+        Some(Self {
+            id,
+            content: payload.get("content")?.as_str()?.clone(),
+            tag: payload.get("tag").and_then(|tag| tag.as_str()).cloned(),
+            date: Utc
+                .timestamp_millis_opt(payload.get("date")?.as_integer()?)
+                .single()?,
+            // memories stored before recall tracking existed have neither field
+            recall_count: payload
+                .get("recall_count")
+                .and_then(|count| count.as_integer())
+                .unwrap_or(0) as u64,
+            last_recalled: payload
+                .get("last_recalled")
+                .and_then(|date| date.as_integer())
+                .and_then(|date| Utc.timestamp_millis_opt(date).single()),
+        })
+    }
+}
+
+/// Keeps every user's memories in their own Qdrant collection.
+pub struct QdrantBackend {
+    client: Qdrant,
+    vector_size: u64,
+    namespace: Option<String>,
+}
+
+impl QdrantBackend {
+    pub fn new(config: &LLMConfig, vector_size: u64, namespace: Option<String>) -> Self {
+        let client = Qdrant::from_url(&format!(
+            "http{}://{}:{}",
+            match config.qdrant_https.unwrap_or(false) {
+                true => "s",
+                false => "",
+            },
+            config.qdrant_host,
+            config.qdrant_port.unwrap_or(6334)
+        ))
+        .skip_compatibility_check()
+        .build()
+        .unwrap();
+
+        Self {
+            client,
+            vector_size,
+            namespace,
+        }
+    }
+
+    async fn collection_vector_size(&self, collection_name: &str) -> anyhow::Result<u64> {
+        let collection_info = self.client.collection_info(collection_name).await?;
+
+        async {
+            if let Config::Params(params) = collection_info
+                .result?
+                .config?
+                .params?
+                .vectors_config?
+                .config?
+            {
+                Some(params.size)
+            } else {
+                None
+            }
+        }
+        .await
+        .ok_or(anyhow::anyhow!("failed to get vector size"))
+    }
+
+    fn collection_name(&self, user_id: UserId) -> String {
+        match &self.namespace {
+            Some(namespace) => format!("chatbot_{namespace}_{user_id}"),
+            None => format!("chatbot_{}", user_id),
+        }
+    }
+
+    async fn try_create_collection(&self, user_id: UserId) -> anyhow::Result<String> {
+        let collection_name = self.collection_name(user_id);
+
+        Ok(
+            match self.client.collection_exists(&collection_name).await? {
+                true => collection_name,
+                false => {
+                    self.client
+                        .create_collection(
+                            CreateCollectionBuilder::new(&collection_name).vectors_config(
+                                VectorParamsBuilder::new(self.vector_size, Distance::Cosine),
+                            ),
+                        )
+                        .await?;
+                    collection_name
+                }
+            },
+        )
+    }
+
+    /// The collection actually holding the user's memories. After a migration that's a new one,
+    /// reachable under the usual name through an alias.
+    async fn backing_collection(&self, user_id: UserId) -> anyhow::Result<Option<String>> {
+        let collection_name = self.collection_name(user_id);
+
+        let aliased = self
+            .client
+            .list_aliases()
+            .await?
+            .aliases
+            .into_iter()
+            .find(|alias| alias.alias_name == collection_name)
+            .map(|alias| alias.collection_name);
+        if aliased.is_some() {
+            return Ok(aliased);
+        }
+
+        Ok(self
+            .client
+            .collection_exists(&collection_name)
+            .await?
+            .then_some(collection_name))
+    }
+
+    fn point_id(id: Option<qdrant_client::qdrant::PointId>) -> Option<u64> {
+        match id?.point_id_options? {
+            PointIdOptions::Num(id) => Some(id),
+            _ => None,
+        }
+    }
+}
+
+#[async_trait]
+impl MemoryBackend for QdrantBackend {
+    async fn health_check(&self, user_id: UserId) -> anyhow::Result<()> {
+        self.client.health_check().await?;
+
+        let collection_name = self.try_create_collection(user_id).await?;
+        let vector_size = self.collection_vector_size(&collection_name).await?;
+
+        if vector_size != self.vector_size {
+            Err(anyhow::anyhow!(
+                "vector size mismatch on {collection_name}, the collection holds {vector_size}-dimensional vectors but the embedding model produces {}, run /migrate to re-embed the stored memories with the current model",
+                self.vector_size,
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn count(&self, user_id: UserId) -> anyhow::Result<u64> {
+        let collection_name = self.try_create_collection(user_id).await?;
+
+        Ok(self
+            .client
+            .count(CountPointsBuilder::new(collection_name).exact(true))
+            .await?
+            .result
+            .map(|result| result.count)
+            .unwrap_or(0))
+    }
+
+    async fn store(
+        &self,
+        user_id: UserId,
+        memories: Vec<(Memory, Vec<f32>)>,
+    ) -> anyhow::Result<()> {
+        let collection_name = self.try_create_collection(user_id).await?;
+
+        if memories.is_empty() {
+            return Ok(());
+        }
+
+        let points = memories
+            .into_iter()
+            .map(|(memory, embedding)| PointStruct::new(memory.id, embedding, memory.into()))
+            .collect::<Vec<_>>();
+
+        self.client
+            .upsert_points(UpsertPointsBuilder::new(collection_name, points))
+            .await?;
+
+        Ok(())
+    }
+
+    async fn search(
+        &self,
+        user_id: UserId,
+        embedding: Vec<f32>,
+        limit: u64,
+        tag: Option<&str>,
+    ) -> anyhow::Result<Vec<(f32, Memory)>> {
+        let collection_name = self.try_create_collection(user_id).await?;
+
+        let mut builder =
+            SearchPointsBuilder::new(collection_name, embedding, limit).with_payload(true); // .params(SearchParamsBuilder::default().exact(true)),
+        if let Some(tag) = tag {
+            builder = builder.filter(Filter::must([Condition::matches("tag", tag.to_string())]));
+        }
+
+        let search_result = self.client.search_points(builder).await?;
+
+        Ok(search_result
+            .result
+            .into_iter()
+            .filter_map(|point| {
+                let id = Self::point_id(point.id)?;
+                Some((point.score, Memory::try_from(id, point.payload)?))
+            })
+            .collect())
+    }
+
+    async fn list(
+        &self,
+        user_id: UserId,
+        with_vectors: bool,
+    ) -> anyhow::Result<Vec<(Memory, Option<Vec<f32>>)>> {
+        let collection_name = self.collection_name(user_id);
+
+        if !self.client.collection_exists(&collection_name).await? {
+            return Ok(vec![]);
+        }
+
+        let mut memories = Vec::new();
+        let mut offset = None;
+
+        loop {
+            let mut builder = ScrollPointsBuilder::new(&collection_name)
+                .with_payload(true)
+                .with_vectors(with_vectors)
+                .limit(256);
+            if let Some(offset) = offset.take() {
+                builder = builder.offset(offset);
+            }
+
+            let scroll_result = self.client.scroll(builder).await?;
+
+            memories.extend(scroll_result.result.into_iter().filter_map(|point| {
+                let id = Self::point_id(point.id)?;
+
+                let vector = point
+                    .vectors
+                    .and_then(|vectors| vectors.vectors_options)
+                    .and_then(|options| match options {
+                        #[allow(deprecated)]
+                        VectorsOptions::Vector(vector) => Some(vector.data),
+                        VectorsOptions::Vectors(_) => None,
+                    });
+
+                Some((Memory::try_from(id, point.payload)?, vector))
+            }));
+
+            match scroll_result.next_page_offset {
+                Some(next) => offset = Some(next),
+                None => break,
+            }
+        }
+
+        Ok(memories)
+    }
+
+    async fn record_recall(&self, user_id: UserId, memory: &Memory) -> anyhow::Result<()> {
+        let mut payload = HashMap::from([(
+            "recall_count".to_string(),
+            Value::from(memory.recall_count as i64),
+        )]);
+        if let Some(last_recalled) = memory.last_recalled {
+            payload.insert(
+                "last_recalled".to_string(),
+                Value::from(last_recalled.timestamp_millis()),
+            );
+        }
+
+        self.client
+            .set_payload(
+                SetPayloadPointsBuilder::new(self.collection_name(user_id), Payload::from(payload))
+                    .points_selector(PointsIdsList::from(vec![memory.id])),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, user_id: UserId, ids: Vec<u64>) -> anyhow::Result<()> {
+        let collection_name = self.collection_name(user_id);
+
+        if !self.client.collection_exists(&collection_name).await? {
+            return Ok(());
+        }
+
+        self.client
+            .delete_points(
+                DeletePointsBuilder::new(collection_name)
+                    .points(PointsIdsList::from(ids))
+                    .wait(true),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn replace(
+        &self,
+        user_id: UserId,
+        memories: Vec<(Memory, Vec<f32>)>,
+    ) -> anyhow::Result<()> {
+        let collection_name = self.collection_name(user_id);
+        let staging = format!("{collection_name}_{}", Utc::now().timestamp_millis());
+
+        self.client
+            .create_collection(
+                CreateCollectionBuilder::new(&staging)
+                    .vectors_config(VectorParamsBuilder::new(self.vector_size, Distance::Cosine)),
+            )
+            .await?;
+
+        if !memories.is_empty() {
+            let points = memories
+                .into_iter()
+                .map(|(memory, embedding)| PointStruct::new(memory.id, embedding, memory.into()))
+                .collect::<Vec<_>>();
+
+            if let Err(why) = self
+                .client
+                .upsert_points(UpsertPointsBuilder::new(&staging, points).wait(true))
+                .await
+            {
+                if let Err(why) = self.client.delete_collection(&staging).await {
+                    log::warn!("failed to clean up {staging}: {why}");
+                }
+                return Err(why.into());
+            }
+        }
+
+        // an alias can't share its name with a collection, so the original has to go first
+        let previous = self.backing_collection(user_id).await?;
+        match previous.as_deref() {
+            Some(previous) if previous == collection_name => {
+                self.client.delete_collection(&collection_name).await?;
+            }
+            Some(_) => {
+                self.client.delete_alias(collection_name.as_str()).await?;
+            }
+            None => {}
+        }
+
+        self.client
+            .create_alias(CreateAliasBuilder::new(&staging, &collection_name))
+            .await
+            .map_err(|why| {
+                anyhow::anyhow!("failed to point {collection_name} at {staging}, the migrated memories are kept there: {why}")
+            })?;
+
+        let replaced = match previous.filter(|previous| *previous != collection_name) {
+            Some(previous) => Some((self.client.delete_collection(&previous).await, previous)),
+            None => None,
+        };
+        if let Some((Err(why), previous)) = replaced {
+            log::warn!("failed to delete the replaced collection {previous}: {why}");
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn payloads_round_trip_the_content_as_is() {
+        let memory = Memory::new("likes \"quoted\" things".to_string());
+
+        let payload = HashMap::from(memory.clone().into());
+        let restored = Memory::try_from(memory.id, payload).unwrap();
+
+        // re-embedding reads the content back, it can't come out json-quoted
+        assert_eq!(restored.content, memory.content);
+        assert_eq!(
+            restored.date.timestamp_millis(),
+            memory.date.timestamp_millis()
+        );
+    }
+
+    #[test]
+    fn payloads_without_content_are_skipped() {
+        let payload = HashMap::from([("date".to_string(), Value::from(0))]);
+
+        assert!(Memory::try_from(1, payload).is_none());
+    }
+
+    #[test]
+    fn memories_from_before_recall_tracking_were_never_recalled() {
+        let payload = HashMap::from([
+            ("content".to_string(), Value::from("old")),
+            ("date".to_string(), Value::from(0)),
+        ]);
+        let memory = Memory::try_from(1, payload).unwrap();
+
+        assert_eq!(memory.recall_count, 0);
+        assert_eq!(memory.last_used(), memory.date);
+    }
+}
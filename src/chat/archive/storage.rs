@@ -1,145 +1,141 @@
-use std::collections::HashMap;
-
-use chrono::{DateTime, TimeZone, Utc};
-use qdrant_client::{
-    Payload, Qdrant,
-    qdrant::{
-        Condition, CreateCollectionBuilder, Distance, FieldCondition, Filter, PointStruct, Range,
-        ScrollPointsBuilder, SearchPointsBuilder, UpsertPointsBuilder, Value, VectorParamsBuilder,
-        condition::ConditionOneOf, point_id::PointIdOptions, vectors_config::Config,
-    },
-};
+use std::{cmp::Reverse, sync::Arc};
+
+use chrono::{DateTime, Utc};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 use serenity::all::UserId;
 
-use crate::config::structure::LLMConfig;
+use crate::{
+    config::structure::{LLMConfig, MemoryBackendKind},
+    utils,
+};
+
+use super::{backend::MemoryBackend, in_memory::InMemoryBackend, qdrant::QdrantBackend};
 
 #[derive(Debug, Clone, Serialize, Deserialize, Hash, Eq, PartialEq)]
 pub struct Memory {
     pub id: u64,
     pub content: String,
-    // pub topic: String,
+    /// Files the memory under a topic or session, recall can be limited to one tag.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
     pub date: DateTime<Utc>,
+    pub recall_count: u64,
+    pub last_recalled: Option<DateTime<Utc>>,
 }
 impl Memory {
     pub fn new(content: String) -> Self {
         Self {
-            id: rand::random(),
+            id: utils::random::random(),
             content,
+            tag: None,
             date: Utc::now(),
+            recall_count: 0,
+            last_recalled: None,
         }
     }
-    pub fn into(self) -> Payload {
-        Payload::from(HashMap::from([
-            ("content".to_string(), Value::from(self.content)),
-            // ("topic".to_string(), Value::from(self.topic)),
-            (
-                "date".to_string(),
-                Value::from(self.date.timestamp_millis()),
-            ),
-        ]))
-    }
-    pub fn try_from(id: u64, payload: HashMap<String, Value>) -> Option<Self> {
-        Some(Self {
-            id,
-            content: payload.get("content")?.to_string(),
-            // topic: payload.get("topic")?.to_string(),
-            date: Utc
-                .timestamp_millis_opt(payload.get("date")?.as_integer()?)
-                .single()?,
-        })
+
+    pub fn with_tag(mut self, tag: Option<String>) -> Self {
+        self.tag = tag;
+        self
+    }
+
+    /// The last time this memory was either stored or recalled.
+    pub fn last_used(&self) -> DateTime<Utc> {
+        self.last_recalled.unwrap_or(self.date)
     }
 }
 
+/// Swaps the names in a memory for the `<user>` and `<assistant>` placeholders. Aliases only
+/// count as whole words, nicknames tend to be short enough to show up inside other words.
+pub fn with_placeholders(
+    text: &str,
+    user_name: &str,
+    assistant_name: &str,
+    assistant_aliases: &[String],
+) -> String {
+    let mut text = text
+        .replace(user_name, "<user>")
+        .replace(assistant_name, "<assistant>");
+
+    for alias in assistant_aliases.iter().filter(|alias| !alias.is_empty()) {
+        if let Ok(regex) = Regex::new(&format!(r"\b{}\b", regex::escape(alias))) {
+            text = regex.replace_all(&text, "<assistant>").to_string();
+        }
+    }
+
+    text
+}
+
+/// A user's memories in a portable form, for backups and moving between vector backends.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MemoryExport {
+    /// The model the vectors came from, they're only reused when importing into the same one.
+    pub embedding_model: String,
+    pub memories: Vec<ExportedMemory>,
+}
+
+impl MemoryExport {
+    /// Whether the vectors can't be used as they are with `embedding_model`.
+    pub fn needs_reembedding(&self, embedding_model: &str, vector_size: usize) -> bool {
+        self.embedding_model != embedding_model
+            || self
+                .memories
+                .iter()
+                .any(|exported| exported.vector.len() != vector_size)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportedMemory {
+    #[serde(flatten)]
+    pub memory: Memory,
+    pub vector: Vec<f32>,
+}
+
 pub struct MemorySettings {
-    pub vector_size: u64,
     pub similarity_threshold: f32,
+    pub max_memories: Option<u64>,
+    pub candidates: Option<u64>,
+    pub recall_boost: Option<f32>,
+    pub recency_weight: Option<f32>,
+    pub recency_half_life_days: f32,
 }
 
 pub struct MemoryStorage {
-    client: Qdrant,
+    backend: Arc<dyn MemoryBackend>,
     settings: MemorySettings,
 }
 
 impl MemoryStorage {
-    pub fn new(config: &LLMConfig, vector_size: u64) -> Self {
-        let client = Qdrant::from_url(&format!(
-            "http{}://{}:{}",
-            match config.qdrant_https.unwrap_or(false) {
-                true => "s",
-                false => "",
-            },
-            config.qdrant_host,
-            config.qdrant_port.unwrap_or(6334)
-        ))
-        .skip_compatibility_check()
-        .build()
-        .unwrap();
+    /// Memories of different namespaces never mix, `None` is the one shared by every persona.
+    pub fn new(config: &LLMConfig, vector_size: u64, namespace: Option<String>) -> Self {
+        let backend: Arc<dyn MemoryBackend> = match config.memory_backend.unwrap_or_default() {
+            MemoryBackendKind::Qdrant => {
+                Arc::new(QdrantBackend::new(config, vector_size, namespace))
+            }
+            MemoryBackendKind::InMemory => Arc::new(InMemoryBackend::new(vector_size, namespace)),
+        };
 
         MemoryStorage {
-            client,
+            backend,
             settings: MemorySettings {
-                vector_size,
                 similarity_threshold: config.similarity_threshold.unwrap_or(0.5),
+                max_memories: config.max_memories_per_user,
+                candidates: config.rag_candidates,
+                recall_boost: config.recall_boost,
+                recency_weight: config.recency_weight,
+                recency_half_life_days: config.recency_half_life_days.unwrap_or(30.0),
             },
         }
     }
 
     pub async fn health_check(&self, user_id: UserId) -> anyhow::Result<()> {
-        self.client.health_check().await?;
-
-        let collection_name = self.try_create_collection(user_id).await?;
-
-        let collection_info = self.client.collection_info(collection_name).await?;
-
-        let vector_size: u64 = async {
-            if let Config::Params(params) = collection_info
-                .result?
-                .config?
-                .params?
-                .vectors_config?
-                .config?
-            {
-                Some(params.size)
-            } else {
-                None
-            }
-        }
-        .await
-        .ok_or(anyhow::anyhow!("failed to get vector size"))?;
-
-        if vector_size != self.settings.vector_size {
-            Err(anyhow::anyhow!(
-                "vector size mismatch, expected {} but got {}",
-                self.settings.vector_size,
-                vector_size
-            ))
-        } else {
-            Ok(())
-        }
+        self.backend.health_check(user_id).await
     }
 
-    async fn try_create_collection(&self, user_id: UserId) -> anyhow::Result<String> {
-        let collection_name = format!("chatbot_{}", user_id);
-
-        Ok(
-            match self.client.collection_exists(&collection_name).await? {
-                true => collection_name,
-                false => {
-                    self.client
-                        .create_collection(
-                            CreateCollectionBuilder::new(&collection_name).vectors_config(
-                                VectorParamsBuilder::new(
-                                    self.settings.vector_size,
-                                    Distance::Cosine,
-                                ),
-                            ),
-                        )
-                        .await?;
-                    collection_name
-                }
-            },
-        )
+    pub async fn count(&self, user_id: UserId) -> anyhow::Result<u64> {
+        self.backend.count(user_id).await
     }
 
     pub async fn store(
@@ -148,14 +144,31 @@ impl MemoryStorage {
         embedding: Vec<f32>,
         user_id: UserId,
     ) -> anyhow::Result<()> {
-        let collection_name = self.try_create_collection(user_id).await?;
+        if let Some(max_memories) = self.settings.max_memories {
+            self.evict(user_id, max_memories).await?;
+        }
 
-        let points = vec![PointStruct::new(memory.id, embedding, memory.into())];
-        self.client
-            .upsert_points(UpsertPointsBuilder::new(collection_name, points))
-            .await?;
+        self.backend.store(user_id, vec![(memory, embedding)]).await
+    }
+
+    /// Makes room for one more memory under the cap, evicting the least valuable ones first:
+    /// the least recalled, and among those the ones that went unused for the longest.
+    async fn evict(&self, user_id: UserId, max_memories: u64) -> anyhow::Result<()> {
+        let count = self.backend.count(user_id).await?;
+
+        if count < max_memories {
+            return Ok(());
+        }
 
-        Ok(())
+        let to_evict = (count + 1 - max_memories) as usize;
+        let ids = least_valuable(self.all(user_id).await?, to_evict);
+
+        log::info!(
+            "memory cap of {max_memories} reached for {user_id}, evicting {} memories",
+            ids.len()
+        );
+
+        self.backend.delete(user_id, ids).await
     }
 
     pub async fn search(
@@ -164,7 +177,25 @@ impl MemoryStorage {
         user_id: UserId,
         limit: u64,
         threshold: Option<f32>,
+        tag: Option<&str>,
     ) -> anyhow::Result<Vec<Memory>> {
+        Ok(self
+            .search_scored(embedding, user_id, limit, threshold, tag)
+            .await?
+            .into_iter()
+            .map(|(_, memory)| memory)
+            .collect())
+    }
+
+    /// Same as [MemoryStorage::search], but along with each memory's similarity score.
+    pub async fn search_scored(
+        &self,
+        embedding: Vec<impl Into<f32>>,
+        user_id: UserId,
+        limit: u64,
+        threshold: Option<f32>,
+        tag: Option<&str>,
+    ) -> anyhow::Result<Vec<(f32, Memory)>> {
         let threshold = threshold.unwrap_or(self.settings.similarity_threshold);
 
         let embedding = embedding
@@ -172,41 +203,64 @@ impl MemoryStorage {
             .map(|x| x.into())
             .collect::<Vec<f32>>();
 
-        let collection_name = self.try_create_collection(user_id).await?;
-
-        let search_result = self
-            .client
-            .search_points(
-                SearchPointsBuilder::new(collection_name, embedding, limit)
-                    // .filter(Filter::all([Condition::matches("bar", 12)]))
-                    .with_payload(true), // .params(SearchParamsBuilder::default().exact(true)),
-            )
-            .await?;
-
-        Ok(search_result
-            .result
+        let scored = self
+            .backend
+            .search(user_id, embedding, fetch_limit(&self.settings, limit), tag)
+            .await?
             .into_iter()
             .enumerate()
-            .filter_map(|(i, point)| {
-                let id = if let PointIdOptions::Num(id) = point.id?.point_id_options? {
-                    id
-                } else {
-                    return None;
-                };
-
-                if point.score > threshold {
-                    log::debug!(
-                        "payload #{i}:\n{}\nscore: {}",
-                        serde_json::to_string_pretty(&point.payload).ok()?,
-                        point.score
-                    );
+            .filter_map(|(i, (score, memory))| {
+                if score > threshold {
+                    log::debug!("memory #{i} scored {score}");
+                    log::trace!("memory #{i}:\n{}", memory.content);
 
-                    Some(Memory::try_from(id, point.payload)?)
+                    Some((score, memory))
                 } else {
                     None
                 }
             })
-            .collect())
+            .collect::<Vec<_>>();
+
+        Ok(rank(scored, &self.settings, limit as usize))
+    }
+
+    /// Score a memory has to beat to get recalled.
+    pub fn similarity_threshold(&self) -> f32 {
+        self.settings.similarity_threshold
+    }
+
+    /// Same as [MemoryStorage::search], but counts the returned memories as recalled.
+    pub async fn recall(
+        &self,
+        embedding: Vec<impl Into<f32>>,
+        user_id: UserId,
+        limit: u64,
+        threshold: Option<f32>,
+        tag: Option<&str>,
+    ) -> anyhow::Result<Vec<Memory>> {
+        let mut memories = self
+            .search(embedding, user_id, limit, threshold, tag)
+            .await?;
+
+        let now = Utc::now();
+
+        for memory in memories.iter_mut() {
+            memory.recall_count += 1;
+            memory.last_recalled = Some(now);
+        }
+
+        // the counts only matter for ranking and eviction later, no need to wait on them
+        let backend = self.backend.clone();
+        let recalled = memories.clone();
+        tokio::spawn(async move {
+            for memory in recalled {
+                if let Err(why) = backend.record_recall(user_id, &memory).await {
+                    log::warn!("failed to record recall of memory {}: {why:?}", memory.id);
+                }
+            }
+        });
+
+        Ok(memories)
     }
 
     #[allow(unused)]
@@ -216,51 +270,351 @@ impl MemoryStorage {
         limit: u32,
         range: Option<chrono::Duration>,
     ) -> anyhow::Result<Vec<Memory>> {
-        let collection_name = self.try_create_collection(user_id).await?;
-
         let range = range.unwrap_or_else(|| chrono::Duration::days(1));
-        let lower_bound_ts = (Utc::now() - range).timestamp_millis();
-
-        // Build a filter: only return points whose "date" field is >= lower_bound_ts.
-        let filter = Filter {
-            must: vec![Condition {
-                condition_one_of: Some(ConditionOneOf::Field(FieldCondition {
-                    key: "date".to_string(),
-                    range: Some(Range {
-                        gt: None,
-                        gte: Some(lower_bound_ts as f64),
-                        lt: None,
-                        lte: None,
-                    }),
-                    ..Default::default()
-                })),
-            }],
-            ..Default::default()
-        };
+        let lower_bound = Utc::now() - range;
 
-        // Use scroll_points to get points matching the filter.
-        let scroll_result = self
-            .client
-            .scroll(
-                ScrollPointsBuilder::new(collection_name)
-                    .with_payload(true)
-                    .filter(filter)
-                    .limit(limit),
-            )
-            .await?;
+        let mut memories = self.all(user_id).await?;
+        memories.retain(|memory| memory.date >= lower_bound);
+        memories.sort_by_key(|memory| Reverse(memory.date));
+        memories.truncate(limit as usize);
+
+        Ok(memories)
+    }
 
-        Ok(scroll_result
-            .result
+    /// Returns every memory stored for the user.
+    pub async fn all(&self, user_id: UserId) -> anyhow::Result<Vec<Memory>> {
+        Ok(self
+            .backend
+            .list(user_id, false)
+            .await?
             .into_iter()
-            .filter_map(|point| {
-                let id = if let PointIdOptions::Num(id) = point.id?.point_id_options? {
-                    id
-                } else {
-                    return None;
-                };
+            .map(|(memory, _)| memory)
+            .collect())
+    }
 
-                Some(Memory::try_from(id, point.payload)?)
-            })
+    /// Same as [MemoryStorage::all], but along with each memory's embedding.
+    pub async fn all_with_vectors(
+        &self,
+        user_id: UserId,
+    ) -> anyhow::Result<Vec<(Memory, Vec<f32>)>> {
+        Ok(self
+            .backend
+            .list(user_id, true)
+            .await?
+            .into_iter()
+            .filter_map(|(memory, vector)| Some((memory, vector?)))
             .collect())
     }
+
+    /// Returns the user's most recently stored memories, newest first.
+    pub async fn list(
+        &self,
+        user_id: UserId,
+        limit: usize,
+        tag: Option<&str>,
+    ) -> anyhow::Result<Vec<Memory>> {
+        let mut memories = self.all(user_id).await?;
+        if let Some(tag) = tag {
+            memories.retain(|memory| memory.tag.as_deref() == Some(tag));
+        }
+        memories.sort_by_key(|memory| Reverse(memory.date));
+        memories.truncate(limit);
+
+        Ok(memories)
+    }
+
+    /// Deletes the memories with the given ids.
+    pub async fn delete(&self, user_id: UserId, ids: Vec<u64>) -> anyhow::Result<()> {
+        self.backend.delete(user_id, ids).await
+    }
+
+    /// Swaps all of the user's memories for the given (already re-embedded) ones, with the
+    /// current vector size. The old ones stay in place if that fails.
+    pub async fn replace_all(
+        &self,
+        user_id: UserId,
+        memories: Vec<(Memory, Vec<f32>)>,
+    ) -> anyhow::Result<()> {
+        self.backend.replace(user_id, memories).await
+    }
+
+    /// Inserts memories with their embeddings as they are, overwriting the ones with the same id.
+    pub async fn upsert(
+        &self,
+        user_id: UserId,
+        memories: Vec<(Memory, Vec<f32>)>,
+    ) -> anyhow::Result<()> {
+        self.backend.store(user_id, memories).await
+    }
+}
+
+/// How many memories to search for to end up with `limit` of them. Over-fetches when
+/// reranking, so boosted memories can climb into the limit.
+fn fetch_limit(settings: &MemorySettings, limit: u64) -> u64 {
+    match (
+        settings.candidates,
+        settings.recall_boost,
+        settings.recency_weight,
+    ) {
+        (Some(candidates), _, _) => candidates.max(limit),
+        (None, None, None) => limit,
+        _ => limit * 2,
+    }
+}
+
+/// The `limit` best of the scored search results, boosting the frequently recalled and recent ones.
+/// The similarity scores are kept as they are.
+fn rank(
+    mut scored: Vec<(f32, Memory)>,
+    settings: &MemorySettings,
+    limit: usize,
+) -> Vec<(f32, Memory)> {
+    if settings.recall_boost.is_some() || settings.recency_weight.is_some() {
+        let now = Utc::now();
+        scored.sort_by(|(a_score, a), (b_score, b)| {
+            ranked(settings, *b_score, b, now).total_cmp(&ranked(settings, *a_score, a, now))
+        });
+    }
+
+    scored.truncate(limit);
+
+    scored
+}
+
+/// Similarity score adjusted by how often the memory was recalled and how old it is.
+fn ranked(settings: &MemorySettings, score: f32, memory: &Memory, now: DateTime<Utc>) -> f32 {
+    let mut ranked = score;
+
+    // logarithmic, so a popular memory can't drown out a far more relevant one
+    if let Some(boost) = settings.recall_boost {
+        ranked += boost * (memory.recall_count as f32).ln_1p();
+    }
+
+    // exponential decay, a memory as old as the half-life gets half the bonus of a new one
+    if let Some(weight) = settings.recency_weight {
+        let age_days = (now - memory.date).num_seconds().max(0) as f32 / 86400.0;
+        ranked += weight * 0.5f32.powf(age_days / settings.recency_half_life_days);
+    }
+
+    ranked
+}
+
+/// Ids of the `count` least valuable memories: the least recalled, and among those the ones
+/// that went unused for the longest.
+fn least_valuable(mut memories: Vec<Memory>, count: usize) -> Vec<u64> {
+    memories.sort_by_key(|memory| (memory.recall_count, memory.last_used()));
+
+    memories
+        .into_iter()
+        .take(count)
+        .map(|memory| memory.id)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_become_placeholders() {
+        assert_eq!(
+            with_placeholders("Alice asked Botty about Bob", "Alice", "Botty", &[]),
+            "<user> asked <assistant> about Bob"
+        );
+    }
+
+    #[test]
+    fn aliases_only_count_as_whole_words() {
+        let aliases = ["Bo".to_string()];
+
+        assert_eq!(
+            with_placeholders("Bo said hi to Bob", "Alice", "Botty", &aliases),
+            "<assistant> said hi to Bob"
+        );
+        assert_eq!(
+            with_placeholders("Alice likes bonsai", "Alice", "Botty", &aliases),
+            "<user> likes bonsai"
+        );
+    }
+
+    #[test]
+    fn empty_aliases_are_skipped() {
+        let aliases = [String::new(), "Bot".to_string()];
+
+        assert_eq!(
+            with_placeholders("Bot and Alice", "Alice", "Botty", &aliases),
+            "<assistant> and <user>"
+        );
+    }
+
+    #[test]
+    fn exports_only_keep_vectors_of_the_same_model() {
+        let export = MemoryExport {
+            embedding_model: "small".to_string(),
+            memories: vec![ExportedMemory {
+                memory: Memory::new("likes tea".to_string()),
+                vector: vec![0.5, 0.5],
+            }],
+        };
+
+        assert!(!export.needs_reembedding("small", 2));
+        assert!(export.needs_reembedding("large", 2));
+        // same name, but a differently configured model
+        assert!(export.needs_reembedding("small", 3));
+
+        // the memory's fields sit next to its vector
+        let json = serde_json::to_value(&export).unwrap();
+        assert_eq!(json["memories"][0]["content"], "likes tea");
+        let restored: MemoryExport = serde_json::from_value(json).unwrap();
+        assert_eq!(restored.memories[0].memory, export.memories[0].memory);
+        assert_eq!(restored.memories[0].vector, [0.5, 0.5]);
+    }
+
+    fn memory(id: u64, recall_count: u64, days_unused: i64) -> Memory {
+        Memory {
+            id,
+            content: id.to_string(),
+            tag: None,
+            date: Utc::now() - chrono::Duration::days(100),
+            recall_count,
+            last_recalled: Some(Utc::now() - chrono::Duration::days(days_unused)),
+        }
+    }
+
+    #[test]
+    fn the_least_recalled_memories_are_evicted_first() {
+        let memories = vec![memory(1, 5, 90), memory(2, 0, 1), memory(3, 2, 1)];
+
+        assert_eq!(least_valuable(memories, 2), [2, 3]);
+    }
+
+    #[test]
+    fn ties_evict_the_longest_unused_first() {
+        let mut never_recalled = memory(3, 0, 0);
+        never_recalled.last_recalled = None;
+        let memories = vec![memory(1, 1, 2), memory(2, 1, 30), never_recalled];
+
+        assert_eq!(least_valuable(memories, 3), [3, 2, 1]);
+    }
+
+    fn settings(recall_boost: Option<f32>, recency_weight: Option<f32>) -> MemorySettings {
+        MemorySettings {
+            similarity_threshold: 0.5,
+            max_memories: None,
+            candidates: None,
+            recall_boost,
+            recency_weight,
+            recency_half_life_days: 30.0,
+        }
+    }
+
+    #[test]
+    fn candidates_are_fetched_for_reranking() {
+        assert_eq!(fetch_limit(&settings(None, None), 5), 5);
+        assert_eq!(fetch_limit(&settings(Some(0.1), None), 5), 10);
+
+        let pool = MemorySettings {
+            candidates: Some(20),
+            ..settings(None, None)
+        };
+        assert_eq!(fetch_limit(&pool, 5), 20);
+        // never fewer than asked for
+        assert_eq!(fetch_limit(&pool, 30), 30);
+    }
+
+    fn ids(memories: Vec<(f32, Memory)>) -> Vec<u64> {
+        memories.into_iter().map(|(_, memory)| memory.id).collect()
+    }
+
+    #[test]
+    fn without_a_boost_the_search_order_is_kept() {
+        let scored = vec![(0.9, memory(1, 0, 0)), (0.8, memory(2, 50, 0))];
+
+        assert_eq!(ids(rank(scored, &settings(None, None), 1)), [1]);
+    }
+
+    #[test]
+    fn frequently_recalled_memories_climb_into_the_limit() {
+        let scored = vec![
+            (0.9, memory(1, 0, 0)),
+            (0.85, memory(2, 10, 0)),
+            (0.8, memory(3, 0, 0)),
+        ];
+
+        assert_eq!(ids(rank(scored, &settings(Some(0.1), None), 2)), [2, 1]);
+    }
+
+    #[test]
+    fn the_boost_cant_drown_out_relevance() {
+        let scored = vec![(0.9, memory(1, 0, 0)), (0.3, memory(2, 1000, 0))];
+
+        assert_eq!(ids(rank(scored, &settings(Some(0.05), None), 1)), [1]);
+    }
+
+    #[test]
+    fn recent_memories_win_close_calls() {
+        let mut recent = memory(2, 0, 0);
+        recent.date = Utc::now();
+        let scored = vec![(0.8, memory(1, 0, 0)), (0.75, recent)];
+
+        assert_eq!(ids(rank(scored.clone(), &settings(None, None), 1)), [1]);
+        assert_eq!(ids(rank(scored, &settings(None, Some(0.1)), 1)), [2]);
+    }
+
+    #[test]
+    fn recency_halves_every_half_life() {
+        let settings = settings(None, Some(1.0));
+        let now = Utc::now();
+        let mut memory = memory(1, 0, 0);
+
+        memory.date = now;
+        assert_eq!(ranked(&settings, 0.5, &memory, now), 1.5);
+        memory.date = now - chrono::Duration::days(30);
+        assert_eq!(ranked(&settings, 0.5, &memory, now), 1.0);
+    }
+
+    #[test]
+    fn untagged_memories_are_stored_without_a_tag() {
+        let untagged = serde_json::to_value(Memory::new("likes tea".to_string())).unwrap();
+        assert!(untagged.get("tag").is_none());
+
+        // memories stored before tags existed still load
+        let restored: Memory = serde_json::from_value(untagged).unwrap();
+        assert_eq!(restored.tag, None);
+
+        let tagged = Memory::new("trip to Rome".to_string()).with_tag(Some("travel".to_string()));
+        assert_eq!(serde_json::to_value(tagged).unwrap()["tag"], "travel");
+    }
+
+    #[tokio::test]
+    async fn recalls_are_counted_in_the_background() {
+        let config = LLMConfig {
+            memory_backend: Some(MemoryBackendKind::InMemory),
+            ..Default::default()
+        };
+        let storage = MemoryStorage::new(&config, 2, None);
+        let user = UserId::new(1668);
+        storage
+            .store(Memory::new("likes tea".to_string()), vec![1.0, 0.0], user)
+            .await
+            .unwrap();
+
+        let recalled = storage
+            .recall(vec![1.0f32, 0.0], user, 5, Some(0.0), None)
+            .await
+            .unwrap();
+        assert_eq!(recalled[0].recall_count, 1);
+        assert!(recalled[0].last_recalled.is_some());
+
+        // handed back right away, saved once the background task got to it
+        let mut stored = 0;
+        for _ in 0..50 {
+            stored = storage.all(user).await.unwrap()[0].recall_count;
+            if stored == 1 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(stored, 1);
+    }
 }
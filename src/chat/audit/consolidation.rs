@@ -0,0 +1,48 @@
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serenity::all::UserId;
+use tokio::io::AsyncWriteExt;
+
+use crate::chat::archive::storage::Memory;
+
+#[derive(Serialize)]
+struct ConsolidationRecord<'a> {
+    timestamp: DateTime<Utc>,
+    user_id: u64,
+    merged: &'a [Memory],
+    into: &'a Memory,
+}
+
+/// Appends a JSON line recording which memories were merged into which, so a
+/// consolidation can be traced (and undone by hand) later on.
+pub async fn record(
+    path: &Path,
+    user_id: UserId,
+    merged: &[Memory],
+    into: &Memory,
+) -> anyhow::Result<()> {
+    let record = ConsolidationRecord {
+        timestamp: Utc::now(),
+        user_id: user_id.get(),
+        merged,
+        into,
+    };
+
+    let mut line = serde_json::to_string(&record)?;
+    line.push('\n');
+
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    file.write_all(line.as_bytes()).await?;
+
+    Ok(())
+}
@@ -0,0 +1,87 @@
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::io::AsyncWriteExt;
+
+/// A user's rating of a reply, along with the exchange it was about and how it was generated.
+#[derive(Serialize)]
+pub struct FeedbackRecord {
+    pub timestamp: DateTime<Utc>,
+    pub user_id: u64,
+    /// Engine the exchange happened in, the channel's id in group mode.
+    pub engine: u64,
+    pub rating: &'static str,
+    pub comment: Option<String>,
+    pub prompt: Option<String>,
+    pub reply: String,
+    pub model: String,
+    pub temperature: f64,
+    pub max_tokens: Option<u64>,
+}
+
+impl FeedbackRecord {
+    /// Appends the record to the feedback file as a JSON line.
+    pub async fn append(&self, path: &Path) -> anyhow::Result<()> {
+        let mut line = serde_json::to_string(self)?;
+        line.push('\n');
+
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+        file.write_all(line.as_bytes()).await?;
+        file.flush().await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(rating: &'static str, comment: Option<&str>) -> FeedbackRecord {
+        FeedbackRecord {
+            timestamp: Utc::now(),
+            user_id: 7,
+            engine: 8,
+            rating,
+            comment: comment.map(str::to_string),
+            prompt: Some("hi".to_string()),
+            reply: "hello!".to_string(),
+            model: "gpt-test".to_string(),
+            temperature: 0.7,
+            max_tokens: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn records_are_appended_as_json_lines() {
+        let path = std::env::temp_dir()
+            .join("chatbot-tests")
+            .join("feedback-records.jsonl");
+        let _ = std::fs::remove_file(&path);
+
+        record("good", None).append(&path).await.unwrap();
+        record("bad", Some("too long")).append(&path).await.unwrap();
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = written
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["rating"], "good");
+        assert_eq!(lines[0]["comment"], serde_json::Value::Null);
+        assert_eq!(lines[1]["rating"], "bad");
+        assert_eq!(lines[1]["comment"], "too long");
+        assert_eq!(lines[1]["reply"], "hello!");
+    }
+}
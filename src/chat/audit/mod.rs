@@ -0,0 +1,3 @@
+pub mod consolidation;
+pub mod feedback;
+pub mod transcript;
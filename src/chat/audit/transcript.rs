@@ -0,0 +1,286 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{LazyLock, Mutex},
+};
+
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use serde::Serialize;
+use serenity::all::UserId;
+use tokio::{io::AsyncWriteExt, sync::mpsc};
+
+use crate::{
+    chat::{ChatMessage, context::UserPrompt},
+    config::structure::ConversationLogConfig,
+};
+
+#[derive(Serialize)]
+struct ConversationRecord<'a> {
+    timestamp: DateTime<Utc>,
+    user_id: u64,
+    system_prompt: &'a str,
+    history: &'a [ChatMessage],
+    prompt: &'a UserPrompt,
+    response: &'a [ChatMessage],
+}
+
+/// One writer task per log file, shared by every engine so their lines never interleave.
+static WRITERS: LazyLock<Mutex<HashMap<PathBuf, mpsc::UnboundedSender<String>>>> =
+    LazyLock::new(Default::default);
+
+/// JSON lines log of every request/response pair sent to the model, written in the background.
+pub struct ConversationLog {
+    sender: mpsc::UnboundedSender<String>,
+    redact: Vec<Regex>,
+    user_id: UserId,
+}
+
+impl ConversationLog {
+    /// Returns `None` when the log is disabled.
+    pub fn new(config: &ConversationLogConfig, user_id: UserId) -> anyhow::Result<Option<Self>> {
+        if !config.enabled {
+            return Ok(None);
+        }
+
+        let redact = config
+            .redact
+            .iter()
+            .flatten()
+            .map(|pattern| Regex::new(pattern))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Some(Self {
+            sender: Self::sender(&config.path),
+            redact,
+            user_id,
+        }))
+    }
+
+    fn sender(path: &Path) -> mpsc::UnboundedSender<String> {
+        let mut writers = WRITERS.lock().unwrap_or_else(|e| e.into_inner());
+
+        if let Some(sender) = writers.get(path).filter(|sender| !sender.is_closed()) {
+            return sender.clone();
+        }
+
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(Self::writer(path.to_path_buf(), receiver));
+        writers.insert(path.to_path_buf(), sender.clone());
+
+        sender
+    }
+
+    async fn writer(path: PathBuf, mut receiver: mpsc::UnboundedReceiver<String>) {
+        let created = match path.parent() {
+            Some(parent) => tokio::fs::create_dir_all(parent).await,
+            None => Ok(()),
+        };
+        if let Err(why) = created {
+            log::error!("failed to create conversation log folder: {why:?}");
+        }
+
+        while let Some(line) = receiver.recv().await {
+            let result: anyhow::Result<()> = async {
+                let mut file = tokio::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .await?;
+                file.write_all(line.as_bytes()).await?;
+
+                Ok(())
+            }
+            .await;
+
+            if let Err(why) = result {
+                log::error!("failed to write to conversation log: {why:?}");
+            }
+        }
+    }
+
+    /// Queues a record of a completed turn, redacting the configured patterns.
+    pub fn record(
+        &self,
+        system_prompt: &str,
+        history: &[ChatMessage],
+        prompt: &UserPrompt,
+        response: &[ChatMessage],
+    ) {
+        let record = ConversationRecord {
+            timestamp: Utc::now(),
+            user_id: self.user_id.get(),
+            system_prompt,
+            history,
+            prompt,
+            response,
+        };
+
+        let line = serde_json::to_value(&record).and_then(|mut record| {
+            self.redact_values(&mut record);
+            serde_json::to_string(&record)
+        });
+        let mut line = match line {
+            Ok(line) => line,
+            Err(why) => {
+                log::error!("failed to serialize conversation record: {why:?}");
+                return;
+            }
+        };
+        line.push('\n');
+
+        if self.sender.send(line).is_err() {
+            log::error!("conversation log writer is no longer running");
+        }
+    }
+
+    /// Redacts the strings in the record rather than its serialized form, so patterns can't
+    /// match across fields or eat into the JSON around them.
+    fn redact_values(&self, value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::String(text) => {
+                for regex in &self.redact {
+                    *text = regex.replace_all(text, "[REDACTED]").into_owned();
+                }
+            }
+            serde_json::Value::Array(values) => values
+                .iter_mut()
+                .for_each(|value| self.redact_values(value)),
+            serde_json::Value::Object(fields) => fields
+                .values_mut()
+                .for_each(|value| self.redact_values(value)),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn prompt(content: &str) -> UserPrompt {
+        UserPrompt {
+            content: Some(content.to_string()),
+            current_time: "now".to_string(),
+            time_since: "a minute".to_string(),
+            relevant_memories: vec![],
+            system_note: None,
+            author: None,
+            freewill: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn records_are_written_as_redacted_json_lines() {
+        let path = std::env::temp_dir()
+            .join("chatbot-tests")
+            .join("transcript-records.jsonl");
+        let _ = std::fs::remove_file(&path);
+
+        let config = ConversationLogConfig {
+            enabled: true,
+            path: path.clone(),
+            redact: Some(vec![r"\d{4}-\d{4}".to_string()]),
+        };
+        let log = ConversationLog::new(&config, UserId::new(7))
+            .unwrap()
+            .unwrap();
+
+        log.record(
+            "be nice",
+            &[ChatMessage::user("hi".to_string())],
+            &prompt("my card is 1234-5678"),
+            &[ChatMessage::assistant("noted".to_string())],
+        );
+
+        // the writer runs in the background
+        let mut written = String::new();
+        for _ in 0..50 {
+            written = std::fs::read_to_string(&path).unwrap_or_default();
+            if written.ends_with('\n') {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        let record: serde_json::Value = serde_json::from_str(written.trim()).unwrap();
+        assert_eq!(record["user_id"], 7);
+        assert_eq!(record["system_prompt"], "be nice");
+        assert_eq!(record["prompt"]["content"], "my card is [REDACTED]");
+        assert!(!written.contains("1234"));
+    }
+
+    #[test]
+    fn disabled_logs_are_not_created() {
+        let config = ConversationLogConfig::default();
+
+        assert!(
+            ConversationLog::new(&config, UserId::new(7))
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn invalid_redaction_patterns_are_refused() {
+        let config = ConversationLogConfig {
+            enabled: true,
+            redact: Some(vec!["(".to_string()]),
+            ..Default::default()
+        };
+
+        assert!(ConversationLog::new(&config, UserId::new(7)).is_err());
+    }
+
+    #[tokio::test]
+    async fn logs_of_one_file_share_a_writer() {
+        let config = |name: &str| ConversationLogConfig {
+            enabled: true,
+            path: std::env::temp_dir().join("chatbot-tests").join(name),
+            ..Default::default()
+        };
+        let log = |config: &ConversationLogConfig, user| {
+            ConversationLog::new(config, UserId::new(user))
+                .unwrap()
+                .unwrap()
+        };
+
+        let shared = config("transcript-shared.jsonl");
+        assert!(log(&shared, 1).sender.same_channel(&log(&shared, 2).sender));
+        assert!(
+            !log(&shared, 1)
+                .sender
+                .same_channel(&log(&config("transcript-other.jsonl"), 1).sender)
+        );
+    }
+
+    #[tokio::test]
+    async fn redaction_stays_within_each_value() {
+        let config = ConversationLogConfig {
+            enabled: true,
+            path: std::env::temp_dir()
+                .join("chatbot-tests")
+                .join("transcript-redaction.jsonl"),
+            redact: Some(vec![r"secret.*".to_string()]),
+        };
+        let log = ConversationLog::new(&config, UserId::new(7))
+            .unwrap()
+            .unwrap();
+
+        let mut record = serde_json::json!({
+            "prompt": { "content": "my secret is out", "time": "now" },
+            "history": ["no secrets here", 5],
+        });
+        log.redact_values(&mut record);
+
+        assert_eq!(
+            record,
+            serde_json::json!({
+                "prompt": { "content": "my [REDACTED]", "time": "now" },
+                "history": ["no [REDACTED]", 5],
+            })
+        );
+    }
+}
@@ -0,0 +1,197 @@
+use std::sync::{
+    Mutex, OnceLock,
+    atomic::{AtomicU64, Ordering},
+};
+
+use async_trait::async_trait;
+use indexmap::IndexMap;
+use rig::embeddings::{Embedding, EmbeddingError};
+
+use super::providers::DynEmbeddingModel;
+
+/// Embeddings of recently embedded texts, shared by every engine. Entries are keyed by the
+/// embedding model too, so switching models never hands out vectors of the old one.
+#[derive(Default)]
+pub struct EmbeddingCache {
+    entries: Mutex<IndexMap<(String, String), Embedding>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl CacheStats {
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            total => self.hits as f64 / total as f64,
+        }
+    }
+}
+
+impl EmbeddingCache {
+    pub fn global() -> &'static Self {
+        static CACHE: OnceLock<EmbeddingCache> = OnceLock::new();
+        CACHE.get_or_init(Self::default)
+    }
+
+    fn get(&self, model: &str, text: &str) -> Option<Embedding> {
+        let entries = self.entries.lock().ok()?;
+        let found = entries.get(&(model.to_string(), text.to_string())).cloned();
+
+        match found {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+
+        found
+    }
+
+    /// Caches an embedding, evicting the oldest ones past `capacity`.
+    fn insert(&self, model: &str, text: &str, embedding: Embedding, capacity: usize) {
+        let Ok(mut entries) = self.entries.lock() else {
+            return;
+        };
+
+        entries.insert((model.to_string(), text.to_string()), embedding);
+        while entries.len() > capacity {
+            entries.shift_remove_index(0);
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        // counted under the lock, so the numbers line up with each other
+        let entries = self.entries.lock().map(|entries| entries.len());
+
+        CacheStats {
+            entries: entries.unwrap_or(0),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Drops every entry and starts counting hits and misses over.
+    pub fn clear(&self) {
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.clear();
+            self.hits.store(0, Ordering::Relaxed);
+            self.misses.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+/// An embedding model going through the [EmbeddingCache] for single texts.
+pub struct CachedEmbeddingModel {
+    inner: Box<dyn DynEmbeddingModel>,
+    model: String,
+    capacity: usize,
+}
+
+impl CachedEmbeddingModel {
+    pub fn new(inner: Box<dyn DynEmbeddingModel>, model: String, capacity: usize) -> Self {
+        Self {
+            inner,
+            model,
+            capacity,
+        }
+    }
+}
+
+#[async_trait]
+impl DynEmbeddingModel for CachedEmbeddingModel {
+    async fn embed_text(&self, input: &str) -> Result<Embedding, EmbeddingError> {
+        let cache = EmbeddingCache::global();
+        if let Some(embedding) = cache.get(&self.model, input) {
+            return Ok(embedding);
+        }
+
+        let embedding = self.inner.embed_text(input).await?;
+        cache.insert(&self.model, input, embedding.clone(), self.capacity);
+
+        Ok(embedding)
+    }
+
+    async fn embed_texts(&self, input: Vec<String>) -> Result<Vec<Embedding>, EmbeddingError> {
+        self.inner.embed_texts(input).await
+    }
+
+    fn ndims(&self) -> usize {
+        self.inner.ndims()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn embedding(text: &str) -> Embedding {
+        Embedding {
+            document: text.to_string(),
+            vec: vec![text.len() as f64],
+        }
+    }
+
+    #[test]
+    fn the_oldest_entries_are_evicted() {
+        let cache = EmbeddingCache::default();
+        for text in ["a", "bb", "ccc"] {
+            cache.insert("model", text, embedding(text), 2);
+        }
+
+        assert!(cache.get("model", "a").is_none());
+        assert_eq!(cache.get("model", "ccc").unwrap().vec, [3.0]);
+        // entries of another model are never handed out
+        assert!(cache.get("other", "bb").is_none());
+
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.hits, stats.misses), (2, 1, 2));
+        assert_eq!(stats.hit_rate(), 1.0 / 3.0);
+
+        cache.clear();
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.hits, stats.misses), (0, 0, 0));
+        assert_eq!(stats.hit_rate(), 0.0);
+    }
+
+    /// Counts how many texts it had to embed, clones sharing the count.
+    #[derive(Clone, Default)]
+    struct Counting(std::sync::Arc<AtomicU64>);
+
+    #[async_trait]
+    impl DynEmbeddingModel for Counting {
+        async fn embed_text(&self, input: &str) -> Result<Embedding, EmbeddingError> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Ok(embedding(input))
+        }
+
+        async fn embed_texts(&self, input: Vec<String>) -> Result<Vec<Embedding>, EmbeddingError> {
+            Ok(input.iter().map(|text| embedding(text)).collect())
+        }
+
+        fn ndims(&self) -> usize {
+            1
+        }
+    }
+
+    #[tokio::test]
+    async fn repeated_texts_are_only_embedded_once() {
+        let counting = Counting::default();
+        // a model name of its own, the cache is shared by the whole process
+        let model = CachedEmbeddingModel::new(
+            Box::new(counting.clone()),
+            "cache-test-model".to_string(),
+            10,
+        );
+
+        model.embed_text("hello").await.unwrap();
+        model.embed_text("hello").await.unwrap();
+        model.embed_text("world").await.unwrap();
+
+        assert_eq!(counting.0.load(Ordering::Relaxed), 2);
+    }
+}
@@ -0,0 +1,36 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+/// Where the time of a single turn went, shown by `/debug last`. Token counts are estimates.
+#[derive(Debug, Clone)]
+pub struct TurnMetrics {
+    pub finished_at: DateTime<Utc>,
+    pub total: Duration,
+    pub embedding: Duration,
+    pub recall: Duration,
+    pub completion: Duration,
+    pub tools: Duration,
+    /// Requests sent to the model, including tool round-trips and continuations.
+    pub completions: u32,
+    pub tool_calls: u32,
+    pub tokens_in: usize,
+    pub tokens_out: usize,
+}
+
+impl Default for TurnMetrics {
+    fn default() -> Self {
+        Self {
+            finished_at: Utc::now(),
+            total: Duration::ZERO,
+            embedding: Duration::ZERO,
+            recall: Duration::ZERO,
+            completion: Duration::ZERO,
+            tools: Duration::ZERO,
+            completions: 0,
+            tool_calls: 0,
+            tokens_in: 0,
+            tokens_out: 0,
+        }
+    }
+}
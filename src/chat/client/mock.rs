@@ -0,0 +1,143 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use async_trait::async_trait;
+use rig::{
+    OneOrMany,
+    completion::{CompletionError, CompletionRequest},
+    embeddings::{Embedding, EmbeddingError},
+    message::{AssistantContent, ToolCall, ToolFunction},
+};
+use serde_json::Value;
+
+use super::providers::{DynCompletionModel, DynEmbeddingModel, ModelCompletion};
+
+/// Answers completions with the scripted replies in order, keeping every request it got.
+/// Clones share the script, so one can be handed to an agent and the other looked at.
+#[derive(Clone, Default)]
+pub struct ScriptedModel {
+    replies: Arc<Mutex<VecDeque<ModelCompletion>>>,
+    requests: Arc<Mutex<Vec<CompletionRequest>>>,
+}
+
+impl ScriptedModel {
+    /// Queues a text reply.
+    pub fn reply(&self, text: &str) -> &Self {
+        self.push(OneOrMany::one(AssistantContent::text(text)), false)
+    }
+
+    /// Queues a text reply that was cut off by `max_tokens`.
+    pub fn cut_off(&self, text: &str) -> &Self {
+        self.push(OneOrMany::one(AssistantContent::text(text)), true)
+    }
+
+    /// Queues a reply calling a single tool.
+    pub fn call(&self, name: &str, arguments: Value) -> &Self {
+        self.push(OneOrMany::one(Self::tool_call(name, arguments)), false)
+    }
+
+    /// Queues a reply with some text along with a call to a single tool.
+    pub fn say_and_call(&self, text: &str, name: &str, arguments: Value) -> &Self {
+        let choice = OneOrMany::many(vec![
+            AssistantContent::text(text),
+            Self::tool_call(name, arguments),
+        ])
+        .expect("there are two parts");
+
+        self.push(choice, false)
+    }
+
+    fn tool_call(name: &str, arguments: Value) -> AssistantContent {
+        AssistantContent::ToolCall(ToolCall {
+            id: format!("call_{name}"),
+            function: ToolFunction {
+                name: name.to_string(),
+                arguments,
+            },
+        })
+    }
+
+    fn push(&self, choice: OneOrMany<AssistantContent>, truncated: bool) -> &Self {
+        self.replies
+            .lock()
+            .unwrap()
+            .push_back(ModelCompletion { choice, truncated });
+        self
+    }
+
+    /// How many of the scripted replies weren't asked for yet.
+    pub fn remaining(&self) -> usize {
+        self.replies.lock().unwrap().len()
+    }
+
+    /// Every request so far, oldest first.
+    pub fn requests(&self) -> MutexGuard<'_, Vec<CompletionRequest>> {
+        self.requests.lock().unwrap()
+    }
+}
+
+#[async_trait]
+impl DynCompletionModel for ScriptedModel {
+    async fn completion(
+        &self,
+        request: CompletionRequest,
+    ) -> Result<ModelCompletion, CompletionError> {
+        self.requests.lock().unwrap().push(request);
+
+        self.replies.lock().unwrap().pop_front().ok_or_else(|| {
+            CompletionError::ProviderError("the script ran out of replies".to_string())
+        })
+    }
+}
+
+/// Embeds a text as a bag of its (lowercased) words, each hashed into one of the dimensions.
+/// Texts sharing words come out similar, which is all recall needs to be replayed.
+pub struct WordEmbedding {
+    dimensions: usize,
+}
+
+impl WordEmbedding {
+    pub fn new(dimensions: usize) -> Self {
+        Self { dimensions }
+    }
+
+    fn embed(&self, text: &str) -> Embedding {
+        let mut vec = vec![0.0; self.dimensions];
+
+        for word in text
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+        {
+            // FNV-1a, std's hashers aren't guaranteed to stay the same between releases
+            let hash = word
+                .to_lowercase()
+                .bytes()
+                .fold(0xcbf29ce484222325u64, |hash, byte| {
+                    (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+                });
+            vec[(hash % self.dimensions as u64) as usize] += 1.0;
+        }
+
+        Embedding {
+            document: text.to_string(),
+            vec,
+        }
+    }
+}
+
+#[async_trait]
+impl DynEmbeddingModel for WordEmbedding {
+    async fn embed_text(&self, input: &str) -> Result<Embedding, EmbeddingError> {
+        Ok(self.embed(input))
+    }
+
+    async fn embed_texts(&self, input: Vec<String>) -> Result<Vec<Embedding>, EmbeddingError> {
+        Ok(input.iter().map(|text| self.embed(text)).collect())
+    }
+
+    fn ndims(&self) -> usize {
+        self.dimensions
+    }
+}
@@ -1,6 +1,17 @@
 mod agent;
+mod cache;
+mod metrics;
+#[cfg(test)]
+pub mod mock;
+mod overrides;
+mod postprocess;
 mod providers;
 mod tools;
+mod tts;
 
 pub use agent::*;
+pub use cache::EmbeddingCache;
+pub use overrides::InlineOverrides;
+pub use postprocess::format_markdown;
 pub use providers::Provider;
+pub use tts::synthesize;
@@ -0,0 +1,150 @@
+use std::sync::LazyLock;
+
+use regex::Regex;
+
+static DIRECTIVE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^\s*\[\s*(\w+)\s*=\s*([^\]\s]+)\s*\]").expect("directive pattern is valid")
+});
+
+/// Parameters a single message asked for, with `[key=value]` directives in front of it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InlineOverrides {
+    pub temperature: Option<f64>,
+    pub top_p: Option<f64>,
+    /// Only ever lowers the configured cap.
+    pub max_tokens: Option<u64>,
+}
+
+impl InlineOverrides {
+    /// Takes the directives off the start of a message, returning what's left of it. Parsing stops
+    /// at the first unknown key or out of bounds value, which stays in the message like the rest.
+    pub fn parse(content: &str) -> (Self, String) {
+        let mut overrides = Self::default();
+        let mut rest = content;
+
+        while let Some(captures) = DIRECTIVE.captures(rest) {
+            let value = &captures[2];
+
+            let applied = match captures[1].to_lowercase().as_str() {
+                "temp" | "temperature" => value
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|temperature| (0.0..=2.0).contains(temperature))
+                    .map(|temperature| overrides.temperature = Some(temperature)),
+                "top_p" => value
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|top_p| *top_p > 0.0 && *top_p <= 1.0)
+                    .map(|top_p| overrides.top_p = Some(top_p)),
+                "max_tokens" => value
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|max_tokens| *max_tokens > 0)
+                    .map(|max_tokens| overrides.max_tokens = Some(max_tokens)),
+                _ => None,
+            };
+
+            if applied.is_none() {
+                break;
+            }
+
+            rest = &rest[captures[0].len()..];
+        }
+
+        match rest.len() == content.len() {
+            true => (overrides, content.to_string()),
+            false => {
+                log::debug!("message overrides {overrides:?}");
+                (overrides, rest.trim_start().to_string())
+            }
+        }
+    }
+
+    /// The configured cap, lowered to the inline one if that's lower.
+    pub fn cap_max_tokens(&self, configured: Option<u64>) -> Option<u64> {
+        match (configured, self.max_tokens) {
+            (Some(configured), Some(inline)) => Some(configured.min(inline)),
+            (configured, inline) => inline.or(configured),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn directives_are_taken_off_the_message() {
+        let (overrides, content) =
+            InlineOverrides::parse("[temp=0.5] [top_p=0.9][max_tokens=200] hi");
+
+        assert_eq!(
+            overrides,
+            InlineOverrides {
+                temperature: Some(0.5),
+                top_p: Some(0.9),
+                max_tokens: Some(200),
+            }
+        );
+        assert_eq!(content, "hi");
+    }
+
+    #[test]
+    fn out_of_range_values_stop_parsing() {
+        for message in [
+            "[temp=2.5] hi",
+            "[temperature=-1] hi",
+            "[top_p=0] hi",
+            "[top_p=1.5] hi",
+            "[max_tokens=0] hi",
+            "[max_tokens=-5] hi",
+            "[max_tokens=lots] hi",
+        ] {
+            assert_eq!(
+                InlineOverrides::parse(message),
+                (InlineOverrides::default(), message.to_string())
+            );
+        }
+
+        let (overrides, content) = InlineOverrides::parse("[temp=1] [top_p=3] hi");
+        assert_eq!(overrides.temperature, Some(1.0));
+        assert_eq!(overrides.top_p, None);
+        assert_eq!(content, "[top_p=3] hi");
+    }
+
+    #[test]
+    fn unknown_keys_stay_in_the_message() {
+        let message = "[seed=4] hi";
+
+        assert_eq!(
+            InlineOverrides::parse(message),
+            (InlineOverrides::default(), message.to_string())
+        );
+    }
+
+    #[test]
+    fn max_tokens_only_goes_lower() {
+        let (overrides, _) = InlineOverrides::parse("[max_tokens=100] hi");
+        assert_eq!(overrides.cap_max_tokens(Some(500)), Some(100));
+        assert_eq!(overrides.cap_max_tokens(None), Some(100));
+
+        let (overrides, _) = InlineOverrides::parse("[max_tokens=1000] hi");
+        assert_eq!(overrides.cap_max_tokens(Some(500)), Some(500));
+
+        assert_eq!(
+            InlineOverrides::default().cap_max_tokens(Some(500)),
+            Some(500)
+        );
+        assert_eq!(InlineOverrides::default().cap_max_tokens(None), None);
+    }
+
+    #[test]
+    fn messages_without_directives_are_left_alone() {
+        for message in ["  hi [temp=0.2]", "[link](https://example.com)", ""] {
+            let (overrides, content) = InlineOverrides::parse(message);
+
+            assert_eq!(overrides, InlineOverrides::default());
+            assert_eq!(content, message);
+        }
+    }
+}
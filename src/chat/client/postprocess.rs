@@ -0,0 +1,487 @@
+use std::sync::LazyLock;
+
+use regex::Regex;
+
+use crate::config::structure::{MarkdownMode, PostprocessAction, PostprocessStep};
+
+static EMOJI: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"<a?:\w+:\d+>|[\p{Extended_Pictographic}\p{Emoji_Modifier}\x{FE0F}\x{200D}]")
+        .expect("emoji pattern is valid")
+});
+
+/// Formatting markers and what of them to keep, in the order they're stripped.
+static MARKDOWN: LazyLock<Vec<(Regex, &'static str)>> = LazyLock::new(|| {
+    [
+        (r"(?s)```[^\n]*\n?(.*?)```", "$1"),
+        (r"`([^`\n]+)`", "$1"),
+        (r"\[([^\]\n]+)\]\(([^)\s]+)\)", "$1 ($2)"),
+        (r"\*\*(.+?)\*\*", "$1"),
+        (r"__(.+?)__", "$1"),
+        (r"~~(.+?)~~", "$1"),
+        (r"\|\|(.+?)\|\|", "$1"),
+        (r"\*([^*\s][^*]*?)\*", "$1"),
+        (r"\b_([^_\s][^_]*?)_\b", "$1"),
+        (r"(?m)^#{1,3}\s+", ""),
+        (r"(?m)^-#\s+", ""),
+        (r"(?m)^>{1,3}\s?", ""),
+    ]
+    .into_iter()
+    .map(|(pattern, replacement)| {
+        (
+            Regex::new(pattern).expect("markdown pattern is valid"),
+            replacement,
+        )
+    })
+    .collect()
+});
+
+/// Characters Discord formats with anywhere in a line, and those it only does at a line's start.
+static MARKDOWN_CHARS: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"[\\*_~`|]").expect("markdown characters pattern is valid"));
+static MARKDOWN_LINES: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?m)^(#{1,3}\s|-#\s|>)").expect("markdown lines pattern is valid")
+});
+
+fn strip_markdown(text: String) -> String {
+    MARKDOWN.iter().fold(text, |text, (regex, replacement)| {
+        regex.replace_all(&text, *replacement).to_string()
+    })
+}
+
+/// Mentions, custom emoji, timestamps and links, which stop working once escaped.
+static UNESCAPED: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"<[^<>\s]+>|https?://[^\s<>]+").expect("unescaped tokens pattern is valid")
+});
+
+fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    let mut last = 0;
+
+    for token in UNESCAPED.find_iter(text) {
+        escaped.push_str(&MARKDOWN_CHARS.replace_all(&text[last..token.start()], r"\$0"));
+        escaped.push_str(token.as_str());
+        last = token.end();
+    }
+    escaped.push_str(&MARKDOWN_CHARS.replace_all(&text[last..], r"\$0"));
+
+    MARKDOWN_LINES.replace_all(&escaped, r"\$1").to_string()
+}
+
+/// Handles a reply's markdown the way its channel wants it.
+pub fn format_markdown(text: String, mode: MarkdownMode) -> String {
+    match mode {
+        MarkdownMode::Preserve => text,
+        MarkdownMode::Strip => strip_markdown(text),
+        MarkdownMode::Escape => escape_markdown(&text),
+    }
+}
+
+/// Runs a reply through the enabled postprocessing steps, in order.
+pub fn postprocess(mut text: String, steps: &[PostprocessStep]) -> anyhow::Result<String> {
+    for step in steps.iter().filter(|step| step.enabled.unwrap_or(true)) {
+        text = match &step.action {
+            PostprocessAction::Lowercase => text.to_lowercase(),
+            PostprocessAction::Replace { words } => {
+                // hash maps don't keep an order, sort so overlapping words always apply the same way
+                let mut words = words.iter().collect::<Vec<_>>();
+                words.sort();
+
+                for (word, replacement) in words {
+                    let regex = Regex::new(&format!(r"(?i)\b{}\b", regex::escape(word)))?;
+                    text = regex
+                        .replace_all(&text, regex::NoExpand(replacement))
+                        .to_string();
+                }
+
+                text
+            }
+            PostprocessAction::StripEmoji => EMOJI.replace_all(&text, "").to_string(),
+            PostprocessAction::StripMarkdown => strip_markdown(text),
+            PostprocessAction::Suffix { text: suffix } => text + suffix,
+        };
+    }
+
+    Ok(text)
+}
+
+/// Tags models wrap their reasoning in when the config doesn't list any.
+const REASONING_TAGS: &[&str] = &["think", "thinking", "reasoning"];
+
+/// Takes the reasoning out of a reply, for every configured tag. Nested tags go along with the
+/// outermost one, stray closing tags are dropped and an unclosed tag is left as it is.
+pub fn strip_reasoning(mut text: String, tags: Option<&[String]>) -> anyhow::Result<String> {
+    let tags = match tags {
+        Some(tags) => tags.iter().map(String::as_str).collect::<Vec<_>>(),
+        None => REASONING_TAGS.to_vec(),
+    };
+
+    for tag in tags {
+        let tag = tag.trim();
+        let marker = Regex::new(&format!(r"(?i)<\|?(/)?{}\|?>\n*", regex::escape(tag)))?;
+
+        let mut kept = String::with_capacity(text.len());
+        let mut last = 0;
+        let mut depth = 0;
+        let mut opened = 0;
+        for cap in marker.captures_iter(&text) {
+            let found = cap.get(0).expect("whole match is always there");
+            match (cap.get(1).is_some(), depth) {
+                (false, 0) => {
+                    kept.push_str(&text[last..found.start()]);
+                    opened = found.start();
+                    last = found.end();
+                    depth = 1;
+                }
+                (false, _) => depth += 1,
+                (true, 0) => {
+                    kept.push_str(&text[last..found.start()]);
+                    last = found.end();
+                }
+                (true, 1) => {
+                    log::trace!("Extracted {tag} reasoning:\n{}", &text[last..found.start()]);
+                    last = found.end();
+                    depth = 0;
+                }
+                (true, _) => depth -= 1,
+            }
+        }
+
+        match depth {
+            0 => kept.push_str(&text[last..]),
+            _ => kept.push_str(&text[opened..]),
+        }
+        text = kept;
+    }
+
+    Ok(text)
+}
+
+/// Holds back the reasoning of a reply that comes in chunks (e.g. while streaming), so only
+/// what the model says outside of it is ever shown. Same tags and nesting as [strip_reasoning].
+pub struct ReasoningFilter {
+    marker: Regex,
+    /// Longest a marker can be, a `<` this close to the end may be the start of one.
+    longest: usize,
+    depth: usize,
+    /// Text that can't be shown yet, because a marker might be cut off in it.
+    pending: String,
+    /// The last chunk ended on a marker, the newlines after it are dropped like in one piece.
+    after_marker: bool,
+}
+
+impl ReasoningFilter {
+    pub fn new(tags: Option<&[String]>) -> anyhow::Result<Self> {
+        let tags = match tags {
+            Some(tags) => tags.iter().map(|tag| tag.trim()).collect::<Vec<_>>(),
+            None => REASONING_TAGS.to_vec(),
+        };
+
+        let marker = Regex::new(&format!(
+            r"(?i)<\|?(/)?(?:{})\|?>\n*",
+            tags.iter()
+                .map(|tag| regex::escape(tag))
+                .collect::<Vec<_>>()
+                .join("|")
+        ))?;
+        // <|/tag|>
+        let longest = tags.iter().map(|tag| tag.len() + 5).max().unwrap_or(0);
+
+        Ok(Self {
+            marker,
+            longest,
+            depth: 0,
+            pending: String::new(),
+            after_marker: false,
+        })
+    }
+
+    /// Takes the next chunk, returning the part of the reply that can be shown so far.
+    pub fn push(&mut self, chunk: &str) -> String {
+        let chunk = match self.after_marker {
+            true => chunk.trim_start_matches('\n'),
+            false => chunk,
+        };
+        if chunk.is_empty() {
+            return String::new();
+        }
+        self.after_marker = false;
+        self.pending.push_str(chunk);
+
+        let mut shown = String::new();
+        let mut last = 0;
+        for found in self.marker.captures_iter(&self.pending) {
+            let marker = found.get(0).expect("whole match is always there");
+            if self.depth == 0 {
+                shown.push_str(&self.pending[last..marker.start()]);
+            }
+
+            // stray closing tags are dropped
+            self.depth = match (found.get(1).is_some(), self.depth) {
+                (false, depth) => depth + 1,
+                (true, depth) => depth.saturating_sub(1),
+            };
+            last = marker.end();
+            self.after_marker = last == self.pending.len();
+        }
+
+        // a marker may be cut off at the end, wait for the rest of it
+        let held = self.pending[last..]
+            .rfind('<')
+            .map(|at| last + at)
+            .filter(|&at| {
+                self.pending.len() - at < self.longest && !self.pending[at..].contains('>')
+            })
+            .unwrap_or(self.pending.len());
+
+        if self.depth == 0 {
+            shown.push_str(&self.pending[last..held]);
+        }
+        self.pending.drain(..held);
+
+        shown
+    }
+
+    /// Returns whatever was held back once the reply is complete. Reasoning that never got
+    /// closed is left out, there's no telling where it would have ended.
+    pub fn finish(self) -> String {
+        match self.depth {
+            0 => self.pending,
+            _ => {
+                log::warn!("reply ended in the middle of its reasoning, leaving it out");
+                String::new()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn step(action: PostprocessAction) -> PostprocessStep {
+        PostprocessStep {
+            enabled: None,
+            action,
+        }
+    }
+
+    fn run(text: &str, steps: &[PostprocessStep]) -> String {
+        postprocess(text.to_string(), steps).unwrap()
+    }
+
+    #[test]
+    fn steps_run_in_order() {
+        let replace = step(PostprocessAction::Replace {
+            words: HashMap::from([("hello".to_string(), "Howdy".to_string())]),
+        });
+        let lowercase = step(PostprocessAction::Lowercase);
+
+        assert_eq!(
+            run("Hello there", &[replace.clone(), lowercase.clone()]),
+            "howdy there"
+        );
+        assert_eq!(run("Hello there", &[lowercase, replace]), "Howdy there");
+    }
+
+    #[test]
+    fn disabled_steps_are_skipped() {
+        let suffix = PostprocessStep {
+            enabled: Some(false),
+            action: PostprocessAction::Suffix {
+                text: " ~Botty".to_string(),
+            },
+        };
+
+        assert_eq!(run("Hi!", &[suffix]), "Hi!");
+    }
+
+    #[test]
+    fn words_are_only_replaced_whole() {
+        let replace = step(PostprocessAction::Replace {
+            words: HashMap::from([("cat".to_string(), "$dog".to_string())]),
+        });
+
+        assert_eq!(
+            run("Cat, cats and a CAT.", &[replace]),
+            "$dog, cats and a $dog."
+        );
+    }
+
+    #[test]
+    fn emoji_are_stripped_custom_ones_included() {
+        let strip = step(PostprocessAction::StripEmoji);
+
+        assert_eq!(
+            run("Nice 👍🏽 <:pog:123> <a:dance:456>!", &[strip]),
+            "Nice   !"
+        );
+    }
+
+    #[test]
+    fn markdown_is_stripped_down_to_its_text() {
+        let strip = step(PostprocessAction::StripMarkdown);
+
+        assert_eq!(
+            run(
+                "# Title\n**bold**, *italic*, ~~gone~~ and `code`\n> quoted [link](https://example.com)",
+                &[strip]
+            ),
+            "Title\nbold, italic, gone and code\nquoted link (https://example.com)"
+        );
+    }
+
+    #[test]
+    fn steps_are_configured_by_name() {
+        let steps: Vec<PostprocessStep> = toml::from_str::<HashMap<String, Vec<_>>>(
+            "[[steps]]\nstep = \"suffix\"\ntext = \" ~Botty\"\n\n[[steps]]\nstep = \"strip_emoji\"\nenabled = false\n",
+        )
+        .unwrap()
+        .remove("steps")
+        .unwrap();
+
+        assert_eq!(
+            steps,
+            [
+                step(PostprocessAction::Suffix {
+                    text: " ~Botty".to_string()
+                }),
+                PostprocessStep {
+                    enabled: Some(false),
+                    action: PostprocessAction::StripEmoji,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn reasoning_is_stripped_with_the_newlines_after_it() {
+        assert_eq!(
+            strip_reasoning("<think>plan it</think>\n\nHello".to_string(), None).unwrap(),
+            "Hello"
+        );
+        assert_eq!(
+            strip_reasoning("<Reasoning>plan it</REASONING>Hello".to_string(), None).unwrap(),
+            "Hello"
+        );
+    }
+
+    #[test]
+    fn nested_reasoning_goes_with_the_outermost_tag() {
+        assert_eq!(
+            strip_reasoning("<think>a <think>b</think> c</think>Hi".to_string(), None).unwrap(),
+            "Hi"
+        );
+    }
+
+    #[test]
+    fn stray_closing_tags_are_dropped_and_unclosed_ones_kept() {
+        assert_eq!(
+            strip_reasoning("Hi</think> there".to_string(), None).unwrap(),
+            "Hi there"
+        );
+        assert_eq!(
+            strip_reasoning("Hi <think>still going".to_string(), None).unwrap(),
+            "Hi <think>still going"
+        );
+    }
+
+    #[test]
+    fn only_the_configured_tags_are_stripped() {
+        let tags = ["analysis".to_string()];
+
+        assert_eq!(
+            strip_reasoning(
+                "<|analysis|>x<|/analysis|>ok <think>y</think>".to_string(),
+                Some(&tags)
+            )
+            .unwrap(),
+            "ok <think>y</think>"
+        );
+    }
+
+    #[test]
+    fn markdown_is_kept_stripped_or_escaped() {
+        let reply = || "# Hi\n**bold** and `code`\n> quote".to_string();
+
+        assert_eq!(format_markdown(reply(), MarkdownMode::Preserve), reply());
+        assert_eq!(
+            format_markdown(reply(), MarkdownMode::Escape),
+            "\\# Hi\n\\*\\*bold\\*\\* and \\`code\\`\n\\> quote"
+        );
+        assert_eq!(
+            format_markdown(reply(), MarkdownMode::Strip),
+            strip_markdown(reply())
+        );
+        assert!(!format_markdown(reply(), MarkdownMode::Strip).contains("**"));
+    }
+
+    /// Runs chunks through a filter, returning what was shown after each and at the end.
+    fn filtered(chunks: &[&str]) -> Vec<String> {
+        let mut filter = ReasoningFilter::new(None).unwrap();
+        let mut shown = chunks
+            .iter()
+            .map(|chunk| filter.push(chunk))
+            .collect::<Vec<_>>();
+        shown.push(filter.finish());
+        shown
+    }
+
+    #[test]
+    fn chunked_reasoning_is_held_back() {
+        assert_eq!(
+            filtered(&["Hi <thi", "nk>plan", " it</th", "ink>\n", "\nthere"]),
+            ["Hi ", "", "", "", "there", ""]
+        );
+    }
+
+    #[test]
+    fn chunks_end_up_like_the_whole_reply() {
+        let reply = "<think>a <think>b</think> c</think>\n\nHello</think> 1 < 2 <b>ok</b>";
+        let chunks = reply
+            .chars()
+            .map(|char| char.to_string())
+            .collect::<Vec<_>>();
+        let chunks = chunks.iter().map(String::as_str).collect::<Vec<_>>();
+
+        assert_eq!(
+            filtered(&chunks).concat(),
+            strip_reasoning(reply.to_string(), None).unwrap()
+        );
+    }
+
+    #[test]
+    fn unclosed_reasoning_is_left_out_at_the_end() {
+        assert_eq!(filtered(&["Hi", " <think>still go", "ing"]).concat(), "Hi ");
+    }
+
+    #[test]
+    fn other_tags_pass_through() {
+        assert_eq!(filtered(&["a <b", "r> tag"]).concat(), "a <br> tag");
+    }
+
+    #[test]
+    fn escaping_leaves_discord_tokens_and_links_alone() {
+        let escaped = format_markdown(
+            "*hi* <@123> <:big_smile:456> <t:1700000000:R> see https://example.com/a_b*c or <https://x.io/_y_>".to_string(),
+            MarkdownMode::Escape,
+        );
+
+        assert_eq!(
+            escaped,
+            r"\*hi\* <@123> <:big_smile:456> <t:1700000000:R> see https://example.com/a_b*c or <https://x.io/_y_>"
+        );
+    }
+
+    #[test]
+    fn escaping_covers_line_markdown() {
+        assert_eq!(
+            format_markdown(
+                "# title\n> quote\n__under__".to_string(),
+                MarkdownMode::Escape
+            ),
+            "\\# title\n\\> quote\n\\_\\_under\\_\\_"
+        );
+    }
+}
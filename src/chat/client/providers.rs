@@ -1,4 +1,4 @@
-use std::fmt::Display;
+use std::{any::Any, fmt::Display};
 
 use async_trait::async_trait;
 
@@ -14,6 +14,71 @@ use rig::{
 };
 use serde::{Deserialize, Serialize};
 
+/// What a provider supports, checked up front instead of finding out from a failing request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProviderCapabilities {
+    /// Function calling through the completion API.
+    pub tools: bool,
+    /// Embedding models, used for long-term memory.
+    pub embeddings: bool,
+    /// Reports when a reply got cut off by `max_tokens`, which `auto_continue` relies on.
+    pub truncation: bool,
+}
+
+impl Display for ProviderCapabilities {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let capabilities = [
+            (self.tools, "tools"),
+            (self.embeddings, "embeddings"),
+            (self.truncation, "truncation"),
+        ]
+        .into_iter()
+        .filter_map(|(supported, name)| supported.then_some(name))
+        .collect::<Vec<_>>();
+
+        match capabilities.is_empty() {
+            true => "none".fmt(f),
+            false => capabilities.join(", ").fmt(f),
+        }
+    }
+}
+
+/// How a provider takes a repetition penalty, if at all.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PenaltyParam {
+    /// `repetition_penalty`, multiplicative with 1.0 being off (vLLM and friends).
+    Repetition,
+    /// `frequency_penalty`, additive between -2.0 and 2.0 with 0.0 being off (OpenAI style).
+    Frequency,
+    Unsupported,
+}
+
+/// Sane sampling settings for a provider, used wherever the config doesn't say otherwise.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ProviderDefaults {
+    pub temperature: f64,
+    pub max_tokens: u64,
+    /// Higher temperatures get rejected by the API, so they're clamped to this.
+    pub max_temperature: f64,
+    pub penalty: PenaltyParam,
+    /// Takes OpenRouter's `reasoning` parameter, asking the model for its own reasoning.
+    pub reasoning: bool,
+}
+
+impl ProviderDefaults {
+    /// The configured repetition penalty as the parameter the provider understands.
+    pub fn penalty_param(&self, repetition_penalty: f64) -> Option<(&'static str, f64)> {
+        match self.penalty {
+            PenaltyParam::Repetition => Some(("repetition_penalty", repetition_penalty)),
+            PenaltyParam::Frequency => Some((
+                "frequency_penalty",
+                (repetition_penalty - 1.0).clamp(-2.0, 2.0),
+            )),
+            PenaltyParam::Unsupported => None,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub enum ProviderClient {
     Anthropic(anthropic::Client),
@@ -57,27 +122,56 @@ where
     }
 }
 
+pub struct ModelCompletion {
+    pub choice: OneOrMany<AssistantContent>,
+    /// Whether the provider reported the output was cut off by `max_tokens`.
+    pub truncated: bool,
+}
+
 #[async_trait]
 pub trait DynCompletionModel: Send + Sync {
     async fn completion(
         &self,
         completion: CompletionRequest,
-    ) -> Result<OneOrMany<AssistantContent>, CompletionError>;
+    ) -> Result<ModelCompletion, CompletionError>;
 }
 
 #[async_trait]
 impl<T> DynCompletionModel for T
 where
     T: rig::completion::CompletionModel + Send + Sync,
+    T::Response: 'static,
 {
     async fn completion(
         &self,
         request: CompletionRequest,
-    ) -> Result<OneOrMany<AssistantContent>, CompletionError> {
-        Ok(self.completion(request).await?.choice)
+    ) -> Result<ModelCompletion, CompletionError> {
+        let response = self.completion(request).await?;
+
+        Ok(ModelCompletion {
+            truncated: truncated(&response.raw_response),
+            choice: response.choice,
+        })
     }
 }
 
+/// Finish reasons aren't part of rig's common response, so peek into the raw responses we know.
+fn truncated(raw_response: &dyn Any) -> bool {
+    if let Some(response) = raw_response.downcast_ref::<openai::CompletionResponse>() {
+        return response
+            .choices
+            .iter()
+            .any(|choice| choice.finish_reason == "length");
+    }
+
+    if let Some(response) = raw_response.downcast_ref::<anthropic::completion::CompletionResponse>()
+    {
+        return response.stop_reason.as_deref() == Some("max_tokens");
+    }
+
+    false
+}
+
 impl ProviderClient {
     /// Returns a completion model wrapper for the given provider and model name.
     pub async fn completion_model(&self, model: &str) -> Box<dyn DynCompletionModel> {
@@ -157,7 +251,7 @@ impl ProviderClient {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, Default)]
 pub enum Provider {
     #[serde(rename = "anthropic")]
     Anthropic,
@@ -188,6 +282,7 @@ pub enum Provider {
     #[serde(rename = "openai")]
     #[serde(alias = "openai-api")]
     #[serde(alias = "openai-compatible")]
+    #[default]
     OpenAI,
 
     #[serde(rename = "perplexity")]
@@ -197,12 +292,6 @@ pub enum Provider {
     Xai,
 }
 
-impl Default for Provider {
-    fn default() -> Self {
-        Self::OpenAI
-    }
-}
-
 impl TryFrom<String> for Provider {
     type Error = anyhow::Error;
 
@@ -214,12 +303,58 @@ impl TryFrom<String> for Provider {
 impl Display for Provider {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         serde_plain::to_string(self)
-            .map_err(|_| std::fmt::Error::default())?
+            .map_err(|_| std::fmt::Error)?
             .fmt(f)
     }
 }
 
 impl Provider {
+    pub fn capabilities(&self) -> ProviderCapabilities {
+        ProviderCapabilities {
+            tools: !matches!(self, Provider::Hyperbolic | Provider::Perplexity),
+            // cohere has embeddings, but we never pass it the input type it requires
+            embeddings: matches!(
+                self,
+                Provider::Azure | Provider::Gemini | Provider::OpenAI | Provider::Xai
+            ),
+            // see `truncated`, only these finish reasons are known
+            truncation: matches!(self, Provider::Anthropic | Provider::OpenAI),
+        }
+    }
+
+    /// Defaults for the provider, `custom_url` tells apart OpenAI from the compatible servers
+    /// that speak its API but take vLLM's parameters.
+    pub fn defaults(&self, custom_url: bool) -> ProviderDefaults {
+        let (temperature, max_temperature, penalty) = match self {
+            Provider::Anthropic => (1.0, 1.0, PenaltyParam::Unsupported),
+            Provider::Cohere => (0.3, 1.0, PenaltyParam::Frequency),
+            Provider::Gemini => (1.0, 2.0, PenaltyParam::Unsupported),
+            Provider::Groq => (0.7, 2.0, PenaltyParam::Unsupported),
+            Provider::Hyperbolic => (0.7, 2.0, PenaltyParam::Repetition),
+            Provider::OpenAI if custom_url => (0.7, 2.0, PenaltyParam::Repetition),
+            Provider::Azure
+            | Provider::Deepseek
+            | Provider::Galadriel
+            | Provider::Moonshot
+            | Provider::OpenAI
+            | Provider::Perplexity
+            | Provider::Xai => (0.7, 2.0, PenaltyParam::Frequency),
+        };
+
+        ProviderDefaults {
+            temperature,
+            // anthropic needs room for thinking, the rest only has to fill a few discord messages
+            max_tokens: match self {
+                Provider::Anthropic => 4096,
+                _ => 2048,
+            },
+            max_temperature,
+            penalty,
+            // openrouter and the compatible servers, the rest reject (or ignore) it
+            reasoning: matches!(self, Provider::OpenAI) && custom_url,
+        }
+    }
+
     pub fn client(
         &self,
         api_key: &str,
@@ -288,3 +423,43 @@ impl Provider {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capabilities_are_listed_by_name() {
+        assert_eq!(
+            Provider::OpenAI.capabilities().to_string(),
+            "tools, embeddings, truncation"
+        );
+        assert_eq!(
+            Provider::Anthropic.capabilities().to_string(),
+            "tools, truncation"
+        );
+        assert_eq!(Provider::Perplexity.capabilities().to_string(), "none");
+    }
+
+    #[test]
+    fn repetition_penalties_are_sent_the_way_the_provider_takes_them() {
+        let penalty =
+            |provider: Provider, custom_url| provider.defaults(custom_url).penalty_param(1.2);
+
+        assert_eq!(
+            penalty(Provider::OpenAI, true),
+            Some(("repetition_penalty", 1.2))
+        );
+        let Some(("frequency_penalty", value)) = penalty(Provider::OpenAI, false) else {
+            panic!("openai takes a frequency penalty");
+        };
+        assert!((value - 0.2).abs() < 1e-9);
+        assert_eq!(penalty(Provider::Anthropic, false), None);
+
+        // out of range penalties are clamped
+        assert_eq!(
+            Provider::Xai.defaults(false).penalty_param(5.0),
+            Some(("frequency_penalty", 2.0))
+        );
+    }
+}
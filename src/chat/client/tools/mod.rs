@@ -1,5 +1,7 @@
+mod react;
 mod recall;
 mod store;
 
+pub use react::*;
 pub use recall::*;
 pub use store::*;
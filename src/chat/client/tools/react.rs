@@ -0,0 +1,132 @@
+use rig::{completion::ToolDefinition, tool::Tool};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::sync::{Arc, Mutex};
+
+/// The emojis the model is allowed to react with.
+pub const SAFE_REACTIONS: &[&str] = &[
+    "👍", "👎", "❤️", "😂", "🤣", "😊", "😮", "😢", "😭", "😡", "🤔", "👀", "🎉", "🔥", "✨", "💯",
+    "🙏", "👏", "🙌", "😎", "🥺", "😴", "🤯", "💀", "✅", "❌",
+];
+
+#[derive(Debug, thiserror::Error)]
+#[error("Add Reaction error")]
+pub struct AddReactionError;
+
+#[derive(Deserialize)]
+pub struct Args {
+    emoji: String,
+}
+
+/// Lets the model react to the user's message. The tool only queues the reactions, the handler
+/// adds them to the message once the reply is sent.
+#[derive(Serialize)]
+pub struct AddReaction {
+    #[serde(skip)]
+    pending: Arc<Mutex<Vec<String>>>,
+}
+
+impl AddReaction {
+    /// What the model is told about the tool in the system prompt.
+    pub const GUIDANCE: &'static str = "You can use the add_reaction tool to react to the user's message with an emoji, like laughing at a joke or giving a thumbs up. Use it sparingly and only when it feels natural, it does not replace your reply.";
+
+    pub fn new(pending: Arc<Mutex<Vec<String>>>) -> Self {
+        Self { pending }
+    }
+
+    /// Returns the emoji from the safe set, ignoring whitespace and emoji variation selectors.
+    pub fn validate(emoji: &str) -> Option<&'static str> {
+        let strip = |emoji: &str| emoji.trim().replace('\u{fe0f}', "");
+        let emoji = strip(emoji);
+
+        SAFE_REACTIONS
+            .iter()
+            .find(|safe| strip(safe) == emoji)
+            .copied()
+    }
+}
+
+impl Tool for AddReaction {
+    const NAME: &'static str = "add_reaction";
+
+    type Error = AddReactionError;
+    type Args = Args;
+    type Output = Value;
+
+    async fn definition(&self, _prompt: String) -> ToolDefinition {
+        serde_json::from_value(json!({
+            "name": "add_reaction",
+            "description": "Reacts to the user's message with an emoji.",
+            "parameters": {
+                "type": "object",
+                "properties": {
+                    "emoji": {
+                        "type": "string",
+                        "description": "The emoji to react with",
+                        "enum": SAFE_REACTIONS
+                    },
+                }
+            }
+        }))
+        .expect("Tool Definition")
+    }
+
+    async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
+        let Some(emoji) = Self::validate(&args.emoji) else {
+            log::info!("[add_reaction] refused emoji \"{}\"", args.emoji);
+            return Ok(json!({
+                "add_reaction_result": format!("Cannot react with \"{}\", use one of: {}", args.emoji, SAFE_REACTIONS.join(" "))
+            }));
+        };
+
+        log::info!("[add_reaction] reacting with {emoji}");
+        self.pending
+            .lock()
+            .map_err(|_| AddReactionError)?
+            .push(emoji.to_string());
+
+        Ok(json!({
+            "add_reaction_result": format!("Reacted with {emoji}")
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_safe_emojis_are_allowed() {
+        assert_eq!(AddReaction::validate(" 👍 "), Some("👍"));
+        // with or without the variation selector
+        assert_eq!(AddReaction::validate("❤"), Some("❤️"));
+        assert_eq!(AddReaction::validate("🍆"), None);
+        assert_eq!(AddReaction::validate("thumbs up"), None);
+    }
+
+    #[tokio::test]
+    async fn reactions_are_queued_for_the_handler() {
+        let pending = Arc::new(Mutex::new(vec![]));
+        let tool = AddReaction::new(pending.clone());
+
+        let refused = tool
+            .call(Args {
+                emoji: "🍆".to_string(),
+            })
+            .await
+            .unwrap();
+        assert!(
+            refused["add_reaction_result"]
+                .as_str()
+                .unwrap()
+                .starts_with("Cannot react")
+        );
+
+        tool.call(Args {
+            emoji: "🎉".to_string(),
+        })
+        .await
+        .unwrap();
+        assert_eq!(*pending.lock().unwrap(), ["🎉"]);
+    }
+}
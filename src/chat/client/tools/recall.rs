@@ -33,6 +33,9 @@ pub struct MemoryRecall {
 }
 
 impl MemoryRecall {
+    /// What the model is told about the tool in the system prompt.
+    pub const GUIDANCE: &'static str = "Actively try to utilize the memory_recall tool to recall information from previous messages and conversations you are not currently aware of. Do not mention this usage of the tool to the user, just use it when needed. If you believe a memory has already been recalled by the user (as seen in the \"relevant_memories\" section), choose not to recall it again.";
+
     pub fn new(
         model: Arc<Box<dyn DynEmbeddingModel>>,
         storage: Arc<MemoryStorage>,
@@ -50,8 +53,6 @@ impl MemoryRecall {
     }
 
     fn search(&self, args: Args) -> anyhow::Result<Vec<String>> {
-        println!("given args: {:?}", serde_json::to_string_pretty(&args)?);
-
         let embedded = tokio::task::block_in_place(|| {
             futures::executor::block_on(self.model.embed_text(&args.query))
         })?
@@ -61,11 +62,12 @@ impl MemoryRecall {
         .collect::<Vec<f32>>();
 
         tokio::task::block_in_place(|| {
-            futures::executor::block_on(self.storage.search(
+            futures::executor::block_on(self.storage.recall(
                 embedded,
                 self.user_id,
                 args.limit.unwrap_or(5),
                 args.threshold,
+                None,
             ))
         })
         .map(|mut x| {
@@ -113,22 +115,25 @@ impl Tool for MemoryRecall {
     }
 
     async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
-        log::info!("[memory_recall] querying vector db with \"{}\"", args.query);
+        // what was said only shows up at trace level, it's the user's conversation
+        log::info!("[memory_recall] querying vector db");
+        log::trace!("[memory_recall] query: \"{}\"", args.query);
         let results = self.search(args).map_err(|_| MemoryRecallError)?;
-        log::info!(
+        log::info!("[memory_recall] found {} memories", results.len());
+        log::trace!(
             "[memory_recall] results: {:?}",
             serde_json::to_string_pretty(&results)
         );
 
         if results.is_empty() {
-            return Ok(json!({
+            Ok(json!({
                 "memory_recall_result": "Could not find any relevant memories"
-            }));
+            }))
         } else {
-            return Ok(json!({
+            Ok(json!({
                 "memory_recall_result": "Found relevant memories",
                 "memories": results
-            }));
+            }))
         }
     }
 }
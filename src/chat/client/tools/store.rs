@@ -5,7 +5,7 @@ use serenity::all::UserId;
 use std::sync::Arc;
 
 use crate::chat::{
-    archive::storage::{Memory, MemoryStorage},
+    archive::storage::{Memory, MemoryStorage, with_placeholders},
     client::providers::DynEmbeddingModel,
 };
 
@@ -30,15 +30,21 @@ pub struct MemoryStore {
     user_name: String,
     #[serde(skip)]
     assistant_name: String,
+    #[serde(skip)]
+    assistant_aliases: Vec<String>,
 }
 
 impl MemoryStore {
+    /// What the model is told about the tool in the system prompt.
+    pub const GUIDANCE: &'static str = "Actively try to utilize the memory_store tool to store important information that you'd like to recall later in the long term memory storage, preferably in bullet points. Do not mention the usage of this tool to the user, just use it when needed.";
+
     pub fn new(
         model: Arc<Box<dyn DynEmbeddingModel>>,
         storage: Arc<MemoryStorage>,
         user_id: UserId,
         user_name: String,
         assistant_name: String,
+        assistant_aliases: Vec<String>,
     ) -> Self {
         Self {
             model,
@@ -46,13 +52,17 @@ impl MemoryStore {
             user_id,
             user_name,
             assistant_name,
+            assistant_aliases,
         }
     }
 
     fn store(&self, memory: &str) -> anyhow::Result<()> {
-        let memory = memory
-            .replace(self.user_name.as_str(), "<user>")
-            .replace(self.assistant_name.as_str(), "<assistant>");
+        let memory = with_placeholders(
+            memory,
+            &self.user_name,
+            &self.assistant_name,
+            &self.assistant_aliases,
+        );
 
         let Embedding { document, vec } = tokio::task::block_in_place(|| {
             futures::executor::block_on(self.model.embed_text(&memory))
@@ -95,12 +105,9 @@ impl Tool for MemoryStore {
     }
 
     async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
-        log::info!("[memory_store] saving memory:\n\"{}\"", args.memory);
-        let result = self.store(&args.memory).map_err(|_| MemoryStoreError)?;
-        log::info!(
-            "[memory_store] result: {:?}",
-            serde_json::to_string_pretty(&result)
-        );
+        log::info!("[memory_store] saving memory");
+        log::trace!("[memory_store] memory:\n\"{}\"", args.memory);
+        self.store(&args.memory).map_err(|_| MemoryStoreError)?;
         Ok(json!({
             "memory_store_result": "Memory store successful!"
         }))
@@ -0,0 +1,89 @@
+use std::{sync::LazyLock, time::Duration};
+
+use serde_json::{Value, json};
+
+use crate::config::structure::TtsConfig;
+
+static CLIENT: LazyLock<reqwest::Client> = LazyLock::new(|| {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(60))
+        .build()
+        .expect("http client builds")
+});
+
+/// Turns a reply into audio, in the configured format.
+pub async fn synthesize(config: &TtsConfig, text: &str) -> anyhow::Result<Vec<u8>> {
+    let max_chars = config.max_chars.unwrap_or(4096);
+    if text.chars().count() > max_chars {
+        anyhow::bail!("reply is longer than the {max_chars} characters read out loud");
+    }
+
+    let url = format!(
+        "{}/audio/speech",
+        config
+            .url
+            .as_deref()
+            .unwrap_or("https://api.openai.com/v1")
+            .trim_end_matches('/')
+    );
+
+    let response = CLIENT
+        .post(url)
+        .bearer_auth(&config.api_key)
+        .json(&request_body(config, text))
+        .send()
+        .await?
+        .error_for_status()?;
+
+    Ok(response.bytes().await?.to_vec())
+}
+
+fn request_body(config: &TtsConfig, text: &str) -> Value {
+    json!({
+        "model": config.model,
+        "input": text,
+        "voice": config.voice,
+        "response_format": config.format.as_deref().unwrap_or("mp3"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> TtsConfig {
+        TtsConfig {
+            enabled: true,
+            api_key: "key".to_string(),
+            model: "tts-1".to_string(),
+            voice: "alloy".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn requests_default_to_mp3() {
+        let body = request_body(&config(), "Hello!");
+
+        assert_eq!(
+            body,
+            json!({
+                "model": "tts-1",
+                "input": "Hello!",
+                "voice": "alloy",
+                "response_format": "mp3",
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn long_replies_are_not_read_out() {
+        let config = TtsConfig {
+            max_chars: Some(5),
+            ..config()
+        };
+
+        let why = synthesize(&config, "Hello there!").await.unwrap_err();
+        assert!(why.to_string().contains("longer than the 5 characters"));
+    }
+}
@@ -0,0 +1,165 @@
+use branch_context::Messages;
+
+use super::message::ChatMessage;
+
+/// Branch bookkeeping on top of `Messages`, which only exposes its branches by moving the selection
+/// around, so everything here walks them and puts the selection back where it was.
+pub trait Branches {
+    /// The selected branch (counting from 1) and how many branches there are.
+    fn position(&mut self) -> (usize, usize);
+
+    /// Drops the oldest branches until at most `max` are left. The selection stays on the same
+    /// branch, or moves to the oldest one left if its branch got dropped.
+    fn cap(&mut self, max: usize);
+}
+
+impl Branches for Messages<ChatMessage> {
+    fn position(&mut self) -> (usize, usize) {
+        let mut before = 0;
+        while self.backward {
+            self.backward();
+            before += 1;
+        }
+
+        let mut count = 1;
+        while self.forward {
+            self.forward();
+            count += 1;
+        }
+
+        for _ in 0..count - 1 - before {
+            self.backward();
+        }
+
+        (before + 1, count)
+    }
+
+    fn cap(&mut self, max: usize) {
+        let max = max.max(1);
+
+        let (selected, count) = self.position();
+        if count <= max {
+            return;
+        }
+
+        while self.backward {
+            self.backward();
+        }
+
+        let mut branches = vec![self.selected().clone()];
+        while self.forward {
+            self.forward();
+            branches.push(self.selected().clone());
+        }
+
+        let dropped = count - max;
+        log::info!("dropping the {dropped} oldest branches of a message");
+
+        let mut branches = branches.into_iter().skip(dropped);
+        let Some(first) = branches.next() else {
+            return;
+        };
+
+        // pushing selects, so this ends up on the newest branch
+        let mut capped = Messages::new(first.into());
+        for branch in branches {
+            capped.push(branch);
+        }
+
+        let selected = selected.saturating_sub(dropped).max(1);
+        for _ in 0..max - selected {
+            capped.backward();
+        }
+
+        *self = capped;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn branches(count: usize) -> Messages<ChatMessage> {
+        let mut messages = Messages::new(ChatMessage::assistant("1".into()).into());
+        for i in 2..=count {
+            messages.push(ChatMessage::assistant(i.to_string()));
+        }
+
+        messages
+    }
+
+    fn select(messages: &mut Messages<ChatMessage>, position: usize) {
+        while messages.backward {
+            messages.backward();
+        }
+        for _ in 1..position {
+            messages.forward();
+        }
+    }
+
+    fn selected(messages: &Messages<ChatMessage>) -> String {
+        messages.selected().content().unwrap()
+    }
+
+    #[test]
+    fn position_counts_from_one() {
+        let mut messages = branches(3);
+        assert_eq!(messages.position(), (3, 3));
+
+        select(&mut messages, 2);
+        assert_eq!(messages.position(), (2, 3));
+        // walking the branches puts the selection back
+        assert_eq!(selected(&messages), "2");
+    }
+
+    #[test]
+    fn cap_drops_the_oldest_branches() {
+        let mut messages = branches(5);
+        messages.cap(3);
+
+        assert_eq!(messages.position(), (3, 3));
+        assert_eq!(selected(&messages), "5");
+
+        select(&mut messages, 1);
+        assert_eq!(selected(&messages), "3");
+    }
+
+    #[test]
+    fn cap_keeps_the_selected_branch() {
+        let mut messages = branches(5);
+        select(&mut messages, 4);
+        messages.cap(3);
+
+        assert_eq!(messages.position(), (2, 3));
+        assert_eq!(selected(&messages), "4");
+    }
+
+    #[test]
+    fn cap_moves_a_dropped_selection_to_the_oldest_left() {
+        let mut messages = branches(5);
+        select(&mut messages, 1);
+        messages.cap(3);
+
+        assert_eq!(messages.position(), (1, 3));
+        assert_eq!(selected(&messages), "3");
+    }
+
+    #[test]
+    fn cap_always_keeps_one_branch() {
+        let mut messages = branches(3);
+        messages.cap(0);
+
+        assert_eq!(messages.position(), (1, 1));
+        assert_eq!(selected(&messages), "3");
+    }
+
+    #[test]
+    fn cap_leaves_fewer_branches_alone() {
+        let mut messages = branches(2);
+        select(&mut messages, 1);
+        messages.cap(3);
+
+        assert_eq!(messages.position(), (1, 2));
+        assert_eq!(selected(&messages), "1");
+    }
+}
@@ -1,15 +1,21 @@
-use std::{fs::File, hash::Hash, path::PathBuf};
+use std::{collections::HashMap, fs::File, hash::Hash, path::PathBuf};
 
 use anyhow::{Result, anyhow};
 use branch_context::{Message, Messages};
 use indexmap::IndexMap;
-use rig::message::{Message as RigMessage, UserContent};
+use rig::{
+    completion::Document,
+    message::{AssistantContent, Message as RigMessage, UserContent},
+};
 use serde::{Deserialize, Serialize};
-use serenity::all::{ChannelId, Http, Message as SerenityMessage, MessageId, UserId};
+use serenity::all::{ChannelId, MessageId, UserId};
 
-use crate::{config::structure::ContextConfig, utils};
+use crate::{chat::prompt::SystemPrompt, config::structure::ContextConfig, utils};
 
-use super::{MessageRole, message::ChatMessage};
+use super::{
+    Branches, MessageRole,
+    message::{ChatMessage, estimate_tokens},
+};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MessageIdentifier {
@@ -69,29 +75,15 @@ impl From<(MessageId, ChannelId, Vec<MessageId>)> for MessageIdentifier {
 }
 impl MessageIdentifier {
     pub fn random() -> Self {
-        let message_id = rand::random();
+        let message_id = utils::random::random();
         Self {
             message_id,
-            channel_id: rand::random(),
+            channel_id: utils::random::random(),
             random: true,
             message_ids: vec![message_id],
         }
     }
 
-    pub async fn to_message(&self, http: &Http) -> Option<SerenityMessage> {
-        if self.random {
-            log::warn!("random message {:?} requested", self);
-            return None;
-        }
-
-        http.get_message(self.channel_id.into(), self.message_id.into())
-            .await
-            .map_err(|why| {
-                log::error!("failed to get message: {why:?}");
-            })
-            .ok()
-    }
-
     pub fn channel(&self) -> ChannelId {
         ChannelId::new(self.channel_id)
     }
@@ -116,12 +108,63 @@ pub struct UserPrompt {
     pub time_since: String,
     pub relevant_memories: Vec<String>,
     pub system_note: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
     #[serde(skip)]
     pub freewill: bool,
 }
 
+/// System note sent when the bot speaks up on its own.
+const DEFAULT_FREEWILL_PROMPT: &str = "Please attempt to pull the user back into the conversation, making sure to keep the same tone and style as you normally would, following all previous instructions, yet keeping the time difference in mind. Your response should only contain the actual response, not your thoughts or anything else.";
+
+/// Free will stops speaking up once this many of its messages went unanswered.
+const DEFAULT_MAX_UNANSWERED_FREEWILL: usize = 3;
+
+/// System note sent when answering the messages that came in while paused.
+const CATCH_UP_PROMPT: &str = "You were away for a while and did not respond to the latest messages. Catch up on everything that was said since your last message, responding to it in a single message as you normally would.";
+
+/// Builds user prompts, stamped with the times of the context that started them.
+pub struct UserPromptBuilder {
+    prompt: UserPrompt,
+}
+
+impl UserPromptBuilder {
+    pub fn content(mut self, content: Option<String>) -> Self {
+        self.prompt.content = content;
+        self
+    }
+
+    pub fn system_note(mut self, system_note: Option<String>) -> Self {
+        self.prompt.system_note = system_note;
+        self
+    }
+
+    pub fn author(mut self, author: Option<String>) -> Self {
+        self.prompt.author = author;
+        self
+    }
+
+    pub fn freewill(mut self) -> Self {
+        self.prompt.freewill = true;
+        self
+    }
+
+    /// Fails if there's nothing to respond to, meaning neither a content nor a system note.
+    pub fn build(self) -> Result<UserPrompt> {
+        if self.prompt.content.is_none() && self.prompt.system_note.is_none() {
+            return Err(anyhow!(
+                "a user prompt needs either a content or a system note"
+            ));
+        }
+
+        Ok(self.prompt)
+    }
+}
+
 pub struct ChatContext {
     messages: IndexMap<MessageIdentifier, Messages<ChatMessage>>,
+    /// Documents attached to the conversation, only kept in memory.
+    documents: Vec<Document>,
     save_path: Option<PathBuf>,
     pub config: ContextConfig,
 }
@@ -131,6 +174,7 @@ impl TryInto<ChatMessage> for UserPrompt {
     fn try_into(self) -> Result<ChatMessage, Self::Error> {
         let mut message = ChatMessage::user(serde_json::to_string(&self)?);
         message.freewill = self.freewill;
+        message.author = self.author;
         Ok(message)
     }
 }
@@ -159,35 +203,89 @@ pub struct ContextWindow {
     pub system_prompt: String,
     pub history: Vec<ChatMessage>,
     pub overflow: Option<Vec<ChatMessage>>,
+    /// Messages that went over the summary token threshold, to be summarized separately.
+    pub summary_due: Option<Vec<ChatMessage>>,
+}
+
+/// Everything a completion request is made of, kept apart until it's sent so each part can be
+/// looked at (or left out) on its own.
+pub struct AssembledContext {
+    pub system_prompt: String,
+    pub history: Vec<ChatMessage>,
+    /// What long-term memory recalled for this turn.
+    pub memories: Vec<String>,
+    /// Notes injected into this turn, like free will's or the persona reminder.
+    pub system_note: Option<String>,
+    /// The turn itself, its memories and note are kept in the fields above.
+    pub prompt: UserPrompt,
+}
+
+impl AssembledContext {
+    pub fn new(system_prompt: String, history: Vec<ChatMessage>, mut prompt: UserPrompt) -> Self {
+        let memories = std::mem::take(&mut prompt.relevant_memories);
+        let system_note = prompt.system_note.take();
+
+        Self {
+            system_prompt,
+            history,
+            memories,
+            system_note,
+            prompt,
+        }
+    }
+
+    /// The turn as it's sent, with its memories and note back in.
+    pub fn user_prompt(&self) -> UserPrompt {
+        UserPrompt {
+            relevant_memories: self.memories.clone(),
+            system_note: self.system_note.clone(),
+            ..self.prompt.clone()
+        }
+    }
+
+    /// Roughly how many tokens the whole request takes up.
+    pub fn tokens(&self) -> Result<usize> {
+        Ok(estimate_tokens(&self.system_prompt)
+            + self
+                .history
+                .iter()
+                .map(|message| message.tokens())
+                .sum::<usize>()
+            + estimate_tokens(&serde_json::to_string(&self.user_prompt())?))
+    }
+
+    /// Flattens it into the preamble, chat history and prompt the API takes.
+    pub fn into_messages(self) -> Result<(String, Vec<RigMessage>, RigMessage)> {
+        let prompt: RigMessage = self.user_prompt().try_into()?;
+        let history = self.history.into_iter().map(Into::into).collect::<Vec<_>>();
+
+        Ok((self.system_prompt, history, prompt))
+    }
 }
 
 impl ChatContext {
     pub async fn new(config: &ContextConfig, user_id: UserId) -> Self {
         log::info!("creating new context");
 
-        let save_path = &config
-            .save_to_disk_folder
-            .as_ref()
-            .map(|path| {
-                if path.is_file() {
-                    std::fs::remove_file(&path)
-                        .map_err(|e| {
-                            log::error!("Failed to remove file: {e}");
-                            e
-                        })
-                        .ok()?;
-                }
-
-                std::fs::create_dir_all(&path)
+        let save_path = &config.save_to_disk_folder.as_ref().and_then(|path| {
+            if path.is_file() {
+                std::fs::remove_file(path)
                     .map_err(|e| {
-                        log::error!("Failed to create dir: {e}");
+                        log::error!("Failed to remove file: {e}");
                         e
                     })
                     .ok()?;
+            }
 
-                Some(path.join(format!("context-{}.bin", user_id)))
-            })
-            .flatten();
+            std::fs::create_dir_all(path)
+                .map_err(|e| {
+                    log::error!("Failed to create dir: {e}");
+                    e
+                })
+                .ok()?;
+
+            Some(path.join(format!("context-{}.bin", user_id)))
+        });
 
         let result = match save_path {
             Some(path) => {
@@ -212,6 +310,7 @@ impl ChatContext {
                             // get latest message and reenable buttons
                             let context = Self {
                                 messages,
+                                documents: vec![],
                                 save_path: save_path.clone(),
                                 config: config.clone(),
                             };
@@ -223,17 +322,57 @@ impl ChatContext {
             None => None,
         };
 
-        match result {
+        let mut context = match result {
             Some(future) => future.await.unwrap_or_else(|_: anyhow::Error| Self {
                 messages: IndexMap::new(),
+                documents: vec![],
                 save_path: save_path.clone(),
                 config: config.clone(),
             }),
             None => Self {
                 messages: IndexMap::new(),
+                documents: vec![],
                 save_path: save_path.clone(),
                 config: config.clone(),
             },
+        };
+
+        context.drop_dangling_tool_call();
+
+        if context.messages.is_empty() {
+            context.seed_greeting();
+        }
+
+        context
+    }
+
+    /// Restarting in the middle of a tool call leaves the call behind without its result,
+    /// which providers reject. There's no telling whether the tool already ran, so drop it.
+    fn drop_dangling_tool_call(&mut self) {
+        let dangling = self.messages.last().is_some_and(|(_, messages)| {
+            matches!(
+                &messages.selected().inner,
+                RigMessage::Assistant { content }
+                    if content.iter().any(|content| matches!(content, AssistantContent::ToolCall(_)))
+            )
+        });
+
+        if dangling {
+            log::warn!("dropping a tool call left without a result by the last shutdown");
+            self.messages.pop();
+        }
+    }
+
+    /// Seeds the configured greeting as the opening assistant message.
+    fn seed_greeting(&mut self) {
+        if let Some(greeting) = &self.config.greeting {
+            let greeting = self
+                .config
+                .system
+                .substitute(greeting, chrono::Duration::seconds(0));
+
+            log::info!("seeding greeting into fresh context");
+            self.add_message(ChatMessage::assistant(greeting), None);
         }
     }
 
@@ -245,8 +384,11 @@ impl ChatContext {
                 path.display()
             );
 
-            let file = File::options().write(true).create(true).open(path)?;
-            file.set_len(0)?;
+            let file = File::options()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(path)?;
             ciborium::into_writer(&self.messages, file)?;
         }
 
@@ -255,9 +397,28 @@ impl ChatContext {
 
     pub fn clear(&mut self) {
         self.messages.clear();
+        self.documents.clear();
         if let Some(path) = &self.save_path {
             std::fs::remove_file(path).ok();
         }
+
+        if self.config.greet_after_clear.unwrap_or(false) {
+            self.seed_greeting();
+        }
+    }
+
+    /// Attaches a document to the conversation, replacing an earlier one with the same name.
+    pub fn attach_document(&mut self, name: String, text: String) {
+        self.documents.retain(|document| document.id != name);
+        self.documents.push(Document {
+            id: name,
+            text,
+            additional_props: HashMap::new(),
+        });
+    }
+
+    pub fn documents(&self) -> &[Document] {
+        &self.documents
     }
 
     pub fn add_message(
@@ -320,6 +481,36 @@ impl ChatContext {
         self.messages.get_index_mut(index).map(|(_, m)| m)
     }
     /// Finds message with the given id, returning the index, the id, and the message itself.
+    /// Adds a new branch to a message and selects it, dropping the oldest ones past `max_branches`.
+    pub fn push_branch(&mut self, id: &MessageIdentifier, message: ChatMessage) -> Result<()> {
+        let max_branches = self.config.max_branches;
+
+        let messages = self
+            .find_mut(id)
+            .ok_or(anyhow!("message not found in engine"))?;
+
+        messages.push(message); // pushes and selects
+
+        if let Some(max_branches) = max_branches {
+            messages.cap(max_branches);
+        }
+
+        Ok(())
+    }
+
+    /// Where a branch pushed with `push_branch` would end up, as (selected, count).
+    pub fn new_branch_position(&mut self, id: &MessageIdentifier) -> Option<(usize, usize)> {
+        let max_branches = self.config.max_branches;
+        let (_, count) = self.find_mut(id)?.position();
+
+        let count = match max_branches {
+            Some(max_branches) => (count + 1).min(max_branches.max(1)),
+            None => count + 1,
+        };
+
+        Some((count, count))
+    }
+
     pub fn find_full(
         &self,
         id: &MessageIdentifier,
@@ -360,10 +551,107 @@ impl ChatContext {
         Ok(())
     }
 
+    /// Removes the user messages with any of the given ids (and the replies that followed them if
+    /// `with_replies`), returning how many messages were removed. The bot's own messages are left
+    /// alone, it deletes those itself when regenerating.
+    pub fn remove_deleted(
+        &mut self,
+        channel: ChannelId,
+        ids: &[MessageId],
+        with_replies: bool,
+    ) -> usize {
+        let before = self.messages.len();
+
+        let mut replying = false;
+        self.messages.retain(|id, messages| {
+            let role = messages.selected().role();
+            let deleted = role == MessageRole::User
+                && !id.random
+                && id.channel_id == channel.get()
+                && id.messages().iter().any(|id| ids.contains(id));
+            let reply = with_replies && replying && role == MessageRole::Assistant;
+
+            replying = deleted || reply;
+            !(deleted || reply)
+        });
+
+        before - self.messages.len()
+    }
+
+    /// Number of messages in the short-term memory.
+    pub fn message_count(&self) -> usize {
+        self.messages.len()
+    }
+
+    /// Estimated token count of the selected branch of the whole context.
+    pub fn tokens(&self) -> usize {
+        self.messages
+            .values()
+            .map(|messages| messages.selected().tokens())
+            .sum()
+    }
+
+    /// How many of the oldest messages have to go, either because there are too many of them
+    /// or because they take up too many tokens. Both drain down to 4/5 of their limit, then a bit
+    /// further so the cut lands on an exchange boundary.
+    fn overflow_count(&self) -> usize {
+        let mut to_remove = 0;
+
+        if self.messages.len() >= self.config.max_stm {
+            to_remove = self.messages.len() - ((self.config.max_stm * 4) / 5);
+        }
+
+        if let Some(max_tokens) = self.config.max_stm_tokens {
+            let mut tokens = self.tokens();
+
+            if tokens >= max_tokens {
+                let target = (max_tokens * 4) / 5;
+                let mut count = 0;
+
+                for messages in self.messages.values() {
+                    if tokens <= target {
+                        break;
+                    }
+                    tokens -= messages.selected().tokens();
+                    count += 1;
+                }
+
+                // always keep the latest message around
+                to_remove = to_remove.max(count.min(self.messages.len().saturating_sub(1)));
+            }
+        }
+
+        self.exchange_boundary(to_remove)
+    }
+
+    /// Moves a cut forward until the kept messages start with a user message, so no reply is
+    /// kept without the message it answers and no message is drained without its reply. Tool
+    /// results don't count, they stay with the calls they answer. The latest message is always kept.
+    fn exchange_boundary(&self, mut cut: usize) -> usize {
+        if cut == 0 {
+            return 0;
+        }
+
+        while cut < self.messages.len().saturating_sub(1) {
+            match self.messages.get_index(cut) {
+                Some((_, messages))
+                    if messages.selected().role() != MessageRole::User
+                        || messages.selected().is_tool_result() =>
+                {
+                    cut += 1
+                }
+                _ => break,
+            }
+        }
+
+        cut
+    }
+
     /// If STM is full, drain until STM is 80% of max_stm
     async fn drain_overflow(&mut self) -> Option<Vec<ChatMessage>> {
-        if self.messages.len() >= self.config.max_stm {
-            let to_remove = self.messages.len() - ((self.config.max_stm * 4) / 5);
+        let to_remove = self.overflow_count();
+
+        if to_remove > 0 {
             log::info!("context close to or full, draining {to_remove} messages");
 
             // set the latest message to be a "freewill" message
@@ -388,7 +676,46 @@ impl ChatContext {
         }
     }
 
-    async fn get_messages(&self) -> Vec<ChatMessage> {
+    /// Takes every message since the last token triggered summary once together they reach
+    /// the configured threshold, marking them so they don't get summarized again.
+    fn take_summary_due(&mut self) -> Option<Vec<ChatMessage>> {
+        let threshold = self.config.summarize_every_tokens?;
+
+        let pending = self
+            .messages
+            .values()
+            .rev()
+            .take_while(|messages| !messages.selected().summarized)
+            .count();
+        let start = self.messages.len() - pending;
+
+        let tokens: usize = self
+            .messages
+            .values()
+            .skip(start)
+            .map(|messages| messages.selected().tokens())
+            .sum();
+
+        if tokens < threshold {
+            return None;
+        }
+
+        log::info!("{tokens} tokens since the last summary, summarizing {pending} messages");
+
+        Some(
+            self.messages
+                .values_mut()
+                .skip(start)
+                .map(|messages| {
+                    let message = messages.mut_selected();
+                    message.summarized = true;
+                    message.clone()
+                })
+                .collect(),
+        )
+    }
+
+    pub async fn get_messages(&self) -> Vec<ChatMessage> {
         self.messages
             .iter()
             .map(|(_, messages)| messages.selected())
@@ -408,29 +735,29 @@ impl ChatContext {
             .collect::<Vec<_>>()
     }
 
-    pub async fn get_context(&mut self, user_prompt: Option<String>) -> Result<ContextWindow> {
+    pub async fn get_context(
+        &mut self,
+        user_prompt: Option<String>,
+        author: Option<String>,
+    ) -> Result<ContextWindow> {
         let user_prompt: Option<UserPrompt> = match user_prompt {
-            Some(prompt) => Some(UserPrompt {
-                content: Some(prompt),
-                current_time: self.config.system.get_time(),
-                relevant_memories: vec![],
-                time_since: utils::time_to_string(self.time_since_last()),
-                system_note: None,
-                freewill: false,
-            }),
+            Some(prompt) => Some(
+                self.prompt_builder()
+                    .content(Some(prompt))
+                    .system_note(self.persona_reminder())
+                    .author(self.group_author(author))
+                    .build()?,
+            ),
             None => None,
         };
 
         if self.messages.is_empty() {
-            let system_prompt = self
-                .config
-                .system
-                .clone()
-                .build(chrono::Duration::seconds(0));
+            let system_prompt = self.system_prompt(chrono::Duration::seconds(0));
 
             return Ok(ContextWindow {
                 history: vec![],
                 overflow: None,
+                summary_due: None,
                 system_prompt: system_prompt.to_string(),
                 user_prompt,
             });
@@ -439,15 +766,29 @@ impl ChatContext {
         // Add the messages
         let ctx = self.get_messages().await;
 
-        let drained = self.drain_overflow().await;
+        // whatever the token trigger already summarized doesn't need summarizing again
+        let drained = self
+            .drain_overflow()
+            .await
+            .filter(|_| self.config.summarize_on_drain.unwrap_or(true))
+            .map(|drained| {
+                drained
+                    .into_iter()
+                    .filter(|message| !message.summarized)
+                    .collect::<Vec<_>>()
+            })
+            .filter(|drained| !drained.is_empty());
+
+        let summary_due = self.take_summary_due();
 
-        let system_prompt = self.config.system.clone().build(self.time_since_last());
+        let system_prompt = self.system_prompt(self.time_since_last());
 
         Ok(ContextWindow {
             user_prompt,
             system_prompt: system_prompt.to_string(),
             history: ctx,
             overflow: drained,
+            summary_due,
         })
     }
 
@@ -485,7 +826,7 @@ impl ChatContext {
             .map(|idx| ctx.remove(idx))
             .ok_or_else(|| anyhow::anyhow!("No user text messages found for prompting"))?;
 
-        let system_prompt = self.config.system.clone().build(self.time_since_last());
+        let system_prompt = self.system_prompt(self.time_since_last());
 
         // if let Some(pos) = context.iter().rposition(|m| m.role == "assistant") {
         //     context.remove(pos);
@@ -496,6 +837,7 @@ impl ChatContext {
             history: ctx,
             system_prompt: system_prompt.to_string(),
             overflow: None, // there is no overflow when regenerating
+            summary_due: None,
         })
     }
 
@@ -503,36 +845,180 @@ impl ChatContext {
         let ContextWindow {
             history,
             overflow,
+            summary_due,
             system_prompt,
             ..
-        } = self.get_context(user_prompt).await?;
-
-        // let message = ChatMessage::user(format!(
-        //     "*it's been around {} since you last said something, and the user did not respond. your next response should attempt to pull the user back into the conversation. please respond once again, making sure to keep the same tone and style as you normally would, following all previous instructions, yet keeping the time difference in mind. your response should only contain the actual response, not your thoughts or anything else.*\n\n\"...\"",
-        //     utils::time_to_string(self.time_since_last()?)
-        // ));
-        let message = UserPrompt {
-            content: None,
-            current_time: self.config.system.get_time(),
-            relevant_memories: vec![],
-            time_since: utils::time_to_string(self.time_since_last()),
-            system_note: Some(
-                "Please attempt to pull the user back into the conversation, making sure to keep the same tone and style as you normally would, following all previous instructions, yet keeping the time difference in mind. Your response should only contain the actual response, not your thoughts or anything else.".to_string(),
-            ),
-            freewill: true,
-        };
+        } = self.get_context(user_prompt, None).await?;
+
+        let template = self
+            .config
+            .freewill_prompt
+            .as_deref()
+            .unwrap_or(DEFAULT_FREEWILL_PROMPT);
+        let note = self
+            .config
+            .system
+            .substitute(template, self.time_since_last());
+
+        let message = self
+            .prompt_builder()
+            .system_note(Some(note))
+            .freewill()
+            .build()?;
 
         // id-less message
         self.add_message(TryInto::<ChatMessage>::try_into(message.clone())?, None);
+        self.prune_freewill();
 
         Ok(ContextWindow {
             user_prompt: Some(message),
             history,
             system_prompt,
             overflow,
+            summary_due,
+        })
+    }
+
+    /// Whether free will already spoke up as often as it may without hearing back from the user.
+    pub fn freewill_exhausted(&self) -> bool {
+        self.unanswered_freewill().len() >= self.max_unanswered_freewill()
+    }
+
+    fn max_unanswered_freewill(&self) -> usize {
+        self.config
+            .max_unanswered_freewill
+            .unwrap_or(DEFAULT_MAX_UNANSWERED_FREEWILL)
+    }
+
+    /// Indexes of the free will prompts since the user last said something, newest first.
+    fn unanswered_freewill(&self) -> Vec<usize> {
+        self.messages
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(i, (id, messages))| (i, id, messages.selected()))
+            // up to the last user message that came from discord, free will prompts have no ids
+            .take_while(|(_, id, message)| id.random || message.role() != MessageRole::User)
+            .filter(|(_, _, message)| message.role() == MessageRole::User && message.freewill)
+            .map(|(i, _, _)| i)
+            .collect()
+    }
+
+    /// Drops the oldest unanswered free will prompts past the cap, along with what the bot said
+    /// after them, so a user who stays away doesn't come back to a pile of them.
+    fn prune_freewill(&mut self) {
+        // the prompt that was just added always stays
+        let max = self.max_unanswered_freewill().max(1);
+        let prompts = self.unanswered_freewill();
+        if prompts.len() <= max {
+            return;
+        }
+
+        let (start, end) = (prompts[prompts.len() - 1], prompts[max - 1]);
+        self.messages.drain(start..end);
+
+        log::info!(
+            "pruned {} unanswered freewill messages",
+            prompts.len() - max
+        );
+    }
+
+    /// The persona reminder, if the upcoming user turn is one of every `persona_reminder_every`.
+    fn persona_reminder(&self) -> Option<String> {
+        let every = self
+            .config
+            .persona_reminder_every
+            .filter(|every| *every > 0)?;
+
+        let turns = self
+            .messages
+            .values()
+            .map(|messages| messages.selected())
+            .filter(|message| {
+                message.role() == MessageRole::User
+                    && !message.freewill
+                    && message.content().is_some()
+            })
+            .count()
+            + 1;
+
+        if turns % every != 0 {
+            return None;
+        }
+
+        log::debug!("reminding the model of its persona on turn {turns}");
+
+        let time_since_last = self.time_since_last();
+        Some(match &self.config.persona_reminder {
+            Some(reminder) => self.config.system.substitute(reminder, time_since_last),
+            None => self.config.system.persona_reminder(time_since_last),
         })
     }
 
+    /// Starts a user prompt, with the current time and the time since the last message filled in.
+    pub fn prompt_builder(&self) -> UserPromptBuilder {
+        UserPromptBuilder {
+            prompt: UserPrompt {
+                content: None,
+                current_time: self.config.system.get_time(),
+                time_since: utils::time_to_string(self.time_since_last()),
+                relevant_memories: vec![],
+                system_note: None,
+                author: None,
+                freewill: false,
+            },
+        }
+    }
+
+    /// A window without new content, asking the model to respond to what it missed while paused.
+    pub async fn catch_up_context(&mut self) -> Result<ContextWindow> {
+        let mut window = self.get_context(None, None).await?;
+
+        window.user_prompt = Some(
+            self.prompt_builder()
+                .system_note(Some(CATCH_UP_PROMPT.to_string()))
+                .build()?,
+        );
+
+        Ok(window)
+    }
+
+    pub fn group_mode(&self) -> bool {
+        self.config.group_mode.unwrap_or(false)
+    }
+
+    /// Only keeps the author around in group mode, single user prompts stay as they were.
+    pub fn group_author(&self, author: Option<String>) -> Option<String> {
+        author.filter(|_| self.group_mode())
+    }
+
+    /// Everyone who spoke in the current context, most recent first.
+    fn participants(&self) -> Vec<String> {
+        let mut participants: Vec<String> = vec![];
+        let authors = self
+            .messages
+            .values()
+            .rev()
+            .filter_map(|messages| messages.selected().author.as_ref());
+
+        for author in authors {
+            if !participants.contains(author) {
+                participants.push(author.clone());
+            }
+        }
+
+        participants
+    }
+
+    fn system_prompt(&self, time_since_last: chrono::Duration) -> SystemPrompt {
+        let mut system = self.config.system.clone();
+        if self.group_mode() {
+            system.participants = Some(self.participants());
+        }
+
+        system.build(time_since_last)
+    }
+
     pub fn time_since_last(&self) -> chrono::Duration {
         let last = match self.latest() {
             Some(last) => last,
@@ -547,3 +1033,597 @@ impl ChatContext {
         self.config.system.add_long_term_memories(memories);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rig::{OneOrMany, message::ToolResultContent};
+
+    use super::*;
+
+    fn config(greeting: Option<&str>, greet_after_clear: bool) -> ContextConfig {
+        let mut config = ContextConfig {
+            greeting: greeting.map(str::to_string),
+            greet_after_clear: Some(greet_after_clear),
+            ..Default::default()
+        };
+        config.system.user_name = "Alice".to_string();
+        config.system.chatbot_name = "Botty".to_string();
+        config
+    }
+
+    fn greeting(context: &ChatContext) -> Option<String> {
+        let message = context.latest()?.selected();
+        assert!(message.role() == MessageRole::Assistant);
+        message.content()
+    }
+
+    #[tokio::test]
+    async fn fresh_contexts_open_with_the_greeting() {
+        let context = ChatContext::new(
+            &config(Some("Hi {user}, I'm {bot}!"), false),
+            UserId::new(1),
+        )
+        .await;
+
+        assert_eq!(context.messages.len(), 1);
+        assert_eq!(greeting(&context).as_deref(), Some("Hi Alice, I'm Botty!"));
+    }
+
+    #[tokio::test]
+    async fn no_greeting_leaves_contexts_empty() {
+        let context = ChatContext::new(&config(None, true), UserId::new(1)).await;
+
+        assert_eq!(context.messages.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn clearing_only_greets_again_when_configured() {
+        let mut context = ChatContext::new(&config(Some("Hello!"), false), UserId::new(1)).await;
+        context.clear();
+        assert_eq!(context.messages.len(), 0);
+
+        let mut context = ChatContext::new(&config(Some("Hello!"), true), UserId::new(1)).await;
+        context.add_message(ChatMessage::user("hi".to_string()), None);
+        context.clear();
+        assert_eq!(context.messages.len(), 1);
+        assert_eq!(greeting(&context).as_deref(), Some("Hello!"));
+    }
+
+    fn spoken_by(content: &str, author: Option<&str>) -> ChatMessage {
+        let mut message = ChatMessage::user(content.to_string());
+        message.author = author.map(str::to_string);
+        message
+    }
+
+    #[tokio::test]
+    async fn authors_are_only_kept_in_group_mode() {
+        let mut config = config(None, false);
+        let context = ChatContext::new(&config, UserId::new(1)).await;
+        assert_eq!(context.group_author(Some("Bob".to_string())), None);
+
+        config.group_mode = Some(true);
+        let context = ChatContext::new(&config, UserId::new(1)).await;
+        assert_eq!(
+            context.group_author(Some("Bob".to_string())).as_deref(),
+            Some("Bob")
+        );
+    }
+
+    #[tokio::test]
+    async fn participants_are_listed_most_recent_first() {
+        let mut config = config(None, false);
+        config.group_mode = Some(true);
+        let mut context = ChatContext::new(&config, UserId::new(1)).await;
+
+        context.add_message(spoken_by("hi", Some("Bob")), None);
+        context.add_message(spoken_by("hey", Some("Carol")), None);
+        context.add_message(ChatMessage::assistant("hello both".to_string()), None);
+        context.add_message(spoken_by("sup", Some("Bob")), None);
+
+        assert_eq!(context.participants(), ["Bob", "Carol"]);
+
+        let prompt = context.system_prompt(chrono::Duration::zero()).to_string();
+        assert!(prompt.contains("People in Conversation"));
+        assert!(prompt.contains("- Bob\n- Carol"));
+    }
+
+    #[tokio::test]
+    async fn single_user_prompts_have_no_participants() {
+        let mut context = ChatContext::new(&config(None, false), UserId::new(1)).await;
+        context.add_message(spoken_by("hi", Some("Bob")), None);
+
+        let prompt = context.system_prompt(chrono::Duration::zero()).to_string();
+        assert!(!prompt.contains("People in Conversation"));
+    }
+
+    fn sized(tokens: usize) -> ChatMessage {
+        ChatMessage::user("a".repeat(tokens * 4))
+    }
+
+    #[tokio::test]
+    async fn overflowing_the_message_limit_drains_to_four_fifths() {
+        let mut config = config(None, false);
+        config.max_stm = 10;
+        let mut context = ChatContext::new(&config, UserId::new(1)).await;
+
+        for _ in 0..9 {
+            context.add_message(sized(1), None);
+        }
+        assert_eq!(context.overflow_count(), 0);
+
+        context.add_message(sized(1), None);
+        assert_eq!(context.overflow_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn overflowing_the_token_limit_drains_the_oldest_messages() {
+        let mut config = config(None, false);
+        config.max_stm = 100;
+        config.max_stm_tokens = Some(100);
+        let mut context = ChatContext::new(&config, UserId::new(1)).await;
+
+        context.add_message(sized(40), None);
+        context.add_message(sized(30), None);
+        context.add_message(sized(20), None);
+        assert_eq!(context.tokens(), 90);
+        assert_eq!(context.overflow_count(), 0);
+
+        context.add_message(sized(15), None);
+        // dropping the first message alone gets to 65, under the 80 token target
+        assert_eq!(context.overflow_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn the_latest_message_is_never_drained_for_tokens() {
+        let mut config = config(None, false);
+        config.max_stm = 100;
+        config.max_stm_tokens = Some(10);
+        let mut context = ChatContext::new(&config, UserId::new(1)).await;
+
+        context.add_message(sized(50), None);
+        assert_eq!(context.overflow_count(), 0);
+
+        context.add_message(sized(50), None);
+        assert_eq!(context.overflow_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn summaries_are_due_once_the_token_threshold_piles_up() {
+        let mut config = config(None, false);
+        config.max_stm = 100;
+        config.summarize_every_tokens = Some(50);
+        let mut context = ChatContext::new(&config, UserId::new(1)).await;
+
+        context.add_message(sized(30), None);
+        assert!(context.take_summary_due().is_none());
+
+        context.add_message(sized(20), None);
+        let due = context.take_summary_due().unwrap();
+        assert_eq!(due.len(), 2);
+        assert!(due.iter().all(|message| message.summarized));
+
+        // only what came after the last summary counts towards the next one
+        context.add_message(sized(40), None);
+        assert!(context.take_summary_due().is_none());
+        context.add_message(sized(10), None);
+        assert_eq!(context.take_summary_due().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn no_threshold_means_no_token_summaries() {
+        let mut config = config(None, false);
+        config.max_stm = 100;
+        let mut context = ChatContext::new(&config, UserId::new(1)).await;
+
+        context.add_message(sized(10_000), None);
+        assert!(context.take_summary_due().is_none());
+    }
+
+    #[tokio::test]
+    async fn documents_are_replaced_by_name_and_cleared_with_the_context() {
+        let mut context = ChatContext::new(&config(None, false), UserId::new(1)).await;
+
+        context.attach_document("notes.txt".to_string(), "old".to_string());
+        context.attach_document("todo.md".to_string(), "- tea".to_string());
+        context.attach_document("notes.txt".to_string(), "new".to_string());
+
+        let documents = context
+            .documents()
+            .iter()
+            .map(|document| (document.id.as_str(), document.text.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(documents, [("todo.md", "- tea"), ("notes.txt", "new")]);
+
+        context.clear();
+        assert!(context.documents().is_empty());
+    }
+
+    #[tokio::test]
+    async fn the_persona_is_recalled_every_few_user_turns() {
+        let mut config = config(None, false);
+        config.max_stm = 100;
+        config.persona_reminder_every = Some(2);
+        config.system.tone = Some("cheerful".to_string());
+        let mut context = ChatContext::new(&config, UserId::new(1)).await;
+
+        assert_eq!(context.persona_reminder(), None);
+
+        context.add_message(ChatMessage::user("hi".to_string()), None);
+        context.add_message(ChatMessage::assistant("hello".to_string()), None);
+        assert_eq!(
+            context.persona_reminder().as_deref(),
+            Some(
+                "Remember that you are Botty. Stay in character and keep the same personality as before. Your tone: cheerful"
+            )
+        );
+
+        // freewill turns aren't the user's, so the next one is still due a reminder
+        let mut freewill = ChatMessage::user("...".to_string());
+        freewill.freewill = true;
+        context.add_message(freewill, None);
+        assert!(context.persona_reminder().is_some());
+
+        context.add_message(ChatMessage::user("how are you".to_string()), None);
+        assert_eq!(context.persona_reminder(), None);
+    }
+
+    #[tokio::test]
+    async fn custom_persona_reminders_are_substituted() {
+        let mut config = config(None, false);
+        config.persona_reminder_every = Some(1);
+        config.persona_reminder = Some("You are {bot}, talking to {user}.".to_string());
+        let context = ChatContext::new(&config, UserId::new(1)).await;
+
+        assert_eq!(
+            context.persona_reminder().as_deref(),
+            Some("You are Botty, talking to Alice.")
+        );
+    }
+
+    #[tokio::test]
+    async fn reminders_are_off_unless_configured() {
+        let mut config = config(None, false);
+        config.persona_reminder_every = Some(0);
+        let context = ChatContext::new(&config, UserId::new(1)).await;
+
+        assert_eq!(context.persona_reminder(), None);
+    }
+
+    #[tokio::test]
+    async fn prompts_need_something_to_respond_to() {
+        let context = ChatContext::new(&config(None, false), UserId::new(1)).await;
+
+        assert!(context.prompt_builder().build().is_err());
+        assert!(
+            context
+                .prompt_builder()
+                .system_note(Some("say hi".to_string()))
+                .build()
+                .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    async fn built_prompts_keep_what_was_set() {
+        let context = ChatContext::new(&config(None, false), UserId::new(1)).await;
+
+        let prompt = context
+            .prompt_builder()
+            .content(Some("hi".to_string()))
+            .author(Some("Bob".to_string()))
+            .freewill()
+            .build()
+            .unwrap();
+
+        assert_eq!(prompt.content.as_deref(), Some("hi"));
+        assert_eq!(prompt.author.as_deref(), Some("Bob"));
+        assert!(prompt.freewill);
+        assert!(prompt.relevant_memories.is_empty());
+        assert!(!prompt.current_time.is_empty());
+    }
+
+    async fn freewill_note(freewill_prompt: Option<&str>) -> Option<String> {
+        let mut config = config(None, false);
+        config.max_stm = 100;
+        config.freewill_prompt = freewill_prompt.map(str::to_string);
+        let mut context = ChatContext::new(&config, UserId::new(1)).await;
+        context.add_message(ChatMessage::user("hi".to_string()), None);
+
+        let window = context.freewill_context(None).await.unwrap();
+        let prompt = window.user_prompt.unwrap();
+        assert!(prompt.freewill);
+        assert!(context.latest().unwrap().selected().freewill);
+
+        prompt.system_note
+    }
+
+    #[tokio::test]
+    async fn freewill_prompts_are_filled_in_from_the_template() {
+        assert_eq!(
+            freewill_note(Some("{user} went quiet, {bot} speaks up"))
+                .await
+                .as_deref(),
+            Some("Alice went quiet, Botty speaks up")
+        );
+        assert_eq!(
+            freewill_note(None).await.as_deref(),
+            Some(DEFAULT_FREEWILL_PROMPT)
+        );
+    }
+
+    fn tool_call() -> ChatMessage {
+        RigMessage::Assistant {
+            content: rig::OneOrMany::one(AssistantContent::ToolCall(rig::message::ToolCall {
+                id: "call".to_string(),
+                function: rig::message::ToolFunction {
+                    name: "memory_recall".to_string(),
+                    arguments: serde_json::json!({ "query": "tea" }),
+                },
+            })),
+        }
+        .into()
+    }
+
+    #[tokio::test]
+    async fn tool_calls_cut_off_by_a_restart_are_dropped() {
+        let mut context = ChatContext::new(&config(None, false), UserId::new(1)).await;
+        context.add_message(ChatMessage::user("hi".to_string()), None);
+        context.add_message(tool_call(), None);
+
+        context.drop_dangling_tool_call();
+
+        assert_eq!(context.messages.len(), 1);
+        assert!(context.latest().unwrap().selected().role() == MessageRole::User);
+    }
+
+    #[tokio::test]
+    async fn answered_tool_calls_are_kept() {
+        let mut context = ChatContext::new(&config(None, false), UserId::new(1)).await;
+        context.add_message(tool_call(), None);
+        context.add_message(
+            ChatMessage::from(RigMessage::User {
+                content: rig::OneOrMany::one(UserContent::tool_result(
+                    "call",
+                    rig::OneOrMany::one(rig::message::ToolResultContent::text("- likes tea")),
+                )),
+            }),
+            None,
+        );
+        context.add_message(ChatMessage::assistant("you like tea".to_string()), None);
+
+        context.drop_dangling_tool_call();
+
+        assert_eq!(context.messages.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn new_branches_are_selected_and_capped() {
+        let mut config = config(None, false);
+        config.max_branches = Some(2);
+        let mut context = ChatContext::new(&config, UserId::new(1)).await;
+
+        let id: MessageIdentifier = (MessageId::new(1), ChannelId::new(1)).into();
+        context.add_message(ChatMessage::assistant("first".to_string()), id.clone());
+
+        assert_eq!(context.new_branch_position(&id), Some((2, 2)));
+        context
+            .push_branch(&id, ChatMessage::assistant("second".to_string()))
+            .unwrap();
+
+        // already at the cap, the next one replaces the oldest
+        assert_eq!(context.new_branch_position(&id), Some((2, 2)));
+        context
+            .push_branch(&id, ChatMessage::assistant("third".to_string()))
+            .unwrap();
+
+        let messages = context.find_mut(&id).unwrap();
+        assert_eq!(messages.position(), (2, 2));
+        assert_eq!(messages.selected().content().as_deref(), Some("third"));
+        messages.backward();
+        assert_eq!(messages.selected().content().as_deref(), Some("second"));
+    }
+
+    #[tokio::test]
+    async fn unknown_messages_cant_be_branched() {
+        let mut context = ChatContext::new(&config(None, false), UserId::new(1)).await;
+        let id: MessageIdentifier = (MessageId::new(1), ChannelId::new(1)).into();
+
+        assert_eq!(context.new_branch_position(&id), None);
+        assert!(
+            context
+                .push_branch(&id, ChatMessage::assistant("hi".to_string()))
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn catching_up_answers_what_came_in_while_paused() {
+        let mut config = config(None, false);
+        config.max_stm = 100;
+        let mut context = ChatContext::new(&config, UserId::new(1)).await;
+        context.add_message(ChatMessage::user("are you there?".to_string()), None);
+        context.add_message(ChatMessage::user("hello??".to_string()), None);
+
+        let window = context.catch_up_context().await.unwrap();
+
+        let prompt = window.user_prompt.unwrap();
+        assert_eq!(prompt.content, None);
+        assert_eq!(prompt.system_note.as_deref(), Some(CATCH_UP_PROMPT));
+        assert_eq!(window.history.len(), 2);
+        // nothing new is added, the missed messages are already in the context
+        assert_eq!(context.messages.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn deleted_messages_are_removed_with_their_replies() {
+        let mut config = config(None, false);
+        config.max_stm = 100;
+        let mut context = ChatContext::new(&config, UserId::new(1)).await;
+        let id = |id: u64| -> MessageIdentifier { (MessageId::new(id), ChannelId::new(1)).into() };
+
+        context.add_message(ChatMessage::user("hi".to_string()), id(1));
+        context.add_message(ChatMessage::assistant("hello".to_string()), id(2));
+        context.add_message(ChatMessage::user("oops".to_string()), id(3));
+        context.add_message(ChatMessage::assistant("what?".to_string()), id(4));
+
+        // other channels and the bot's own messages are left alone
+        assert_eq!(
+            context.remove_deleted(ChannelId::new(2), &[MessageId::new(1)], false),
+            0
+        );
+        assert_eq!(
+            context.remove_deleted(ChannelId::new(1), &[MessageId::new(2)], true),
+            0
+        );
+
+        assert_eq!(
+            context.remove_deleted(ChannelId::new(1), &[MessageId::new(3)], true),
+            2
+        );
+        assert_eq!(
+            context.remove_deleted(ChannelId::new(1), &[MessageId::new(1)], false),
+            1
+        );
+        let messages = context.get_messages().await;
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].content().as_deref(), Some("hello"));
+    }
+
+    async fn exchanges(messages: &[(&str, &str)]) -> ChatContext {
+        let mut config = config(None, false);
+        config.max_stm = 100;
+        let mut context = ChatContext::new(&config, UserId::new(1)).await;
+
+        for (role, content) in messages {
+            let message = match *role {
+                "user" => ChatMessage::user(content.to_string()),
+                "tool" => RigMessage::User {
+                    content: OneOrMany::one(UserContent::tool_result(
+                        "call",
+                        OneOrMany::one(ToolResultContent::text(*content)),
+                    )),
+                }
+                .into(),
+                _ => ChatMessage::assistant(content.to_string()),
+            };
+            context.add_message(message, None);
+        }
+
+        context
+    }
+
+    #[tokio::test]
+    async fn drains_stop_in_front_of_a_user_message() {
+        let context = exchanges(&[
+            ("user", "hi"),
+            ("assistant", "hello"),
+            ("user", "how are you"),
+            ("assistant", "good"),
+        ])
+        .await;
+
+        assert_eq!(context.exchange_boundary(0), 0);
+        // the reply goes along with the message it answers
+        assert_eq!(context.exchange_boundary(1), 2);
+        assert_eq!(context.exchange_boundary(2), 2);
+    }
+
+    #[tokio::test]
+    async fn drains_keep_tool_results_with_their_call() {
+        let context = exchanges(&[
+            ("user", "what did I say yesterday"),
+            ("assistant", "let me check"),
+            ("tool", "hi"),
+            ("assistant", "you said hi"),
+            ("user", "thanks"),
+            ("assistant", "anytime"),
+        ])
+        .await;
+
+        // the tool result is sent as a user message, but the exchange goes on past it
+        assert_eq!(context.exchange_boundary(1), 4);
+        assert_eq!(context.exchange_boundary(2), 4);
+        assert_eq!(context.exchange_boundary(3), 4);
+    }
+
+    #[tokio::test]
+    async fn drains_keep_the_latest_message() {
+        let context = exchanges(&[
+            ("user", "hi"),
+            ("assistant", "hello"),
+            ("assistant", "anyone there?"),
+        ])
+        .await;
+
+        assert_eq!(context.exchange_boundary(1), 2);
+        assert_eq!(context.exchange_boundary(2), 2);
+    }
+
+    #[tokio::test]
+    async fn unanswered_freewill_is_capped() {
+        let mut config = config(None, false);
+        config.max_stm = 50;
+        config.max_unanswered_freewill = Some(2);
+        let mut context = ChatContext::new(&config, UserId::new(1)).await;
+        context.add_message(
+            ChatMessage::user("bye for now".to_string()),
+            (MessageId::new(1), ChannelId::new(1)),
+        );
+        assert!(!context.freewill_exhausted());
+
+        for reply in ["you there?", "hello?", "still around?"] {
+            context.freewill_context(None).await.unwrap();
+            context.add_message(ChatMessage::assistant(reply.to_string()), None);
+        }
+
+        // the oldest prompt and its reply went, the user's message stays
+        let contents = context
+            .messages
+            .values()
+            .map(|messages| messages.selected())
+            .filter(|message| !message.freewill)
+            .filter_map(|message| message.content())
+            .collect::<Vec<_>>();
+        assert_eq!(contents, ["bye for now", "hello?", "still around?"]);
+        assert!(context.freewill_exhausted());
+
+        // hearing back from the user lets free will speak up again
+        context.add_message(
+            ChatMessage::user("back!".to_string()),
+            (MessageId::new(2), ChannelId::new(1)),
+        );
+        assert!(!context.freewill_exhausted());
+    }
+
+    #[test]
+    fn assembled_turns_are_sent_as_they_came_in() {
+        let prompt = UserPrompt {
+            content: Some("What's my cat called?".to_string()),
+            current_time: "2025-01-01 12:00".to_string(),
+            time_since: "5 minutes".to_string(),
+            relevant_memories: vec!["Alice has a cat called Tom".to_string()],
+            system_note: Some("Be kind.".to_string()),
+            author: None,
+            freewill: false,
+        };
+        let history = vec![ChatMessage::user("hi".to_string())];
+
+        let assembled = AssembledContext::new("preamble".to_string(), history, prompt.clone());
+        assert_eq!(assembled.memories, ["Alice has a cat called Tom"]);
+        assert_eq!(assembled.system_note.as_deref(), Some("Be kind."));
+        assert!(assembled.prompt.relevant_memories.is_empty());
+
+        let sent = serde_json::to_string(&assembled.user_prompt()).unwrap();
+        assert_eq!(sent, serde_json::to_string(&prompt).unwrap());
+        assert_eq!(
+            assembled.tokens().unwrap(),
+            estimate_tokens("preamble")
+                + ChatMessage::user("hi".to_string()).tokens()
+                + estimate_tokens(&sent)
+        );
+
+        let (preamble, history, _) = assembled.into_messages().unwrap();
+        assert_eq!(preamble, "preamble");
+        assert_eq!(history.len(), 1);
+    }
+}
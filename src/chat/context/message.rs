@@ -1,15 +1,31 @@
-use std::fmt::Display;
+use std::{fmt::Display, sync::OnceLock};
 
 use branch_context::{Message, Messages};
 use chrono::{DateTime, Utc};
 use rig::message::{AssistantContent, Message as RigMessage, UserContent};
 use serde::{Deserialize, Serialize};
 
+use super::UserPrompt;
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ChatMessage {
     pub inner: RigMessage,
     pub sent_at: DateTime<Utc>,
     pub freewill: bool,
+    /// Display name of who sent the message, only tracked in group mode.
+    #[serde(default)]
+    pub author: Option<String>,
+    /// Model that generated the message, if it wasn't the configured one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// Whether the message was already summarized by the token threshold trigger.
+    #[serde(default)]
+    pub summarized: bool,
+    /// Recalled memories the reply says it relied on, only kept around until it's sent.
+    #[serde(skip)]
+    pub cited_memories: Vec<String>,
+    #[serde(skip)]
+    tokens: OnceLock<usize>,
 }
 
 #[derive(PartialEq, Eq)]
@@ -33,6 +49,11 @@ impl ChatMessage {
             inner: RigMessage::assistant(content),
             sent_at: Utc::now(),
             freewill: false,
+            author: None,
+            model: None,
+            summarized: false,
+            cited_memories: vec![],
+            tokens: OnceLock::new(),
         }
     }
 
@@ -41,6 +62,11 @@ impl ChatMessage {
             inner: RigMessage::user(content),
             sent_at: Utc::now(),
             freewill: false,
+            author: None,
+            model: None,
+            summarized: false,
+            cited_memories: vec![],
+            tokens: OnceLock::new(),
         }
     }
 
@@ -67,12 +93,44 @@ impl ChatMessage {
         }
     }
 
+    /// Rough estimate of how many tokens the message takes up, computed once and cached.
+    /// User prompts only count what they render to, not the JSON they're wrapped in.
+    pub fn tokens(&self) -> usize {
+        *self.tokens.get_or_init(|| {
+            let text = match self.content() {
+                Some(content) => match serde_json::from_str::<UserPrompt>(&content) {
+                    Ok(prompt) => [
+                        prompt.content.unwrap_or_default(),
+                        prompt.system_note.unwrap_or_default(),
+                        prompt.relevant_memories.join("\n"),
+                    ]
+                    .join("\n"),
+                    Err(_) => content,
+                },
+                // tool calls and results, count them as they get sent
+                None => serde_json::to_string(&self.inner).unwrap_or_default(),
+            };
+
+            estimate_tokens(&text)
+        })
+    }
+
     pub fn role(&self) -> MessageRole {
         match &self.inner {
             RigMessage::User { .. } => MessageRole::User,
             RigMessage::Assistant { .. } => MessageRole::Assistant,
         }
     }
+
+    /// Tool results are sent as user messages, but they belong to the assistant's turn.
+    pub fn is_tool_result(&self) -> bool {
+        match &self.inner {
+            RigMessage::User { content } => content
+                .iter()
+                .any(|content| matches!(content, UserContent::ToolResult(_))),
+            RigMessage::Assistant { .. } => false,
+        }
+    }
 }
 
 impl Default for ChatMessage {
@@ -81,26 +139,31 @@ impl Default for ChatMessage {
             inner: RigMessage::user(""),
             sent_at: Utc::now(),
             freewill: false,
+            author: None,
+            model: None,
+            summarized: false,
+            cited_memories: vec![],
+            tokens: OnceLock::new(),
         }
     }
 }
 
-impl Into<ChatMessage> for &Messages<ChatMessage> {
-    fn into(self) -> ChatMessage {
+impl From<&Messages<ChatMessage>> for ChatMessage {
+    fn from(messages: &Messages<ChatMessage>) -> Self {
         // &self.selected().clone() ends up being a cheaper clone than self.into_selected()
-        self.selected().clone()
+        messages.selected().clone()
     }
 }
 
-impl Into<Message<ChatMessage>> for ChatMessage {
-    fn into(self) -> Message<ChatMessage> {
-        Message::new(self)
+impl From<ChatMessage> for Message<ChatMessage> {
+    fn from(message: ChatMessage) -> Self {
+        Message::new(message)
     }
 }
 
-impl Into<RigMessage> for ChatMessage {
-    fn into(self) -> RigMessage {
-        self.inner
+impl From<ChatMessage> for RigMessage {
+    fn from(message: ChatMessage) -> Self {
+        message.inner
     }
 }
 
@@ -110,6 +173,49 @@ impl From<RigMessage> for ChatMessage {
             inner: message,
             sent_at: Utc::now(),
             freewill: false,
+            author: None,
+            model: None,
+            summarized: false,
+            cited_memories: vec![],
+            tokens: OnceLock::new(),
         }
     }
 }
+
+/// Estimates the token count of a text, assuming roughly four characters per token
+/// (which holds up well enough for english text on most tokenizers).
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_are_estimated_from_characters() {
+        assert_eq!(estimate_tokens(""), 0);
+        assert_eq!(estimate_tokens("abcd"), 1);
+        assert_eq!(estimate_tokens("abcde"), 2);
+        assert_eq!(estimate_tokens("ääää"), 1);
+    }
+
+    #[test]
+    fn prompts_only_count_what_they_render_to() {
+        let prompt = UserPrompt {
+            content: Some("a".repeat(40)),
+            current_time: "2025-01-01 12:00".to_string(),
+            time_since: "5 minutes".to_string(),
+            relevant_memories: vec![],
+            system_note: None,
+            author: None,
+            freewill: false,
+        };
+        let json = serde_json::to_string(&prompt).unwrap();
+        let message = ChatMessage::user(json.clone());
+
+        assert!(estimate_tokens(&json) > 10);
+        // the content plus the joining newlines of the empty note and memories
+        assert_eq!(message.tokens(), 11);
+    }
+}
@@ -1,5 +1,9 @@
+mod branches;
+#[allow(clippy::module_inception)]
 mod context;
 mod message;
 
-pub use context::{ChatContext, ContextWindow, MessageIdentifier, UserPrompt};
-pub use message::{ChatMessage, MessageRole};
+pub use branches::Branches;
+
+pub use context::{AssembledContext, ChatContext, ContextWindow, MessageIdentifier, UserPrompt};
+pub use message::{ChatMessage, MessageRole, estimate_tokens};
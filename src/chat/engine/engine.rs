@@ -1,83 +1,239 @@
-use std::ops::{Deref, DerefMut};
+use std::{
+    collections::HashSet,
+    ops::{Deref, DerefMut},
+    time::Instant,
+};
 
 use anyhow::anyhow;
 use serenity::all::UserId;
 
 use crate::{
     chat::{
-        client::{CompletionAgent, CompletionResult},
-        context::{ContextWindow, MessageIdentifier},
+        audit::transcript::ConversationLog,
+        client::{CompletionAgent, CompletionResult, CompletionTimeout},
+        context::{ContextWindow, MessageIdentifier, MessageRole, UserPrompt},
+        prompt::SystemPromptBuilder,
+    },
+    config::{
+        store::ChatBotConfig,
+        structure::{ChatBotConfigInner, ConversationLogConfig, UserPreferences},
     },
-    config::{store::ChatBotConfig, structure::ChatBotConfigInner},
+    utils,
 };
 
 use super::super::context::{ChatContext, ChatMessage};
 
+/// Sent along when regenerating a reply that repeated the previous one.
+const VARY_NUDGE: &str = "Your previous reply was nearly identical to the one before it. Respond differently this time, don't repeat yourself.";
+
+/// Sent along with the operator's instructions for a broadcast.
+const BROADCAST_NOTE: &str = "Write a message to post in a public channel, addressed to everyone there rather than a single user. Stay in character and only respond with the message itself. What the message should be about:";
+
 pub struct ChatEngine {
     pub client: CompletionAgent,
     user_id: UserId,
     context: ChatContext,
+    transcript: Option<ConversationLog>,
 }
 
 impl ChatEngine {
     pub async fn new(config: ChatBotConfig, user_id: UserId) -> anyhow::Result<Self> {
-        let ChatBotConfigInner {
-            context: context_config,
-            llm: llm_config,
-            ..
-        } = config.into_inner();
+        let config = Self::prepare_config(config);
 
-        let context = ChatContext::new(&context_config, user_id).await;
+        let system = &config.context.system;
         let client = CompletionAgent::new(
-            llm_config,
+            config.llm.clone(),
             user_id,
-            context_config.system.user_name,
-            context_config.system.chatbot_name,
+            system.user_name.clone(),
+            system.chatbot_name.clone(),
+            system.chatbot_aliases.clone().unwrap_or_default(),
         )
         .await?;
 
+        Self::with_client(config, user_id, client).await
+    }
+
+    /// Puts the engine together around a client that's already built from (or for) the config.
+    pub(super) async fn with_client(
+        config: ChatBotConfigInner,
+        user_id: UserId,
+        client: CompletionAgent,
+    ) -> anyhow::Result<Self> {
+        let preferences = config.preferences(user_id);
+        let ChatBotConfigInner {
+            context: mut context_config,
+            conversation_log,
+            ..
+        } = config;
+
+        Self::apply_preferences(&mut context_config.system, preferences);
+
+        let transcript = Self::transcript(conversation_log, user_id)?;
+        let context = ChatContext::new(&context_config, user_id).await;
+
         Ok(Self {
             client,
             context,
             user_id,
+            transcript,
         })
     }
 
-    // initializes with
-    pub async fn reload(self, config: ChatBotConfig) -> anyhow::Result<Self> {
-        // let client = client.unwrap_or(ChatClient::new(&config.llm, user_id))
+    /// Rebuilds the client and transcript from the config, keeping the context window.
+    /// Leaves the engine as it was if that fails.
+    pub async fn reload(&mut self, config: ChatBotConfig) -> anyhow::Result<()> {
         let ChatBotConfigInner {
             context: context_config,
             llm: llm_config,
+            conversation_log,
             ..
-        } = config.into_inner();
+        } = Self::prepare_config(config);
 
+        let transcript = Self::transcript(conversation_log, self.user_id)?;
         let client = CompletionAgent::new(
             llm_config,
             self.user_id,
             context_config.system.user_name,
             context_config.system.chatbot_name,
+            context_config.system.chatbot_aliases.unwrap_or_default(),
         )
         .await?;
 
-        Ok(Self {
-            client,
-            context: self.context,
-            user_id: self.user_id,
-        })
+        self.client = client;
+        self.transcript = transcript;
+
+        Ok(())
+    }
+
+    /// Applies the overrides of deterministic mode, which pins the temperature to 0.
+    fn prepare_config(config: ChatBotConfig) -> ChatBotConfigInner {
+        let mut config = config.into_inner();
+
+        if config.deterministic.as_ref().is_some_and(|d| d.enabled) {
+            config.llm.temperature = Some(0.0);
+        }
+
+        config
     }
 
-    pub fn into_context(self) -> ChatContext {
-        self.context
+    /// Merges what the user customized over the config's persona. Only the customizations are
+    /// persisted (in `user_preferences`), so fields added to the config later still reach
+    /// users who customized others, and restarts rebuild the same prompt.
+    fn apply_preferences(system: &mut SystemPromptBuilder, preferences: UserPreferences) {
+        let UserPreferences {
+            timezone,
+            about,
+            language,
+            standing_context,
+            ..
+        } = preferences;
+
+        if timezone.is_some() {
+            system.timezone = timezone;
+        }
+        if about.is_some() {
+            system.user_about = about;
+        }
+        if let Some(language) = language {
+            system.language = Self::language(language);
+        }
+        if standing_context.is_some() {
+            system.standing_context = standing_context;
+        }
+    }
+
+    /// The prompt's language for a /language choice, `auto` leaves it up to the model.
+    pub fn language(choice: String) -> Option<String> {
+        match choice.eq_ignore_ascii_case("auto") {
+            true => None,
+            false => Some(choice),
+        }
+    }
+
+    fn transcript(
+        config: Option<ConversationLogConfig>,
+        user_id: UserId,
+    ) -> anyhow::Result<Option<ConversationLog>> {
+        match config {
+            Some(config) => ConversationLog::new(&config, user_id),
+            None => Ok(None),
+        }
+    }
+
+    /// The configured cold start note, if the prompt opens a conversation the bot knows nothing
+    /// about yet: nobody talked to it so far (a greeting doesn't count) and it has no memories.
+    async fn cold_start_note(
+        &self,
+        prompt: &Option<(String, MessageIdentifier)>,
+        context: &Option<ContextType>,
+    ) -> Option<String> {
+        let note = self.context.config.cold_start_note.as_ref()?;
+
+        if prompt.is_none()
+            || !matches!(context, None | Some(ContextType::User { .. }))
+            || self.context.latest_with_role(MessageRole::User).is_some()
+        {
+            return None;
+        }
+
+        match self.client.has_memories().await {
+            Ok(false) => {
+                log::info!("cold start, nothing is known about {} yet", self.user_id);
+                Some(
+                    self.context
+                        .config
+                        .system
+                        .substitute(note, chrono::Duration::seconds(0)),
+                )
+            }
+            Ok(true) => None,
+            Err(why) => {
+                log::warn!("failed to check for memories, skipping the cold start note: {why:?}");
+                None
+            }
+        }
     }
 
     pub async fn user_prompt(
         &mut self,
         prompt: Option<(String, MessageIdentifier)>,
+        author: Option<String>,
+        context: Option<ContextType>,
+    ) -> anyhow::Result<ChatMessage> {
+        let started = Instant::now();
+        self.client.start_turn();
+
+        let result = self.prompt_turn(prompt, author, context).await;
+
+        self.client.finish_turn(started.elapsed());
+
+        result
+    }
+
+    async fn prompt_turn(
+        &mut self,
+        prompt: Option<(String, MessageIdentifier)>,
+        author: Option<String>,
         context: Option<ContextType>,
     ) -> anyhow::Result<ChatMessage> {
         let retries = 5;
 
+        let system_note = match &context {
+            Some(ContextType::User { system_note }) => system_note.clone(),
+            _ => None,
+        };
+        let system_note = match (system_note, self.cold_start_note(&prompt, &context).await) {
+            (Some(note), Some(cold_start)) => Some(format!("{note}\n{cold_start}")),
+            (note, cold_start) => note.or(cold_start),
+        };
+        let (regen, model, nudge) = match &context {
+            Some(ContextType::Regen { model, nudge, .. }) => (true, model.clone(), nudge.clone()),
+            _ => (false, None, None),
+        };
+
+        let max_duplicates = self.context.config.duplicate_retries.unwrap_or(0);
+        let mut duplicates = 0;
+
         let mut i = 0;
         while i < retries {
             let (prompt, message_id) = match prompt.clone() {
@@ -86,16 +242,22 @@ impl ChatEngine {
             };
 
             let context: ContextWindow = match context {
-                Some(ContextType::User) => self.context.get_context(prompt).await?,
+                Some(ContextType::User { .. }) => {
+                    self.context.get_context(prompt, author.clone()).await?
+                }
                 Some(ContextType::Freewill) => self.context.freewill_context(prompt).await?,
-                Some(ContextType::Regen(ref message_id)) => {
-                    self.context.get_regen_context(message_id).await?
+                Some(ContextType::CatchUp) => self.context.catch_up_context().await?,
+                Some(ContextType::Regen { ref identifier, .. }) => {
+                    self.context.get_regen_context(identifier).await?
                 }
-                None => self.context.get_context(prompt).await?,
+                None => self.context.get_context(prompt, author.clone()).await?,
             };
 
             if let Some(drained) = context.overflow {
                 log::info!("draining {drained:?}");
+                if self.context.config.role_summaries.unwrap_or(false) {
+                    self.update_role_summaries(drained.clone()).await;
+                }
                 self.client
                     .store(
                         drained,
@@ -105,22 +267,74 @@ impl ChatEngine {
                     .await?;
             }
 
-            let mut prompt = if let Some(prompt) = context.user_prompt {
-                Some(prompt)
-            } else {
-                None
+            if let Some(due) = context.summary_due {
+                log::info!("summarizing {due:?}");
+                self.client
+                    .store(
+                        due,
+                        &self.context.config.system.user_name,
+                        &self.context.config.system.chatbot_name,
+                    )
+                    .await?;
+            }
+
+            let mut prompt = context
+                .user_prompt
+                .ok_or(anyhow!("unable to get a user prompt"))?;
+
+            // keep the persona reminder if this turn got one
+            if let Some(note) = &system_note {
+                prompt.system_note = Some(match prompt.system_note.take() {
+                    Some(reminder) => format!("{reminder}\n{note}"),
+                    None => note.clone(),
+                });
             }
-            .ok_or(anyhow!("unable to get a user prompt"))?;
+
+            // only keep a copy of the request around if we have to log it
+            let request = self
+                .transcript
+                .as_ref()
+                .map(|_| (context.system_prompt.clone(), context.history.clone()));
+
+            let nudge = match (nudge.clone(), duplicates > 0) {
+                (Some(nudge), true) => Some(format!("{nudge}\n{VARY_NUDGE}")),
+                (None, true) => Some(VARY_NUDGE.to_string()),
+                (nudge, false) => nudge,
+            };
+
+            let documents = self.context.documents().to_vec();
+            let completion = match nudge.is_some() {
+                true => {
+                    self.client
+                        .regenerate(
+                            &mut prompt,
+                            context.system_prompt,
+                            context.history,
+                            documents,
+                            nudge.as_deref(),
+                            model.as_deref(),
+                        )
+                        .await
+                }
+                false => {
+                    self.client
+                        .completion(
+                            &mut prompt,
+                            context.system_prompt,
+                            context.history,
+                            documents,
+                            model.as_deref(),
+                        )
+                        .await
+                }
+            };
 
             // retry if we get an error as well, but only up to the max retries
-            let response = match self
-                .client
-                .completion(&mut prompt, context.system_prompt, context.history)
-                .await
-            {
+            let response = match completion {
                 Ok(response) => response,
                 Err(why) => {
-                    if i + 1 >= retries {
+                    // a stalled provider is unlikely to do better right away, don't keep the user waiting
+                    if i + 1 >= retries || why.is::<CompletionTimeout>() {
                         return Err(why);
                     } else {
                         log::warn!("error:\n{why:?}\nretrying, attempt {i}");
@@ -131,15 +345,27 @@ impl ChatEngine {
             };
 
             match response {
-                CompletionResult::Message(completion_message) => {
-                    let message = ChatMessage::from(completion_message);
+                CompletionResult::Message(completion_message, cited_memories) => {
+                    let mut message = ChatMessage::from(completion_message);
+                    message.cited_memories = cited_memories;
+                    message.model = model.clone().filter(|model| model != self.client.model());
 
                     let content = message.content();
 
                     if let Some(content) = content {
                         log::trace!("output:\n{content}");
 
-                        if content.len() > 0 {
+                        // regenerations are already meant to differ from what they replace
+                        if !regen && duplicates < max_duplicates && self.repeats_last(&content) {
+                            duplicates += 1;
+                            log::info!(
+                                "reply repeats the previous one, regenerating ({duplicates}/{max_duplicates})"
+                            );
+                            continue;
+                        }
+
+                        if !content.is_empty() {
+                            self.record(request, &prompt, std::slice::from_ref(&message));
                             self.context.add_user_message(
                                 prompt,
                                 message_id.unwrap_or(MessageIdentifier::random()),
@@ -155,20 +381,133 @@ impl ChatEngine {
                         continue;
                     }
                 }
-                CompletionResult::Tool((call, response)) => {
-                    self.context.add_message(ChatMessage::from(call), None);
-                    self.context.add_message(ChatMessage::from(response), None);
+                CompletionResult::Tool((call, response))
+                | CompletionResult::MultiTool((call, response)) => {
+                    let call = ChatMessage::from(call);
+                    let response = ChatMessage::from(response);
+                    self.record(request, &prompt, &[call.clone(), response.clone()]);
+
+                    self.context.add_message(call, None);
+                    self.context.add_message(response, None);
 
                     log::info!("called functions, prompting again");
 
                     continue;
                 }
+                CompletionResult::ToolWithReply((call, response), reply, cited_memories) => {
+                    let call = ChatMessage::from(call);
+                    let response = ChatMessage::from(response);
+                    let mut message = ChatMessage::from(reply);
+                    message.cited_memories = cited_memories;
+                    message.model = model.clone().filter(|model| model != self.client.model());
+
+                    self.context.add_message(call.clone(), None);
+                    self.context.add_message(response.clone(), None);
+
+                    // nothing was left of the text, let the model reply with the results in sight
+                    if message.content().is_none_or(|content| content.is_empty()) {
+                        self.record(request, &prompt, &[call, response]);
+                        log::info!("called functions, prompting again");
+                        continue;
+                    }
+
+                    self.record(request, &prompt, &[call, response, message.clone()]);
+                    self.context.add_user_message(
+                        prompt,
+                        message_id.unwrap_or(MessageIdentifier::random()),
+                    )?;
+                    return Ok(message);
+                }
             }
         }
 
         Err(anyhow::anyhow!("too many retries"))
     }
 
+    /// Whether the reply is (nearly) the same as the last one in the context.
+    fn repeats_last(&self, content: &str) -> bool {
+        let Some(last) = self
+            .context
+            .latest_with_role(MessageRole::Assistant)
+            .and_then(|last| last.selected().content())
+        else {
+            return false;
+        };
+
+        let threshold = self.context.config.duplicate_threshold.unwrap_or(0.9);
+
+        similarity(&last, content) >= threshold
+    }
+
+    fn record(
+        &self,
+        request: Option<(String, Vec<ChatMessage>)>,
+        prompt: &UserPrompt,
+        response: &[ChatMessage],
+    ) {
+        if let (Some(transcript), Some((system_prompt, history))) = (&self.transcript, request) {
+            transcript.record(&system_prompt, &history, prompt, response);
+        }
+    }
+
+    /// Writes an in-persona announcement from the operator's instructions. Only the given persona
+    /// goes into the request, none of this engine's conversation.
+    pub async fn broadcast(
+        &self,
+        system: SystemPromptBuilder,
+        instructions: &str,
+    ) -> anyhow::Result<String> {
+        let mut prompt = UserPrompt {
+            content: None,
+            current_time: system.get_time(),
+            time_since: utils::time_to_string(chrono::Duration::zero()),
+            relevant_memories: vec![],
+            system_note: Some(format!("{BROADCAST_NOTE}\n{instructions}")),
+            author: None,
+            freewill: false,
+        };
+        let system_prompt = system.build(chrono::Duration::zero()).to_string();
+
+        match self
+            .client
+            .completion(&mut prompt, system_prompt, vec![], vec![], None)
+            .await?
+        {
+            CompletionResult::Message(message, _) => ChatMessage::from(message)
+                .content()
+                .filter(|content| !content.trim().is_empty())
+                .ok_or(anyhow!("the model came back with an empty broadcast")),
+            _ => Err(anyhow!(
+                "the model called a tool instead of writing the broadcast"
+            )),
+        }
+    }
+
+    /// Folds drained messages into the user facts and assistant commitments of the system
+    /// prompt. Failing only costs the summaries an update, so it doesn't fail the turn.
+    async fn update_role_summaries(&mut self, drained: Vec<ChatMessage>) {
+        let system = &self.context.config.system;
+        let summaries = self
+            .client
+            .summarize_roles(
+                drained,
+                &system.user_name,
+                &system.chatbot_name,
+                system.user_facts.as_deref(),
+                system.assistant_commitments.as_deref(),
+            )
+            .await;
+
+        match summaries {
+            Ok((facts, commitments)) => {
+                let system = &mut self.context.config.system;
+                system.user_facts = facts;
+                system.assistant_commitments = commitments;
+            }
+            Err(why) => log::warn!("failed to update the role summaries: {why:?}"),
+        }
+    }
+
     pub async fn summarize_and_store(
         &self,
         context: Vec<ChatMessage>,
@@ -185,6 +524,43 @@ impl ChatEngine {
     pub fn clear_context(&mut self) {
         self.context.clear()
     }
+
+    /// Clears the short-term memory if the conversation sat idle for longer than
+    /// `auto_clear_after`, summarizing it into long-term memory first. Returns a system note
+    /// telling the model about the fresh start if it did.
+    pub async fn expire_idle(&mut self) -> anyhow::Result<Option<String>> {
+        let Some(after) = self.context.config.auto_clear_after else {
+            return Ok(None);
+        };
+
+        let idle = self.time_since_last();
+        if self.message_count() == 0 || idle < chrono::Duration::seconds(after as i64) {
+            return Ok(None);
+        }
+
+        log::info!(
+            "conversation of {} was idle for {}, clearing it",
+            self.user_id,
+            utils::time_to_string(idle)
+        );
+
+        if self.context.config.summarize_on_auto_clear.unwrap_or(true) {
+            let messages = self.context.get_messages().await;
+            self.summarize_and_store(
+                messages,
+                &self.context.config.system.user_name,
+                &self.context.config.system.chatbot_name,
+            )
+            .await?;
+        }
+
+        self.clear_context();
+
+        Ok(Some(format!(
+            "The conversation was reset after {} of inactivity, earlier messages are no longer in context.",
+            utils::time_to_string(idle)
+        )))
+    }
 }
 
 impl Deref for ChatEngine {
@@ -202,7 +578,151 @@ impl DerefMut for ChatEngine {
 }
 
 pub enum ContextType {
-    User,
+    User {
+        /// Passed to the model along with the prompt, e.g. by keyword triggers.
+        system_note: Option<String>,
+    },
     Freewill,
-    Regen(MessageIdentifier),
+    /// Answers everything that came in while the engine was paused.
+    CatchUp,
+    Regen {
+        identifier: MessageIdentifier,
+        /// One of the alternate models to use instead of the configured one.
+        model: Option<String>,
+        /// Steers the new reply (like "make it shorter") without being stored.
+        nudge: Option<String>,
+    },
+}
+
+/// Dice coefficient of the (lowercased, punctuation-free) words of both texts, 1.0 being the same
+/// words and 0.0 no words in common.
+fn similarity(a: &str, b: &str) -> f64 {
+    let words = |text: &str| {
+        text.split_whitespace()
+            .map(|word| {
+                word.chars()
+                    .filter(|c| c.is_alphanumeric())
+                    .flat_map(char::to_lowercase)
+                    .collect::<String>()
+            })
+            .filter(|word| !word.is_empty())
+            .collect::<HashSet<_>>()
+    };
+
+    let (a, b) = (words(a), words(b));
+    if a.is_empty() && b.is_empty() {
+        return 1.0;
+    }
+
+    2.0 * a.intersection(&b).count() as f64 / (a.len() + b.len()) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preferences_only_override_what_was_set() {
+        let mut system = SystemPromptBuilder {
+            timezone: Some(chrono_tz::Europe::Paris),
+            user_about: Some("From the config.".to_string()),
+            ..Default::default()
+        };
+
+        ChatEngine::apply_preferences(
+            &mut system,
+            UserPreferences {
+                timezone: None,
+                about: Some("From /aboutme.".to_string()),
+                ..Default::default()
+            },
+        );
+        assert_eq!(system.timezone, Some(chrono_tz::Europe::Paris));
+        assert_eq!(system.user_about.as_deref(), Some("From /aboutme."));
+
+        ChatEngine::apply_preferences(
+            &mut system,
+            UserPreferences {
+                timezone: Some(chrono_tz::Asia::Tokyo),
+                about: None,
+                ..Default::default()
+            },
+        );
+        assert_eq!(system.timezone, Some(chrono_tz::Asia::Tokyo));
+        assert_eq!(system.user_about.as_deref(), Some("From /aboutme."));
+    }
+
+    /// Where a test keeps its config, starting out without one.
+    fn config_path(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join("chatbot-tests").join(name);
+        let _ = std::fs::remove_dir_all(&dir);
+
+        dir.join("config.toml")
+    }
+
+    #[tokio::test]
+    async fn preferences_survive_a_save_and_a_reload() {
+        let path = config_path("preferences");
+        let user = UserId::new(1);
+
+        let mut config = ChatBotConfig::read(path.clone()).unwrap();
+        config.context.system.long_term_memory = Some(vec!["likes tea".to_string()]);
+        config
+            .save_preferences(user, |preferences| {
+                preferences.timezone = Some(chrono_tz::Europe::Berlin);
+                preferences.about = Some("likes trains".to_string());
+            })
+            .await
+            .unwrap();
+
+        let config = ChatBotConfig::read(path).unwrap();
+        let mut system = config.context.system.clone();
+        ChatEngine::apply_preferences(&mut system, config.preferences(user));
+
+        assert_eq!(system.timezone, Some(chrono_tz::Europe::Berlin));
+        assert_eq!(system.user_about.as_deref(), Some("likes trains"));
+        // everything that wasn't customized still comes from the config
+        assert_eq!(system.chatbot_name, config.context.system.chatbot_name);
+        // memories aren't saved with the prompt, they're recalled from storage every time
+        assert_eq!(system.long_term_memory, None);
+        assert_eq!(
+            config.preferences(UserId::new(2)),
+            UserPreferences::default()
+        );
+    }
+
+    #[tokio::test]
+    async fn emptied_preferences_are_removed() {
+        let path = config_path("emptied-preferences");
+        let user = UserId::new(1);
+
+        let mut config = ChatBotConfig::read(path.clone()).unwrap();
+        config
+            .save_preferences(user, |preferences| {
+                preferences.about = Some("likes trains".to_string())
+            })
+            .await
+            .unwrap();
+        config
+            .save_preferences(user, |preferences| preferences.about = None)
+            .await
+            .unwrap();
+
+        let config = ChatBotConfig::read(path).unwrap();
+        assert!(
+            config
+                .context
+                .user_preferences
+                .as_ref()
+                .is_none_or(|preferences| !preferences.contains_key("1"))
+        );
+    }
+
+    #[test]
+    fn similarity_goes_by_shared_words() {
+        assert_eq!(similarity("Hello there!", "hello, THERE"), 1.0);
+        assert_eq!(similarity("tea please", "coffee now"), 0.0);
+        assert_eq!(similarity("I like tea", "I like coffee"), 2.0 / 3.0);
+        assert_eq!(similarity("", "..."), 1.0);
+    }
 }
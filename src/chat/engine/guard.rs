@@ -1,8 +1,8 @@
 use serenity::all::UserId;
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::atomic::Ordering};
 use tokio::sync::{RwLock, RwLockReadGuard};
 
-use crate::{bot::Data, utils::macros::config};
+use crate::{bot::Data, chat::client::MissingCredentials, utils::macros::config};
 
 use super::ChatEngine;
 
@@ -23,7 +23,12 @@ impl<'a> EngineGuard<'a> {
             false => {
                 let mut user_map = data.user_map.write().await;
                 let config = config!(data);
-                let engine = ChatEngine::new(config, user.clone()).await?;
+
+                // no point building an engine that can't answer
+                if data.missing_credentials.load(Ordering::Relaxed) {
+                    return Err(MissingCredentials::for_config(&config.llm).into());
+                }
+                let engine = ChatEngine::new(config, user).await?;
 
                 user_map.insert(user, RwLock::new(engine));
             }
@@ -1,5 +1,8 @@
+#[allow(clippy::module_inception)]
 mod engine;
 mod guard;
+#[cfg(test)]
+pub mod replay;
 
 pub use engine::{ChatEngine, ContextType};
 pub use guard::EngineGuard;
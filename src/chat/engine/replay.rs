@@ -0,0 +1,459 @@
+use rig::message::{Message as RigMessage, UserContent};
+use serde::Serialize;
+use serde_json::json;
+use serenity::all::{ChannelId, MessageId, UserId};
+
+use crate::{
+    chat::{
+        ChatMessage,
+        client::{
+            CompletionAgent,
+            mock::{ScriptedModel, WordEmbedding},
+        },
+    },
+    config::structure::ChatBotConfigInner,
+};
+
+use super::{ChatEngine, ContextType};
+
+const VECTOR_SIZE: usize = 64;
+
+/// Replays a conversation through an engine on a scripted completion model, with memories
+/// embedded by their words and kept by the in-memory backend. Memories are shared by every
+/// engine in the process, so each scenario needs a user of its own.
+pub struct Replay {
+    pub engine: ChatEngine,
+    pub model: ScriptedModel,
+    messages: u64,
+}
+
+impl Replay {
+    /// Alice talking to Botty, with the config changed by `configure` first.
+    pub async fn new(
+        user_id: u64,
+        configure: impl FnOnce(&mut ChatBotConfigInner),
+    ) -> anyhow::Result<Self> {
+        let mut config = ChatBotConfigInner::default();
+        config.context.max_stm = 50;
+        config.context.system.user_name = "Alice".to_string();
+        config.context.system.chatbot_name = "Botty".to_string();
+        config.llm.model = "scripted".to_string();
+        config.llm.supports_tools = Some(true);
+        configure(&mut config);
+
+        let user_id = UserId::new(user_id);
+        let model = ScriptedModel::default();
+        let client = CompletionAgent::with_models(
+            config.llm.clone(),
+            user_id,
+            config.context.system.user_name.clone(),
+            config.context.system.chatbot_name.clone(),
+            Box::new(model.clone()),
+            Box::new(WordEmbedding::new(VECTOR_SIZE)),
+        )
+        .await?;
+        let engine = ChatEngine::with_client(config, user_id, client).await?;
+
+        Ok(Self {
+            engine,
+            model,
+            messages: 0,
+        })
+    }
+
+    /// Sends a message from the user, keeping the reply in the context like the message handler.
+    pub async fn say(&mut self, content: &str) -> anyhow::Result<ChatMessage> {
+        let channel = ChannelId::new(1);
+        let prompt = MessageId::new(self.messages + 1);
+        let reply = MessageId::new(self.messages + 2);
+        self.messages += 2;
+
+        let response = self
+            .engine
+            .user_prompt(
+                Some((content.to_string(), (prompt, channel).into())),
+                None,
+                Some(ContextType::User { system_note: None }),
+            )
+            .await?;
+        self.engine.add_message(response.clone(), (reply, channel));
+
+        Ok(response)
+    }
+
+    /// What the user has in long-term memory, as stored.
+    pub async fn memories(&self) -> anyhow::Result<Vec<String>> {
+        Ok(self
+            .engine
+            .client
+            .memories("", 100)
+            .await?
+            .into_iter()
+            .map(|memory| memory.content)
+            .collect())
+    }
+
+    /// The roles in the context, oldest first, with tool results told apart.
+    pub fn roles(&self) -> Vec<&'static str> {
+        (0..self.engine.message_count())
+            .filter_map(|i| self.engine.get(i))
+            .map(|messages| match &messages.selected().inner {
+                RigMessage::User { content } => match content.first() {
+                    UserContent::ToolResult(_) => "tool",
+                    _ => "user",
+                },
+                RigMessage::Assistant { .. } => "assistant",
+            })
+            .collect()
+    }
+}
+
+/// Everything in a request part as JSON, for looking up what ended up in it.
+pub fn sent(part: &impl Serialize) -> String {
+    json!(part).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::structure::ToolCallText;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn drained_messages_are_summarized_and_recalled() {
+        let mut replay = Replay::new(1001, |config| config.context.max_stm = 4)
+            .await
+            .unwrap();
+
+        replay
+            .model
+            .reply("Choo choo!")
+            .reply("Pasta.")
+            .reply("<user> loves steam trains.")
+            .reply("Anytime!")
+            .reply("You love them!");
+
+        replay.say("I love steam trains").await.unwrap();
+        replay.say("What should I cook tonight?").await.unwrap();
+        assert!(replay.memories().await.unwrap().is_empty());
+
+        // the oldest exchange gets drained, reply and all, and summarized before the reply
+        let reply = replay.say("Thanks!").await.unwrap();
+        assert_eq!(reply.content().as_deref(), Some("Anytime!"));
+        assert_eq!(
+            replay.memories().await.unwrap(),
+            ["<user> loves steam trains."]
+        );
+        assert_eq!(replay.roles(), ["user", "assistant", "user", "assistant"]);
+
+        let reply = replay.say("steam trains?").await.unwrap();
+        assert_eq!(reply.content().as_deref(), Some("You love them!"));
+        assert_eq!(replay.model.remaining(), 0);
+
+        let requests = replay.model.requests();
+        assert_eq!(requests.len(), 5);
+
+        let summary = &requests[2];
+        assert!(summary.tools.is_empty());
+        assert!(sent(&summary.prompt).contains("I love steam trains"));
+        assert!(sent(&summary.prompt).contains("Choo choo!"));
+        assert!(!sent(&summary.prompt).contains("cook"));
+
+        // recalled with the names put back in, and only when it's relevant
+        assert!(!sent(&requests[3].prompt).contains("steam trains."));
+        assert!(sent(&requests[4].prompt).contains("Alice loves steam trains."));
+    }
+
+    #[tokio::test]
+    async fn tool_calls_round_trip() {
+        let mut replay = Replay::new(1002, |_| {}).await.unwrap();
+
+        replay
+            .model
+            .call("add_reaction", json!({ "emoji": "🎉" }))
+            .reply("Congratulations!");
+
+        let reply = replay.say("I got the job!").await.unwrap();
+        assert_eq!(reply.content().as_deref(), Some("Congratulations!"));
+        assert_eq!(replay.model.remaining(), 0);
+        assert_eq!(replay.engine.client.take_reactions(), ["🎉"]);
+
+        // the call and its result are kept, ahead of the prompt that led to them
+        assert_eq!(replay.roles(), ["assistant", "tool", "user", "assistant"]);
+
+        let usage = replay
+            .engine
+            .client
+            .tools()
+            .await
+            .into_iter()
+            .map(|(definition, usage)| (definition.name, usage))
+            .collect::<Vec<_>>();
+        assert!(usage.contains(&("add_reaction".to_string(), 1)));
+        assert!(usage.contains(&("memory_store".to_string(), 0)));
+
+        // the model gets to see what the tool came back with
+        let requests = replay.model.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(sent(requests[1].chat_history.last().unwrap()).contains("Reacted with 🎉"));
+    }
+
+    #[tokio::test]
+    async fn turns_are_measured() {
+        let mut replay = Replay::new(1003, |_| {}).await.unwrap();
+        assert!(replay.engine.client.last_turn().is_none());
+
+        replay
+            .model
+            .call("add_reaction", json!({ "emoji": "👍" }))
+            .reply("Sure.");
+        replay.say("Can you react to this?").await.unwrap();
+
+        let turn = replay.engine.client.last_turn().unwrap();
+        assert_eq!(turn.completions, 2);
+        assert_eq!(turn.tool_calls, 1);
+        assert!(turn.tokens_in > 0 && turn.tokens_out > 0);
+        assert!(turn.total >= turn.completion + turn.tools);
+    }
+
+    #[tokio::test]
+    async fn repeated_replies_are_regenerated() {
+        let mut replay = Replay::new(1004, |config| config.context.duplicate_retries = Some(1))
+            .await
+            .unwrap();
+
+        replay
+            .model
+            .reply("I'm doing great, thanks!")
+            .reply("I'm doing great, thanks!")
+            .reply("Still great!")
+            .reply("Still great!")
+            .reply("Still great!");
+
+        replay.say("How are you?").await.unwrap();
+        let reply = replay.say("And now?").await.unwrap();
+        assert_eq!(reply.content().as_deref(), Some("Still great!"));
+
+        // out of retries, the repeat goes through
+        let reply = replay.say("And now?").await.unwrap();
+        assert_eq!(reply.content().as_deref(), Some("Still great!"));
+        assert_eq!(replay.model.remaining(), 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn memories_round_trip_through_the_tools() {
+        let mut replay = Replay::new(1005, |_| {}).await.unwrap();
+
+        replay
+            .model
+            .call(
+                "memory_store",
+                json!({ "memory": "Alice has a cat called Tom" }),
+            )
+            .reply("Cute name!")
+            .call("memory_recall", json!({ "query": "cat called" }))
+            .reply("Tom!");
+
+        let reply = replay.say("My cat is called Tom.").await.unwrap();
+        assert_eq!(reply.content().as_deref(), Some("Cute name!"));
+        assert_eq!(
+            replay.memories().await.unwrap(),
+            ["<user> has a cat called Tom"]
+        );
+
+        let reply = replay.say("What is my cat called?").await.unwrap();
+        assert_eq!(reply.content().as_deref(), Some("Tom!"));
+        assert_eq!(replay.model.remaining(), 0);
+
+        let requests = replay.model.requests();
+        assert_eq!(requests.len(), 4);
+        assert!(
+            sent(requests[1].chat_history.last().unwrap()).contains("Memory store successful!")
+        );
+
+        // the memory came back through the tool, not by being recalled into the prompt
+        assert!(!sent(&requests[2].prompt).contains("Tom"));
+        assert!(
+            sent(requests[3].chat_history.last().unwrap()).contains("Alice has a cat called Tom")
+        );
+    }
+
+    #[tokio::test]
+    async fn recall_previews_include_what_falls_short() {
+        let replay = Replay::new(1006, |_| {}).await.unwrap();
+        let client = &replay.engine.client;
+
+        client.remember("loves steam trains", None).await.unwrap();
+        client
+            .remember("cooks pasta on fridays", None)
+            .await
+            .unwrap();
+
+        let (threshold, scored) = client.preview_recall("steam trains", 5).await.unwrap();
+        let scored = scored
+            .into_iter()
+            .map(|(score, memory)| (score > threshold, memory.content))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            scored,
+            [
+                (true, "loves steam trains".to_string()),
+                (false, "cooks pasta on fridays".to_string())
+            ]
+        );
+
+        // previews don't count as recalls
+        let recalled = client.memories("", 10).await.unwrap();
+        assert!(recalled.iter().all(|memory| memory.recall_count == 0));
+    }
+
+    #[tokio::test]
+    async fn cold_starts_only_note_the_first_message() {
+        let mut replay = Replay::new(1007, |config| {
+            config.context.cold_start_note = Some("Get to know {user}.".to_string())
+        })
+        .await
+        .unwrap();
+
+        replay.model.reply("Hi! What do you like?").reply("Nice.");
+        replay.say("Hello").await.unwrap();
+        replay.say("Trains").await.unwrap();
+
+        let requests = replay.model.requests();
+        assert!(sent(&requests[0].prompt).contains("Get to know Alice."));
+        assert!(!sent(&requests[1].prompt).contains("Get to know"));
+    }
+
+    #[tokio::test]
+    async fn remembered_users_are_not_a_cold_start() {
+        let mut replay = Replay::new(1008, |config| {
+            config.context.cold_start_note = Some("Get to know {user}.".to_string())
+        })
+        .await
+        .unwrap();
+        replay
+            .engine
+            .client
+            .remember("likes trains", None)
+            .await
+            .unwrap();
+
+        replay.model.reply("Welcome back!");
+        replay.say("Hello").await.unwrap();
+
+        assert!(!sent(&replay.model.requests()[0].prompt).contains("Get to know"));
+    }
+
+    #[tokio::test]
+    async fn tool_calls_without_tools_are_answered_directly() {
+        let mut replay = Replay::new(1009, |config| config.llm.use_tools = Some(false))
+            .await
+            .unwrap();
+
+        replay
+            .model
+            .call("memory_recall", json!({ "query": "cats" }))
+            .reply("I don't remember any cats.");
+
+        let reply = replay.say("Do I have a cat?").await.unwrap();
+        assert_eq!(
+            reply.content().as_deref(),
+            Some("I don't remember any cats.")
+        );
+
+        let requests = replay.model.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[1].tools.is_empty());
+        assert!(
+            requests[1]
+                .preamble
+                .as_deref()
+                .unwrap()
+                .ends_with("without calling any tools.")
+        );
+        assert_eq!(replay.roles(), ["user", "assistant"]);
+    }
+
+    #[tokio::test]
+    async fn broadcasts_leave_the_conversation_out() {
+        let mut replay = Replay::new(1010, |_| {}).await.unwrap();
+        replay.model.reply("Hi!").reply("Hello everyone, news!");
+        replay.say("This stays between us.").await.unwrap();
+
+        let system = replay.engine.config.system.clone();
+        let broadcast = replay
+            .engine
+            .broadcast(system, "a new feature")
+            .await
+            .unwrap();
+        assert_eq!(broadcast, "Hello everyone, news!");
+
+        let requests = replay.model.requests();
+        assert!(requests[1].chat_history.is_empty());
+        assert!(!sent(&requests[1].prompt).contains("stays between us"));
+        assert!(sent(&requests[1].prompt).contains("a new feature"));
+        // nothing about it is kept in the context either
+        assert_eq!(replay.roles(), ["user", "assistant"]);
+    }
+
+    #[tokio::test]
+    async fn eager_text_goes_out_before_the_tool_results() {
+        let mut replay = Replay::new(1011, |config| {
+            config.llm.tool_call_text = Some(ToolCallText::EagerText)
+        })
+        .await
+        .unwrap();
+
+        replay
+            .model
+            .say_and_call("Congrats!", "add_reaction", json!({ "emoji": "🎉" }));
+
+        let reply = replay.say("I got the job!").await.unwrap();
+        assert_eq!(reply.content().as_deref(), Some("Congrats!"));
+        assert_eq!(replay.model.requests().len(), 1);
+        assert_eq!(replay.engine.client.take_reactions(), ["🎉"]);
+        assert_eq!(replay.roles(), ["assistant", "tool", "user", "assistant"]);
+    }
+
+    #[tokio::test]
+    async fn deferred_text_waits_for_the_tool_results() {
+        let mut replay = Replay::new(1012, |_| {}).await.unwrap();
+
+        replay
+            .model
+            .say_and_call("Let me react.", "add_reaction", json!({ "emoji": "🎉" }))
+            .reply("Congratulations!");
+
+        let reply = replay.say("I got the job!").await.unwrap();
+        assert_eq!(reply.content().as_deref(), Some("Congratulations!"));
+        assert_eq!(replay.model.requests().len(), 2);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn conversations_only_show_up_in_trace_logs() {
+        crate::utils::log::capture::install();
+        let mut replay = Replay::new(1013, |_| {}).await.unwrap();
+
+        replay
+            .model
+            .call("memory_store", json!({ "memory": "Alice owns a zebra" }))
+            .reply("Noted!")
+            .call("memory_recall", json!({ "query": "striped pets" }))
+            .reply("Your zebra!");
+
+        replay.say("I own a zebra.").await.unwrap();
+        replay.say("Which pet do I have?").await.unwrap();
+
+        for text in ["owns a zebra", "striped pets", "I own a zebra"] {
+            let levels = crate::utils::log::capture::levels_mentioning(text);
+            assert!(
+                levels.iter().all(|level| *level == log::Level::Trace),
+                "{text:?} was logged at {levels:?}"
+            );
+        }
+        // and they do show up there, for debugging
+        assert!(
+            crate::utils::log::capture::levels_mentioning("owns a zebra")
+                .contains(&log::Level::Trace)
+        );
+    }
+}
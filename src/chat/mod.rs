@@ -1,7 +1,9 @@
 mod archive;
+pub mod audit;
 pub mod client;
 pub mod context;
 pub mod engine;
 pub mod prompt;
 
+pub use archive::storage::MemoryExport;
 pub use context::ChatMessage;
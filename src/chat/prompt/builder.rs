@@ -1,4 +1,4 @@
-use chrono::Duration;
+use chrono::{Datelike, Duration, NaiveDate, NaiveTime};
 use chrono_tz::Tz;
 use serde::{Deserialize, Serialize};
 
@@ -9,6 +9,8 @@ use super::{prompt::SystemPrompt, template::TemplateVariables};
 #[derive(Clone, Serialize, Deserialize, Debug, Default, PartialEq)]
 pub struct SystemPromptBuilder {
     pub chatbot_name: String,
+    /// Nicknames the bot also goes by, treated like its name by triggers and memories.
+    pub chatbot_aliases: Option<Vec<String>>,
     pub user_name: String,
     pub about: String,
     pub max_ltm: usize,
@@ -20,14 +22,61 @@ pub struct SystemPromptBuilder {
     pub conversation_goals: Option<Vec<String>>,
     pub conversational_examples: Option<Vec<String>>,
     pub context: Option<Vec<String>>,
+    /// Rules or instructions that always apply, kept across /clear. Users can set their own with /standing.
+    pub standing_context: Option<String>,
 
     #[serde(skip)]
     pub long_term_memory: Option<Vec<String>>,
+    #[serde(skip)]
+    pub participants: Option<Vec<String>>,
+    /// Running summary of what the user shared, kept up by `role_summaries`.
+    #[serde(skip)]
+    pub user_facts: Option<String>,
+    /// Running summary of what the bot committed to, kept up by `role_summaries`.
+    #[serde(skip)]
+    pub assistant_commitments: Option<String>,
 
     pub user_about: Option<String>,
     pub timezone: Option<Tz>,
     pub language: Option<String>,
+    /// Notes added to the context on matching days, like birthdays and holidays.
+    pub special_dates: Option<Vec<SpecialDate>>,
+}
+
+/// A note for a given day, `MM-DD` for every year or `YYYY-MM-DD` for a single one.
+#[derive(Clone, Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct SpecialDate {
+    pub date: String,
+    pub note: String,
+}
+
+impl SpecialDate {
+    pub fn matches(&self, today: NaiveDate) -> bool {
+        if let Ok(date) = NaiveDate::parse_from_str(&self.date, "%Y-%m-%d") {
+            return date == today;
+        }
+
+        // chrono won't parse a date without a year, go through the month and day by hand
+        let month_day = self.date.split_once('-').and_then(|(month, day)| {
+            Some((
+                month.trim().parse::<u32>().ok()?,
+                day.trim().parse::<u32>().ok()?,
+            ))
+        });
+
+        match month_day {
+            Some((month, day)) => today.month() == month && today.day() == day,
+            None => {
+                log::warn!(
+                    "invalid special date \"{}\", expected MM-DD or YYYY-MM-DD",
+                    self.date
+                );
+                false
+            }
+        }
+    }
 }
+
 impl SystemPromptBuilder {
     #[allow(unused)]
     pub fn add_long_term_memory(mut self, new_memory: String) -> Self {
@@ -69,6 +118,45 @@ impl SystemPromptBuilder {
         }
     }
 
+    /// The time of day in the user's timezone.
+    pub fn local_time(&self) -> NaiveTime {
+        match self.timezone {
+            Some(timezone) => chrono::Utc::now().with_timezone(&timezone).time(),
+            None => chrono::Utc::now().time(),
+        }
+    }
+
+    /// Today's date in the user's timezone.
+    pub fn today(&self) -> NaiveDate {
+        match self.timezone {
+            Some(timezone) => chrono::Utc::now().with_timezone(&timezone).date_naive(),
+            None => chrono::Utc::now().date_naive(),
+        }
+    }
+
+    /// Substitutes the template placeholders in an arbitrary piece of text.
+    pub fn substitute(&self, text: &str, time_since_last: Duration) -> String {
+        let time = self.get_time();
+        let time_since = utils::time_to_string(time_since_last);
+
+        TemplateVariables::new(&self.user_name, &self.chatbot_name, &time, &time_since)
+            .substitute_template(text)
+    }
+
+    /// A condensed version of the persona, for reminding the model of it in long conversations.
+    pub fn persona_reminder(&self, time_since_last: Duration) -> String {
+        let mut reminder = format!(
+            "Remember that you are {}. Stay in character and keep the same personality as before.",
+            self.chatbot_name
+        );
+
+        if let Some(tone) = &self.tone {
+            reminder.push_str(&format!(" Your tone: {tone}"));
+        }
+
+        self.substitute(&reminder, time_since_last)
+    }
+
     pub fn build(mut self, time_since_last: Duration) -> SystemPrompt {
         let time = self.get_time();
 
@@ -92,6 +180,62 @@ impl SystemPromptBuilder {
         self.user_about = variables.substitute_optional_template(self.user_about.as_deref());
         self.language = variables.substitute_optional_template(self.language.as_deref());
 
+        let today = self.today();
+        let notes = self
+            .special_dates
+            .iter()
+            .flatten()
+            .filter(|special| special.matches(today))
+            .map(|special| variables.substitute_template(&special.note))
+            .collect::<Vec<_>>();
+        if !notes.is_empty() {
+            self.context.get_or_insert_default().extend(notes);
+        }
+
         SystemPrompt::new(self)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn special(date: &str) -> SpecialDate {
+        SpecialDate {
+            date: date.to_string(),
+            note: "It's {user}'s birthday!".to_string(),
+        }
+    }
+
+    #[test]
+    fn special_dates_match_every_year_or_once() {
+        let today = NaiveDate::from_ymd_opt(2025, 3, 14).unwrap();
+
+        assert!(special("03-14").matches(today));
+        assert!(special("3-14").matches(today));
+        assert!(special("2025-03-14").matches(today));
+        assert!(!special("2024-03-14").matches(today));
+        assert!(!special("03-15").matches(today));
+        assert!(!special("pi day").matches(today));
+    }
+
+    #[test]
+    fn todays_notes_end_up_in_the_context() {
+        let builder = SystemPromptBuilder {
+            user_name: "Alice".to_string(),
+            chatbot_name: "Botty".to_string(),
+            special_dates: Some(vec![special("1999-01-01")]),
+            ..Default::default()
+        };
+        let today = builder.today().format("%m-%d").to_string();
+        assert!(!builder.clone().build(Duration::zero()).contains("birthday"));
+
+        let mut builder = builder;
+        builder.special_dates = Some(vec![special(&today)]);
+        assert!(
+            builder
+                .build(Duration::zero())
+                .contains("It's Alice's birthday!")
+        );
+    }
+}
@@ -1,5 +1,7 @@
 mod builder;
+#[allow(clippy::module_inception)]
 mod prompt;
 mod template;
 
 pub use builder::SystemPromptBuilder;
+pub use prompt::SystemPrompt;
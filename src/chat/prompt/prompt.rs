@@ -1,4 +1,4 @@
-use std::ops::Deref;
+use std::{fmt, ops::Deref};
 
 use super::builder::SystemPromptBuilder;
 
@@ -37,6 +37,11 @@ Your job is to respond to last message from {}. You can use other messages for c
             builder.chatbot_name, builder.about
         ));
 
+        Self::append_section(
+            &mut prompt,
+            "Standing Context",
+            builder.standing_context.take(),
+        );
         Self::append_section(&mut prompt, "Tone", builder.tone.take());
         Self::append_section(&mut prompt, "Age", builder.age.take());
         Self::append_section(
@@ -79,16 +84,46 @@ Your job is to respond to last message from {}. You can use other messages for c
         }
 
         // Long term memory section.
-        if let Some(ltm) = builder.long_term_memory.take() {
-            if !ltm.is_empty() {
-                let formatted = ltm
-                    .into_iter()
-                    .enumerate()
-                    .map(|(i, mem)| format!("### Memory {}\n```memory\n{}\n```\n", i + 1, mem))
-                    .collect::<Vec<_>>()
-                    .join("\n");
-                Self::append_section(&mut prompt, "Long Term Memory", Some(formatted));
-            }
+        if let Some(ltm) = builder
+            .long_term_memory
+            .take()
+            .filter(|ltm| !ltm.is_empty())
+        {
+            let formatted = ltm
+                .into_iter()
+                .enumerate()
+                .map(|(i, mem)| format!("### Memory {}\n```memory\n{}\n```\n", i + 1, mem))
+                .collect::<Vec<_>>()
+                .join("\n");
+            Self::append_section(&mut prompt, "Long Term Memory", Some(formatted));
+        }
+
+        // Rolling summaries of what fell out of the context.
+        Self::append_section(
+            &mut prompt,
+            &format!("What {} Told You Before", builder.user_name),
+            builder.user_facts.take(),
+        );
+        Self::append_section(
+            &mut prompt,
+            "What You Committed To Before",
+            builder.assistant_commitments.take(),
+        );
+
+        // Group conversation participants.
+        if let Some(participants) = builder
+            .participants
+            .take()
+            .filter(|participants| !participants.is_empty())
+        {
+            Self::append_section(
+                &mut prompt,
+                "People in Conversation",
+                Some(format!(
+                    "This is a group conversation, every message sent by a person carries an `author` field telling who said it. Keep track of who said what and address people by name when appropriate.\n{}",
+                    Self::bullet_list(Some(participants)).unwrap_or_default()
+                )),
+            );
         }
 
         // User about section.
@@ -120,9 +155,11 @@ Your job is to respond to last message from {}. You can use other messages for c
                 .join("\n")
         })
     }
+}
 
-    pub fn to_string(&self) -> String {
-        self.inner.to_string()
+impl fmt::Display for SystemPrompt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.inner)
     }
 }
 
@@ -133,3 +170,26 @@ impl Deref for SystemPrompt {
         &self.inner
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn standing_context_comes_right_after_the_role() {
+        let prompt = SystemPrompt::new(SystemPromptBuilder {
+            chatbot_name: "Botty".to_string(),
+            user_name: "Alice".to_string(),
+            about: "A friendly bot.".to_string(),
+            standing_context: Some("Always answer in rhymes.".to_string()),
+            tone: Some("cheerful".to_string()),
+            ..Default::default()
+        });
+
+        let standing = prompt
+            .find("## Standing Context\nAlways answer in rhymes.")
+            .unwrap();
+        assert!(prompt.find("## About Botty").unwrap() < standing);
+        assert!(standing < prompt.find("## Tone").unwrap());
+    }
+}
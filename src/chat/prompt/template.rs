@@ -30,8 +30,8 @@ impl<'a> TemplateVariables<'a> {
 
     /// Helper to substitute template placeholders for a vector of strings.
     pub fn substitute_templates(&self, vec: &[String]) -> Vec<String> {
-        vec.into_iter()
-            .map(|s| Self::substitute_template(&self, &s))
+        vec.iter()
+            .map(|s| Self::substitute_template(self, s))
             .collect()
     }
 
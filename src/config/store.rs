@@ -1,16 +1,22 @@
 use anyhow::bail;
-use serenity::prelude::TypeMapKey;
+use regex::Regex;
+use serenity::{all::UserId, prelude::TypeMapKey};
 
-use super::structure::{ChatBotConfigInner, ChatBotConfigTOML};
+use super::structure::{ChatBotConfigInner, ChatBotConfigTOML, UserPreferences};
 use std::{
     ops::{Deref, DerefMut},
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 
 #[derive(Debug, Clone)]
 pub struct ChatBotConfig {
     pub path: PathBuf,
     cached: ChatBotConfigTOML,
+    /// The top-level file as it is, without what it includes. Changes are saved into it alone,
+    /// so included files (secrets, personas) never get copied over.
+    own: toml::Value,
+    /// `cached` as it was read, to tell what changed since.
+    read: toml::Value,
 }
 
 impl ChatBotConfig {
@@ -21,7 +27,7 @@ impl ChatBotConfig {
         };
 
         if !path.exists() {
-            return Ok(Self::new(path)?);
+            return Self::new(path);
         }
 
         if !path.is_file() {
@@ -30,21 +36,164 @@ impl ChatBotConfig {
             );
         }
 
-        let config_str = std::fs::read_to_string(&path)?;
+        let own: toml::Value = toml::from_str(&std::fs::read_to_string(&path)?)?;
+        let cached: ChatBotConfigTOML =
+            Self::with_includes(&path, own.clone(), &mut vec![])?.try_into()?;
+        let read = toml::Value::try_from(&cached)?;
 
         Ok(Self {
             path,
-            cached: toml::from_str(&config_str)?,
+            cached,
+            own,
+            read,
         })
     }
 
+    /// Merges everything a config file includes over it, later files overriding earlier ones.
+    /// `stack` holds the files currently being read, to catch include cycles.
+    fn with_includes(
+        path: &Path,
+        mut value: toml::Value,
+        stack: &mut Vec<PathBuf>,
+    ) -> Result<toml::Value, anyhow::Error> {
+        let canonical = path.canonicalize()?;
+        if stack.contains(&canonical) {
+            bail!(
+                "Config includes form a cycle: {} -> {}",
+                stack
+                    .iter()
+                    .map(|path| path.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(" -> "),
+                canonical.display()
+            );
+        }
+        stack.push(canonical);
+
+        let includes = match value.get("include") {
+            Some(include) => include.clone().try_into::<Vec<String>>()?,
+            None => vec![],
+        };
+
+        let dir = path.parent().unwrap_or(Path::new("."));
+        for pattern in includes {
+            for include in Self::resolve_include(dir, &pattern)? {
+                log::info!("including config file {}", include.display());
+
+                let included = toml::from_str(&std::fs::read_to_string(&include)?)?;
+                let mut included = Self::with_includes(&include, included, stack)?;
+                // only the top-level file's includes are the config's own
+                if let Some(table) = included.as_table_mut() {
+                    table.remove("include");
+                }
+
+                Self::merge(&mut value, included);
+            }
+        }
+
+        stack.pop();
+
+        Ok(value)
+    }
+
+    /// Lists the files an include pattern points at, in alphabetical order.
+    fn resolve_include(dir: &Path, pattern: &str) -> Result<Vec<PathBuf>, anyhow::Error> {
+        let path = dir.join(pattern);
+
+        let (Some(parent), Some(name)) = (path.parent(), path.file_name().and_then(|n| n.to_str()))
+        else {
+            bail!("Invalid config include \"{pattern}\"");
+        };
+
+        if !name.contains('*') {
+            return Ok(vec![path]);
+        }
+
+        let regex = Regex::new(&format!("^{}$", regex::escape(name).replace(r"\*", ".*")))?;
+
+        let mut paths = std::fs::read_dir(parent)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.is_file()
+                    && path
+                        .file_name()
+                        .and_then(|name| name.to_str())
+                        .is_some_and(|name| regex.is_match(name))
+            })
+            .collect::<Vec<_>>();
+        paths.sort();
+
+        Ok(paths)
+    }
+
+    /// Merges `overlay` into `base`, tables key by key and everything else by replacing it.
+    fn merge(base: &mut toml::Value, overlay: toml::Value) {
+        match (base, overlay) {
+            (toml::Value::Table(base), toml::Value::Table(overlay)) => {
+                for (key, value) in overlay {
+                    match base.get_mut(&key) {
+                        Some(existing) => Self::merge(existing, value),
+                        None => {
+                            base.insert(key, value);
+                        }
+                    }
+                }
+            }
+            (base, overlay) => *base = overlay,
+        }
+    }
+
+    /// Applies what changed between `read` and `current` to `own`, leaving the rest of it as
+    /// it is. Tables are gone through key by key, so only the changed keys end up in it.
+    fn apply_changes(own: &mut toml::Value, read: &toml::Value, current: &toml::Value) {
+        match (read, current) {
+            (toml::Value::Table(read), toml::Value::Table(current)) => {
+                if !own.is_table() {
+                    *own = toml::Value::Table(Default::default());
+                }
+                let Some(own) = own.as_table_mut() else {
+                    return;
+                };
+
+                for (key, value) in current {
+                    match read.get(key) {
+                        Some(previous) if previous == value => {}
+                        Some(previous) => Self::apply_changes(
+                            own.entry(key.clone())
+                                .or_insert_with(|| toml::Value::Table(Default::default())),
+                            previous,
+                            value,
+                        ),
+                        None => {
+                            own.insert(key.clone(), value.clone());
+                        }
+                    }
+                }
+
+                for key in read.keys().filter(|key| !current.contains_key(*key)) {
+                    own.remove(key);
+                }
+            }
+            (read, current) if read != current => *own = current.clone(),
+            _ => {}
+        }
+    }
+
+    /// What the top-level file should hold now.
+    fn own_toml(&self) -> Result<String, anyhow::Error> {
+        let mut own = self.own.clone();
+        Self::apply_changes(&mut own, &self.read, &toml::Value::try_from(&self.cached)?);
+
+        Ok(toml::to_string(&own)?)
+    }
+
     pub fn update(&mut self) -> bool {
         let new = Self::read(self.path.clone()).unwrap();
 
         match self.cached.config == new.cached.config {
             true => false,
             false => {
-                self.cached = new.cached;
+                *self = new;
                 true
             }
         }
@@ -56,6 +205,8 @@ impl ChatBotConfig {
         let config = Self {
             path,
             cached: ChatBotConfigTOML::default(),
+            own: toml::Value::Table(Default::default()),
+            read: toml::Value::Table(Default::default()),
         };
 
         config.save()?;
@@ -68,16 +219,50 @@ impl ChatBotConfig {
     }
 
     pub fn save(&self) -> Result<(), anyhow::Error> {
-        std::fs::write(&self.path, toml::to_string(&self.cached)?)?;
+        std::fs::write(&self.path, self.own_toml()?)?;
 
         Ok(())
     }
 
     pub async fn async_save(&self) -> Result<(), anyhow::Error> {
-        tokio::fs::write(&self.path, toml::to_string(&self.cached)?).await?;
+        tokio::fs::write(&self.path, self.own_toml()?).await?;
 
         Ok(())
     }
+
+    /// Changes the user's preferences on top of the latest config file and saves them.
+    /// Preferences left with nothing set are removed altogether.
+    pub async fn save_preferences(
+        &mut self,
+        key: UserId,
+        change: impl FnOnce(&mut UserPreferences),
+    ) -> Result<(), anyhow::Error> {
+        self.update();
+
+        let preferences = self
+            .context
+            .user_preferences
+            .get_or_insert_with(Default::default);
+        let entry = preferences.entry(key.to_string()).or_default();
+        change(entry);
+        if *entry == UserPreferences::default() {
+            preferences.remove(&key.to_string());
+        }
+
+        self.async_save().await
+    }
+}
+
+impl ChatBotConfigInner {
+    /// What the user (or channel, in group mode) customized, nothing if they didn't.
+    pub fn preferences(&self, key: UserId) -> UserPreferences {
+        self.context
+            .user_preferences
+            .as_ref()
+            .and_then(|preferences| preferences.get(&key.to_string()))
+            .cloned()
+            .unwrap_or_default()
+    }
 }
 
 impl Deref for ChatBotConfig {
@@ -103,3 +288,130 @@ impl PartialEq for ChatBotConfig {
 impl TypeMapKey for ChatBotConfig {
     type Value = ChatBotConfig;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::structure::UserPreferences;
+
+    /// A folder of its own for the test, along with a config holding the defaults to start from.
+    fn setup(name: &str) -> (PathBuf, toml::Value) {
+        let dir = std::env::temp_dir()
+            .join("chatbot-tests")
+            .join(format!("config-{name}"));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("personas")).unwrap();
+
+        let defaults = ChatBotConfig::read(dir.join("defaults.toml")).unwrap();
+        let value = toml::Value::try_from(&defaults.cached).unwrap();
+
+        (dir, value)
+    }
+
+    fn set(value: &mut toml::Value, path: &[&str], new: impl Into<toml::Value>) {
+        let (last, tables) = path.split_last().unwrap();
+        let table = tables.iter().fold(value, |value, key| {
+            value
+                .as_table_mut()
+                .unwrap()
+                .entry(key.to_string())
+                .or_insert_with(|| toml::Value::Table(Default::default()))
+        });
+        table
+            .as_table_mut()
+            .unwrap()
+            .insert(last.to_string(), new.into());
+    }
+
+    /// The top-level config including a secret and two personas, the later one winning.
+    fn with_includes(name: &str) -> PathBuf {
+        let (dir, mut base) = setup(name);
+        set(
+            &mut base,
+            &["include"],
+            vec!["secrets.toml".to_string(), "personas/*.toml".to_string()],
+        );
+        set(
+            &mut base,
+            &["config", "context", "system", "chatbot_name"],
+            "base",
+        );
+        std::fs::write(dir.join("config.toml"), toml::to_string(&base).unwrap()).unwrap();
+
+        std::fs::write(
+            dir.join("secrets.toml"),
+            "[config.discord]\ntoken = \"secret-token\"\n",
+        )
+        .unwrap();
+        for persona in ["a", "b"] {
+            std::fs::write(
+                dir.join("personas").join(format!("{persona}.toml")),
+                format!("[config.context.system]\nchatbot_name = \"{persona}\"\n"),
+            )
+            .unwrap();
+        }
+
+        dir.join("config.toml")
+    }
+
+    #[test]
+    fn includes_are_merged_over_the_config() {
+        let config = ChatBotConfig::read(with_includes("merge")).unwrap();
+
+        assert_eq!(config.discord.token, "secret-token");
+        assert_eq!(
+            config.cached.include,
+            vec!["secrets.toml", "personas/*.toml"]
+        );
+    }
+
+    #[test]
+    fn later_includes_win_conflicts() {
+        let config = ChatBotConfig::read(with_includes("conflict")).unwrap();
+
+        assert_eq!(config.context.system.chatbot_name, "b");
+    }
+
+    #[test]
+    fn saving_leaves_included_values_out() {
+        let path = with_includes("save");
+
+        let mut config = ChatBotConfig::read(path.clone()).unwrap();
+        config
+            .context
+            .user_preferences
+            .get_or_insert_with(Default::default)
+            .insert(
+                "1".to_string(),
+                UserPreferences {
+                    tts: Some(true),
+                    ..Default::default()
+                },
+            );
+        config.save().unwrap();
+
+        let saved = std::fs::read_to_string(&path).unwrap();
+        assert!(!saved.contains("secret-token"));
+        assert!(saved.contains("chatbot_name = \"base\""));
+        assert!(saved.contains("personas/*.toml"));
+
+        let config = ChatBotConfig::read(path).unwrap();
+        assert_eq!(config.discord.token, "secret-token");
+        assert_eq!(config.context.system.chatbot_name, "b");
+        assert_eq!(
+            config.context.user_preferences.as_ref().unwrap()["1"].tts,
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn include_cycles_are_refused() {
+        let (dir, _) = setup("cycle");
+        std::fs::write(dir.join("a.toml"), "include = [\"b.toml\"]\n").unwrap();
+        std::fs::write(dir.join("b.toml"), "include = [\"a.toml\"]\n").unwrap();
+
+        let error = ChatBotConfig::read(dir.join("a.toml")).unwrap_err();
+
+        assert!(error.to_string().contains("cycle"), "{error}");
+    }
+}
@@ -1,11 +1,16 @@
-use std::path::PathBuf;
+use std::{collections::HashMap, path::PathBuf};
 
+use chrono_tz::Tz;
 use serde::{Deserialize, Serialize};
 
 use crate::chat::{client::Provider, prompt::SystemPromptBuilder};
 
 #[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
 pub struct ChatBotConfigTOML {
+    /// Other config files merged over this one, in order. Relative to this file, `*` matches
+    /// any part of a file name (like `personas/*.toml`).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
     pub config: ChatBotConfigInner,
 }
 
@@ -15,18 +20,306 @@ pub struct ChatBotConfigInner {
     pub llm: LLMConfig,
     pub freewill: FreewillConfig,
     pub context: ContextConfig,
+    pub conversation_log: Option<ConversationLogConfig>,
+    /// Keyword (matched case-insensitively on word boundaries) to what happens when a message contains it.
+    pub triggers: Option<HashMap<String, TriggerAction>>,
+    pub moderation: Option<ModerationConfig>,
+    pub memory_consolidation: Option<MemoryConsolidationConfig>,
+    pub welcome: Option<WelcomeConfig>,
+    pub feedback: Option<FeedbackConfig>,
+    pub tts: Option<TtsConfig>,
+    /// Testing/development only: makes runs reproducible, don't enable it on a live bot.
+    pub deterministic: Option<DeterministicConfig>,
+}
+
+/// Checks incoming messages before replying to them, refusing the ones that break the rules.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ModerationConfig {
+    pub enabled: bool,
+    /// Refuses messages containing any of these (case-insensitively, on word boundaries).
+    pub blocked_keywords: Option<Vec<String>>,
+    /// Rules the model checks messages against, skipped when not set.
+    pub rules: Option<String>,
+    /// Refuses messages when the model check fails instead of letting them through.
+    pub fail_closed: Option<bool>,
+    /// Sent instead of a reply to refused messages.
+    pub refusal: Option<String>,
+    /// Logs refused messages along with their content.
+    pub log_flagged: Option<bool>,
+}
+
+/// Collects the ratings users give replies with /feedback.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct FeedbackConfig {
+    pub enabled: bool,
+    /// JSON lines file every rating gets recorded to, along with the exchange it's about.
+    pub path: PathBuf,
+}
+
+/// Reads replies out loud for users who turned it on with /tts, through an OpenAI compatible
+/// speech endpoint. The audio is attached to the reply, which is sent as text only if it fails.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct TtsConfig {
+    pub enabled: bool,
+    /// Base URL of the API, OpenAI's by default.
+    pub url: Option<String>,
+    pub api_key: String,
+    pub model: String,
+    pub voice: String,
+    /// Audio format asked for, `mp3` by default.
+    pub format: Option<String>,
+    /// Longer replies are sent without audio, 4096 characters by default.
+    pub max_chars: Option<usize>,
+}
+
+/// Greets members who join a guild the bot is in, bots are never greeted.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct WelcomeConfig {
+    pub enabled: bool,
+    /// Channel the greeting is posted in, only members of its guild get greeted.
+    /// Greetings are sent as DMs when not set.
+    pub channel: Option<u64>,
+    /// Supports the prompt placeholders, `{user}` mentions the new member.
+    pub template: String,
+    /// Greets at most one member of a guild per this many seconds, 10 by default.
+    pub cooldown_secs: Option<u64>,
+}
+
+/// Periodically merges clusters of similar long-term memories into single ones.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct MemoryConsolidationConfig {
+    pub enabled: bool,
+    pub interval_secs: u64,
+    /// Cosine similarity two memories need to end up in the same cluster.
+    pub similarity_threshold: f32,
+    /// Smallest cluster worth merging, defaults to 2.
+    pub min_cluster_size: Option<usize>,
+    /// JSON lines file every consolidation gets recorded to.
+    pub audit_log: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct DeterministicConfig {
+    pub enabled: bool,
+    /// Seed for every random choice the bot makes.
+    pub seed: u64,
+}
+
+/// How replies point out the recalled memories they relied on.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MemoryCitations {
+    /// Appends a small "(recalled: ...)" footnote.
+    Footnote,
+    /// Reacts to the reply with 🧠.
+    React,
+}
+
+/// Where long-term memories are kept.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MemoryBackendKind {
+    #[default]
+    Qdrant,
+    /// Kept in the bot's own memory and lost on restart, for tests and small deployments.
+    InMemory,
+}
+
+/// Which personas see a user's long-term memories.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MemoryScope {
+    /// Every persona remembers everything the user told any of them.
+    #[default]
+    Shared,
+    /// Each persona (by name) only remembers what was said to it.
+    Persona,
+}
+
+/// How the model is made to reason before replying.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReasoningMode {
+    /// Only asks the provider for its own reasoning (the `reasoning` parameter, which only
+    /// OpenRouter and other OpenAI compatible servers take). For models that reason natively
+    /// (o-series, DeepSeek R1, Claude with extended thinking, ...), which get confused by being
+    /// told how to think.
+    Native,
+    /// Only tells the model to think in `<think>` tags first. For regular chat models.
+    Prompt,
+    /// Both of the above, what `reason` used to do.
+    Both,
+}
+
+/// What happens to the markdown of replies in a channel.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MarkdownMode {
+    #[default]
+    Preserve,
+    /// Removes the formatting, keeping the formatted text.
+    Strip,
+    /// Shows the formatting characters as they are, instead of formatting.
+    Escape,
+}
+
+/// What happens to text the model sends along with tool calls.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ToolCallText {
+    /// Sent as the reply right away, the model only gets to see the tool results next turn.
+    EagerText,
+    /// Kept with the calls, the model replies once it saw the tool results.
+    #[default]
+    DeferText,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum TriggerAction {
+    /// Passes a system note along with the user's prompt.
+    SystemNote { note: String },
+    /// Answers with a fixed reply instead of generating one.
+    Reply { content: String },
+    /// Only logs the tagged message, for operators to pick up.
+    Tag { tag: String },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PostprocessStep {
+    /// Lets a step stay in the config while turned off, on by default.
+    pub enabled: Option<bool>,
+    #[serde(flatten)]
+    pub action: PostprocessAction,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "step", rename_all = "snake_case")]
+pub enum PostprocessAction {
+    /// Same as `force_lowercase`, but at a chosen point of the chain.
+    Lowercase,
+    /// Replaces words (matched case-insensitively, on word boundaries) with others.
+    Replace { words: HashMap<String, String> },
+    /// Removes emoji, custom ones included.
+    StripEmoji,
+    /// Removes Discord's markdown, keeping the formatted text.
+    StripMarkdown,
+    /// Appended to the reply as is, like a signature.
+    Suffix { text: String },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ConversationLogConfig {
+    pub enabled: bool,
+    pub path: PathBuf,
+    /// Regex patterns replaced with `[REDACTED]` before a record is written.
+    pub redact: Option<Vec<String>>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
 pub struct ContextConfig {
     pub max_stm: usize,
+    /// Also drains the short-term memory once its estimated token count reaches this.
+    pub max_stm_tokens: Option<usize>,
+    /// Whether drained messages get summarized into long-term memory, on by default.
+    pub summarize_on_drain: Option<bool>,
+    /// Summarizes into long-term memory whenever this many tokens piled up since the last such summary.
+    pub summarize_every_tokens: Option<usize>,
+    /// Keeps separate running summaries of what the user shared and what the bot committed to
+    /// from drained messages, in the system prompt.
+    pub role_summaries: Option<bool>,
     pub save_to_disk_folder: Option<PathBuf>,
     pub system: SystemPromptBuilder,
+    /// Opening assistant message seeded into fresh contexts, supports the prompt placeholders.
+    pub greeting: Option<String>,
+    pub greet_after_clear: Option<bool>,
+    /// System note for the first message of a conversation with nothing in long-term memory either,
+    /// like asking a getting-to-know-you question. Supports the prompt placeholders.
+    pub cold_start_note: Option<String>,
+    /// Seeds contexts nobody talked to yet with up to this many (at most 100) of the channel's
+    /// recent messages, capped so they fit in `max_stm`.
+    pub backfill: Option<usize>,
+    /// Clears the short-term memory when a message comes in after this many seconds of silence.
+    pub auto_clear_after: Option<u64>,
+    /// Whether auto-cleared conversations get summarized into long-term memory first, on by default.
+    pub summarize_on_auto_clear: Option<bool>,
+    /// Shares one conversation per channel and tells the model who said what.
+    pub group_mode: Option<bool>,
+    /// Keeps at most this many branches (regenerations and edits) per message, dropping the oldest.
+    pub max_branches: Option<usize>,
+    /// System note for free will messages, supports the prompt placeholders (`{time_since}`, `{user}`, ...).
+    pub freewill_prompt: Option<String>,
+    /// Free will stays quiet once this many of its messages went unanswered, and only the latest
+    /// this many are kept in the context. 3 by default.
+    pub max_unanswered_freewill: Option<usize>,
+    /// Languages users can pick from with /language, besides `auto`.
+    pub languages: Option<Vec<String>>,
+    /// What users chose through commands, keyed by engine (user, or channel in group mode) id.
+    pub user_preferences: Option<HashMap<String, UserPreferences>>,
+    /// Regenerates replies that (nearly) repeat the previous one, up to this many times. Off by default.
+    pub duplicate_retries: Option<usize>,
+    /// How similar (0 to 1, by shared words) a reply has to be to count as a repeat, 0.9 by default.
+    pub duplicate_threshold: Option<f64>,
+    /// Reminds the model of its persona with a system note every this many user turns.
+    pub persona_reminder_every: Option<usize>,
+    /// Condensed persona the reminder repeats, supports the prompt placeholders.
+    /// Built from the name and tone when not set.
+    pub persona_reminder: Option<String>,
+}
+
+/// Per user overrides of the system prompt settings, unset ones fall back to the config's.
+/// Keyed like the engines, so in group mode everything but `tts` is set for the whole channel.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct UserPreferences {
+    /// Set with /timezone.
+    pub timezone: Option<Tz>,
+    /// Set with /aboutme, replaces the system prompt's `user_about`.
+    pub about: Option<String>,
+    /// Set with /tts, has replies come with audio when `tts` is enabled. Always kept by user.
+    pub tts: Option<bool>,
+    /// Set with /language, one of `languages` or `auto` for no restriction.
+    pub language: Option<String>,
+    /// Set with /standing, replaces the configured standing context.
+    pub standing_context: Option<String>,
+}
+
+/// Has to produce vectors of the same size as the main embedding model, or the memory
+/// collection will be rejected by its health check.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct FallbackEmbeddingConfig {
+    pub provider: Option<Provider>,
+    pub model: String,
+    pub api_key: Option<String>,
+    pub custom_url: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
 pub struct DiscordConfig {
     pub token: String,
+    /// Sends responses as replies to the message that triggered them.
+    pub reply_to_message: Option<bool>,
+    /// Reacts to messages as soon as they come in, until the reply is sent.
+    pub seen_reaction: Option<bool>,
+    /// Emoji of the seen reaction, 👀 by default.
+    pub seen_emoji: Option<String>,
+    /// Removes messages users delete from the context. Off by default.
+    pub forget_deleted: Option<bool>,
+    /// Also removes the bot's reply to a deleted message.
+    pub forget_deleted_replies: Option<bool>,
+    /// How long users have to wait between regenerations, in seconds. No cooldown by default.
+    pub regen_cooldown_secs: Option<u64>,
+    /// Whether user mentions in the bot's messages ping, on by default. `@everyone` and `@here` never do.
+    pub allow_user_mentions: Option<bool>,
+    /// Whether role mentions in the bot's messages ping, on by default.
+    pub allow_role_mentions: Option<bool>,
+    /// Registers commands in these guilds only, where they show up right away, instead of
+    /// globally (which can take up to an hour). Meant for development.
+    pub command_guilds: Option<Vec<u64>>,
+    /// How many gateway shards to run, 0 for as many as discord recommends. One by default,
+    /// only bots in a lot of guilds need more.
+    pub shards: Option<u32>,
+    /// How the markdown of replies is handled, by channel id. Kept as is in channels not listed.
+    pub channel_markdown: Option<HashMap<String, MarkdownMode>>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
@@ -34,28 +327,112 @@ pub struct FreewillConfig {
     pub min_time_secs: u64,
     pub max_time_secs: u64,
     pub steepness: f64,
+    /// Starts counting down to free will again right after /clear, instead of on the next message.
+    pub restart_after_clear: Option<bool>,
+    /// When the bot keeps from speaking up on its own, in each user's timezone.
+    pub quiet_hours: Option<QuietHours>,
+}
+
+/// A daily window, `start = "22:00"` and `end = "08:00"` spans midnight.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct QuietHours {
+    pub start: chrono::NaiveTime,
+    pub end: chrono::NaiveTime,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
 pub struct LLMConfig {
     pub api_key: String,
+    /// What users are told while `api_key` is missing, instead of the provider's errors.
+    pub setup_message: Option<String>,
     pub model: String,
+    /// Other models of the same provider a reply may be regenerated with.
+    pub alternate_models: Option<Vec<String>>,
     pub provider: Provider,
+    /// Same as `reasoning_mode = "both"`, kept for older configs.
     pub reason: Option<bool>,
+    /// Same as `reasoning_mode = "prompt"`, kept for older configs.
     pub fake_reason: Option<bool>,
+    /// Takes precedence over `reason` and `fake_reason`.
+    pub reasoning_mode: Option<ReasoningMode>,
+    /// Tags whose content gets stripped from replies, as `<tag>...</tag>` or `<|tag|>...<|/tag|>`.
+    /// `think`, `thinking` and `reasoning` by default.
+    pub reasoning_tags: Option<Vec<String>>,
     pub embedding_model: String,
     pub embedding_provider: Option<Provider>,
     pub embedding_custom_url: Option<String>,
     pub embedding_api_key: Option<String>,
+    /// How many embeddings are kept around for texts that get embedded again, 1000 by default, 0 turns it off.
+    pub embedding_cache_size: Option<usize>,
     pub custom_url: Option<String>,
+    /// On by default, but only takes effect if the provider supports tools (see `supports_tools`).
+    /// Without tools memories are still recalled into every prompt, just not by the model itself.
     pub use_tools: Option<bool>,
+    /// Enables or disables individual tools by name, tools that aren't listed stay enabled.
+    pub tools: Option<HashMap<String, bool>>,
+    /// `defer_text` by default.
+    pub tool_call_text: Option<ToolCallText>,
+    /// Tool results longer than this many characters get cut short, 8000 by default.
+    pub max_tool_result_chars: Option<usize>,
+    /// Tool calls with longer arguments are refused, 16000 characters by default.
+    pub max_tool_args_chars: Option<usize>,
+    /// Calls a tool that failed on its own (not because of its arguments) once more before
+    /// telling the model it failed. Off by default.
+    pub retry_tools: Option<bool>,
+    /// How long to wait before retrying a failed tool, 500 milliseconds by default.
+    pub tool_retry_delay_ms: Option<u64>,
+    /// Overrides the provider's tool capability, for endpoints serving models without function calling.
+    pub supports_tools: Option<bool>,
     pub force_lowercase: Option<bool>,
+    /// Applied to every reply, in order.
+    pub postprocess: Option<Vec<PostprocessStep>>,
+    /// Falls back to a default picked for the provider, 0 means no cap with `allow_unbounded`.
     pub max_tokens: Option<u64>,
+    /// Lets an unset (or 0) `max_tokens` leave replies uncapped.
+    pub allow_unbounded: Option<bool>,
+    pub auto_continue: Option<bool>,
+    pub max_continuations: Option<u32>,
+    /// Gives up on a completion (or continuation) that takes longer than this.
+    pub completion_timeout_secs: Option<u64>,
+    /// Falls back to a default picked for the provider.
     pub temperature: Option<f64>,
     pub top_p: Option<f64>,
+    /// Lets messages start with `[temp=0.2]`, `[top_p=0.9]` or `[max_tokens=200]` to tweak only
+    /// their own reply. Values out of bounds are ignored, and `max_tokens` only goes lower.
+    pub inline_overrides: Option<bool>,
+    /// 1.0 is off, sent as `frequency_penalty` (minus one) to providers that take that instead.
     pub repetition_penalty: Option<f64>,
     pub vector_size: Option<usize>,
     pub similarity_threshold: Option<f32>,
+    pub max_memories_per_user: Option<u64>,
+    /// How many memories get recalled into each prompt, 5 by default.
+    pub rag_limit: Option<u64>,
+    /// How many memories are fetched before ranking and filtering cut them down to `rag_limit`.
+    /// Can't be less than `rag_limit`, twice the limit when reranking by default.
+    pub rag_candidates: Option<u64>,
+    /// Replaces the instructions for summarizing conversations into long-term memory. Summaries
+    /// should call the people `<user>` and `<assistant>`, those get swapped for their names on recall.
+    pub summary_prompt: Option<String>,
+    /// Weight given to how often a memory was recalled when ranking recall results.
+    pub recall_boost: Option<f32>,
+    /// Weight given to how recently a memory was stored when ranking recall results.
+    pub recency_weight: Option<f32>,
+    /// Age in days at which a memory's recency bonus is halved, 30 by default.
+    pub recency_half_life_days: Option<f32>,
+    /// Has replies point out the recalled memories they relied on.
+    pub memory_citations: Option<MemoryCitations>,
+    /// Caps the estimated tokens of the memories recalled into a prompt, the lowest ranked go first.
+    pub memory_token_budget: Option<usize>,
+    /// Recalled memories longer than this many characters get cut short, on a word boundary.
+    pub max_memory_chars: Option<usize>,
+    /// Embedding backend to try when the configured one fails at startup.
+    pub fallback_embedding: Option<FallbackEmbeddingConfig>,
+    /// Keeps the bot running without long-term memory when no embedding backend works.
+    pub disable_memory_on_failure: Option<bool>,
+    /// Qdrant by default.
+    pub memory_backend: Option<MemoryBackendKind>,
+    /// `shared` by default. Memories made under one scope stay out of sight under the other.
+    pub memory_scope: Option<MemoryScope>,
     pub qdrant_host: String,
     pub qdrant_port: Option<u16>,
     pub qdrant_https: Option<bool>,
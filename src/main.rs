@@ -16,6 +16,14 @@ async fn main() {
 
     let config = ChatBotConfig::read(PathBuf::from("config.toml")).unwrap();
 
+    if let Some(deterministic) = config.deterministic.as_ref().filter(|d| d.enabled) {
+        log::warn!(
+            "deterministic mode is on (seed {}), this is meant for testing only",
+            deterministic.seed
+        );
+        utils::random::seed(deterministic.seed);
+    }
+
     let bot = bot::ChatBot::new(config).await.unwrap();
 
     bot.run().await;
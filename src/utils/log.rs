@@ -1,7 +1,23 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{LazyLock, RwLock},
+};
+
 use colog::format::CologStyle;
 use colored::Colorize;
 use env_logger::Builder;
-use log::{Level, LevelFilter};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serenity::all::UserId;
+
+/// Users whose interactions are logged more verbosely than everything else, set with /debug user.
+static VERBOSE: LazyLock<RwLock<HashMap<UserId, LevelFilter>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+tokio::task_local! {
+    /// Whose interaction the current task is handling.
+    static USER: UserId;
+}
 
 struct CustomLevelTokens;
 
@@ -33,12 +49,16 @@ impl CologStyle for CustomLevelTokens {
     }
 }
 
-pub struct Logger;
+pub struct Logger {
+    inner: env_logger::Logger,
+    level: LevelFilter,
+}
 
 impl Logger {
     pub fn init(level: Option<LevelFilter>) {
-        Builder::new()
-            .filter(Some("chatbot"), level.unwrap_or(LevelFilter::Info))
+        let inner = Builder::new()
+            // the gate below holds back what's past `level`, unless a verbose user is involved
+            .filter(Some("chatbot"), LevelFilter::Trace)
             .filter(Some("rig-core"), LevelFilter::Trace)
             .filter(Some("reqwest"), LevelFilter::Warn)
             .filter(Some("serenity"), LevelFilter::Warn)
@@ -46,6 +66,160 @@ impl Logger {
             .target(env_logger::Target::Stdout)
             .format(colog::formatter(CustomLevelTokens))
             .write_style(env_logger::WriteStyle::Always)
-            .init();
+            .build();
+
+        let logger = Self {
+            inner,
+            level: level.unwrap_or(LevelFilter::Info),
+        };
+
+        log::set_max_level(LevelFilter::Trace);
+        log::set_boxed_logger(Box::new(logger)).expect("logger is only set once");
+    }
+
+    /// Logs a user's interactions at `level` from now on, or like everything else with `None`.
+    /// Debug leaves conversation content out, it only shows up at trace.
+    pub fn set_verbose(user: UserId, level: Option<LevelFilter>) {
+        let mut verbose = VERBOSE
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        match level {
+            Some(level) => verbose.insert(user, level),
+            None => verbose.remove(&user),
+        };
+    }
+
+    /// Runs `future` as part of a user's interaction, so its logs follow their verbosity.
+    pub async fn scope<F: Future>(user: UserId, future: F) -> F::Output {
+        USER.scope(user, future).await
+    }
+
+    /// The user of the current task and their verbosity, if they have one set.
+    fn verbose() -> Option<(UserId, LevelFilter)> {
+        let user = USER.try_with(|user| *user).ok()?;
+        let verbose = VERBOSE
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        verbose.get(&user).map(|level| (user, *level))
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        if !metadata.target().starts_with("chatbot") || metadata.level() <= self.level {
+            return self.inner.enabled(metadata);
+        }
+
+        Self::verbose().is_some_and(|(_, level)| metadata.level() <= level)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        match Self::verbose() {
+            // tagged, so one user's flow can be picked out of everything else
+            Some((user, _)) => self.inner.log(
+                &Record::builder()
+                    .args(format_args!("[{user}] {}", record.args()))
+                    .metadata(record.metadata().clone())
+                    .module_path(record.module_path())
+                    .file(record.file())
+                    .line(record.line())
+                    .build(),
+            ),
+            None => self.inner.log(record),
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// Collects every record logged by the tests, for checking what ends up in the logs.
+#[cfg(test)]
+pub mod capture {
+    use std::sync::{Mutex, Once};
+
+    use log::{Level, LevelFilter, Log, Metadata, Record};
+
+    static RECORDS: Mutex<Vec<(Level, String)>> = Mutex::new(Vec::new());
+
+    struct Capture;
+
+    impl Log for Capture {
+        fn enabled(&self, _metadata: &Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record) {
+            RECORDS
+                .lock()
+                .unwrap()
+                .push((record.level(), record.args().to_string()));
+        }
+
+        fn flush(&self) {}
+    }
+
+    /// Starts capturing, the tests all share one logger so look for something unique to yours.
+    pub fn install() {
+        static INSTALL: Once = Once::new();
+
+        INSTALL.call_once(|| {
+            log::set_logger(&Capture).expect("no other logger is set in tests");
+            log::set_max_level(LevelFilter::Trace);
+        });
+    }
+
+    /// The levels of the records captured so far that mention `text`.
+    pub fn levels_mentioning(text: &str) -> Vec<Level> {
+        RECORDS
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, message)| message.contains(text))
+            .map(|(level, _)| *level)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata(level: Level) -> Metadata<'static> {
+        Metadata::builder()
+            .level(level)
+            .target("chatbot::test")
+            .build()
+    }
+
+    #[tokio::test]
+    async fn verbose_users_get_past_the_level() {
+        let logger = Logger {
+            inner: Builder::new()
+                .filter(Some("chatbot"), LevelFilter::Trace)
+                .build(),
+            level: LevelFilter::Info,
+        };
+        let (verbose, other) = (UserId::new(1991), UserId::new(1992));
+        Logger::set_verbose(verbose, Some(LevelFilter::Debug));
+
+        assert!(logger.enabled(&metadata(Level::Info)));
+        assert!(!logger.enabled(&metadata(Level::Debug)));
+
+        let logger = &logger;
+        let enabled =
+            |user, level| Logger::scope(user, async move { logger.enabled(&metadata(level)) });
+        assert!(enabled(verbose, Level::Debug).await);
+        // debug keeps the conversation content out
+        assert!(!enabled(verbose, Level::Trace).await);
+        assert!(!enabled(other, Level::Debug).await);
+
+        Logger::set_verbose(verbose, None);
+        assert!(!enabled(verbose, Level::Debug).await);
     }
 }
@@ -1,5 +1,12 @@
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
 use futures::StreamExt;
-use serenity::all::{ChannelId, CreateButton, CreateMessage, Http, MessageId};
+use serenity::all::{
+    ChannelId, CreateButton, CreateMessage, EditMessage, GuildId, Http, HttpError, Message,
+    MessageId, MessageReference, StatusCode, User,
+};
+use tokio::{sync::watch, task::JoinHandle};
 
 pub fn time_to_string(time: chrono::Duration) -> String {
     match time.num_seconds() {
@@ -33,16 +40,30 @@ pub fn chunk_string(s: &str) -> Vec<String> {
             break;
         }
 
-        // Consider the first 2000 characters.
-        let slice = &remaining[..2000];
+        // Consider the first 2000 bytes, without cutting a character in half.
+        let mut limit = 2000;
+        while !remaining.is_char_boundary(limit) {
+            limit -= 1;
+        }
+        let slice = &remaining[..limit];
 
         // Try to find a split point: newline, then period, then space.
-        let split_point = slice
+        let mut split_point = slice
             .rfind('\n')
             .or_else(|| slice.rfind('.'))
             .or_else(|| slice.rfind(' '))
             .map(|i| i + 1) // include the delimiter in the chunk
-            .unwrap_or(2000);
+            .unwrap_or(limit);
+
+        // an escaped character stays with its backslash, or the escape ends up showing
+        let backslashes = slice[..split_point]
+            .bytes()
+            .rev()
+            .take_while(|byte| *byte == b'\\')
+            .count();
+        if backslashes % 2 == 1 && split_point > 1 {
+            split_point -= 1;
+        }
 
         // Take the chunk up to the determined split point.
         let chunk = &remaining[..split_point];
@@ -58,6 +79,10 @@ pub fn chunk_string(s: &str) -> Vec<String> {
 pub struct ButtonStates {
     pub prev_disabled: bool,
     pub regen_or_next: RegenOrNext,
+    /// Alternate model the message was generated with, shown as a label next to the buttons.
+    pub model: Option<String>,
+    /// Selected branch and branch count of the message, shown as a label when there's more than one.
+    pub branch: Option<(usize, usize)>,
 }
 
 pub enum RegenOrNext {
@@ -79,7 +104,7 @@ pub fn chunk_message(message: &str, state: ButtonStates) -> anyhow::Result<Vec<C
         RegenOrNext::Regen => ("regen", '♻'),
     };
 
-    let message = CreateMessage::new()
+    let mut message = CreateMessage::new()
         .content(last)
         .button(
             CreateButton::new("prev")
@@ -102,11 +127,77 @@ pub fn chunk_message(message: &str, state: ButtonStates) -> anyhow::Result<Vec<C
                 .disabled(false),
         );
 
+    if let Some((selected, count)) = state.branch.filter(|(_, count)| *count > 1) {
+        message = message.button(
+            CreateButton::new("branch")
+                .label(format!("{selected}/{count}"))
+                .style(serenity::all::ButtonStyle::Secondary)
+                .disabled(true),
+        );
+    }
+
+    if let Some(model) = state.model {
+        message = message.button(
+            CreateButton::new("model")
+                .label(model)
+                .style(serenity::all::ButtonStyle::Secondary)
+                .disabled(true),
+        );
+    }
+
     messages.push(message);
 
     Ok(messages)
 }
 
+/// How much of each memory a citation footnote shows.
+const FOOTNOTE_MEMORY_LENGTH: usize = 60;
+
+/// A small footnote listing the recalled memories a reply relied on, shortened to fit.
+pub fn memory_footnote(memories: &[String]) -> String {
+    let memories = memories
+        .iter()
+        .map(|memory| {
+            let memory = memory.replace('\n', " ");
+            match memory.chars().count() > FOOTNOTE_MEMORY_LENGTH {
+                true => format!(
+                    "{}…",
+                    memory
+                        .chars()
+                        .take(FOOTNOTE_MEMORY_LENGTH)
+                        .collect::<String>()
+                ),
+                false => memory,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    format!("\n-# (recalled: {})", memories.join("; "))
+}
+
+/// The name someone goes by where they wrote: their server nickname in guilds, or their global
+/// display name in DMs, which come without any member data.
+pub fn author_name(author: &User, guild: Option<GuildId>, nick: Option<&str>) -> String {
+    match (guild, nick) {
+        (Some(_), Some(nick)) => nick.to_string(),
+        _ => author.display_name().to_string(),
+    }
+}
+
+/// Makes the first chunk a reply to the given message. If that message is gone by the time
+/// the chunks are sent, Discord sends them as plain messages instead of failing.
+pub fn reply_to(
+    mut messages: Vec<CreateMessage>,
+    reference: impl Into<MessageReference>,
+) -> Vec<CreateMessage> {
+    if let Some(first) = messages.first_mut() {
+        *first =
+            std::mem::take(first).reference_message(reference.into().fail_if_not_exists(false));
+    }
+
+    messages
+}
+
 pub async fn send_message_batch(
     channel: ChannelId,
     http: &Http,
@@ -144,3 +235,386 @@ pub async fn delete_message_batch(
         .into_iter()
         .collect()
 }
+
+const EDIT_RETRIES: u32 = 5;
+
+/// Edits a message, backing off and retrying whenever Discord rate limits the request.
+/// Other failures, like validation errors (50035), are returned right away, and empty content
+/// is refused without asking Discord at all.
+pub async fn edit_message(
+    http: &Http,
+    message: &mut Message,
+    edit: EditMessage,
+) -> anyhow::Result<()> {
+    // discord answers an empty message with a validation error, better not to send it at all
+    let empty = serde_json::to_value(&edit)
+        .ok()
+        .and_then(|edit| Some(edit.get("content")?.as_str()?.trim().is_empty()))
+        .unwrap_or(false);
+    if empty {
+        anyhow::bail!("refusing to edit message {} to empty content", message.id);
+    }
+
+    let mut backoff = Duration::from_millis(500);
+
+    for _ in 0..EDIT_RETRIES {
+        match message.edit(http, edit.clone()).await {
+            Ok(()) => return Ok(()),
+            Err(serenity::Error::Http(HttpError::UnsuccessfulRequest(response)))
+                if response.status_code == StatusCode::TOO_MANY_REQUESTS =>
+            {
+                log::warn!(
+                    "rate limited while editing message {}, retrying in {backoff:?}",
+                    message.id
+                );
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+            Err(why) => return Err(why.into()),
+        }
+    }
+
+    Err(anyhow::anyhow!(
+        "gave up editing message {} after {EDIT_RETRIES} rate limited attempts",
+        message.id
+    ))
+}
+
+/// Where [MessageEditor] puts the content, a discord message outside of tests.
+#[async_trait]
+pub trait EditTarget: Send + 'static {
+    async fn edit(&mut self, content: &str) -> anyhow::Result<()>;
+}
+
+#[async_trait]
+impl EditTarget for (Arc<Http>, Message) {
+    async fn edit(&mut self, content: &str) -> anyhow::Result<()> {
+        let (http, message) = self;
+        edit_message(http, message, EditMessage::new().content(content)).await
+    }
+}
+
+/// Coalesces rapid content updates of a single message (e.g. while streaming) into at most
+/// one edit per `interval`, always flushing the latest content and never an empty one.
+pub struct MessageEditor<T = (Arc<Http>, Message)> {
+    sender: watch::Sender<String>,
+    handle: JoinHandle<(T, String)>,
+}
+
+impl MessageEditor {
+    pub fn new(http: Arc<Http>, message: Message, interval: Duration) -> Self {
+        let content = message.content.clone();
+        Self::with_target((http, message), content, interval)
+    }
+}
+
+impl<T: EditTarget> MessageEditor<T> {
+    /// Takes over a target currently showing `content`.
+    pub fn with_target(target: T, content: String, interval: Duration) -> Self {
+        let (sender, mut receiver) = watch::channel(content.clone());
+
+        let handle = tokio::spawn(async move {
+            let mut target = target;
+            let mut sent = content;
+
+            while receiver.changed().await.is_ok() {
+                let content = receiver.borrow_and_update().clone();
+
+                if !content.trim().is_empty() && content != sent {
+                    match target.edit(&content).await {
+                        Ok(()) => sent = content,
+                        Err(why) => log::error!("failed to edit message: {why:?}"),
+                    }
+                }
+
+                tokio::time::sleep(interval).await;
+            }
+
+            (target, sent)
+        });
+
+        Self { sender, handle }
+    }
+
+    /// Queues new content, replacing any update that was not flushed yet.
+    pub fn update(&self, content: impl Into<String>) {
+        let content = content.into();
+
+        if content.trim().is_empty() {
+            log::warn!("refusing to edit a message to empty content");
+            return;
+        }
+
+        self.sender.send_replace(content);
+    }
+
+    /// Stops coalescing and makes sure the latest content made it to the target.
+    pub async fn finish(self) -> anyhow::Result<T> {
+        let latest = self.sender.borrow().clone();
+        drop(self.sender);
+
+        let (mut target, sent) = self.handle.await?;
+
+        if !latest.trim().is_empty() && latest != sent {
+            target.edit(&latest).await?;
+        }
+
+        Ok(target)
+    }
+}
+
+/// How often the preview of a reply that's still being continued gets updated.
+const PREVIEW_INTERVAL: Duration = Duration::from_millis(1500);
+
+/// Shows a reply as it's being continued in a message of its own, for as long as `progress`
+/// keeps reporting. Returns that message, for it to make way for the finished reply.
+pub async fn preview_reply(
+    http: Arc<Http>,
+    channel: ChannelId,
+    mut progress: watch::Receiver<String>,
+) -> Option<Message> {
+    let mut editor: Option<MessageEditor> = None;
+
+    while progress.changed().await.is_ok() {
+        let shown = progress.borrow_and_update().clone();
+        if shown.trim().is_empty() {
+            continue;
+        }
+
+        // only the end is shown once it outgrows a message, that's where the news is
+        let shown = match shown.char_indices().rev().nth(1900) {
+            Some((at, _)) => format!("…{}", &shown[at..]),
+            None => shown,
+        };
+
+        match &editor {
+            Some(editor) => editor.update(shown),
+            None => match channel.say(&http, shown).await {
+                Ok(message) => {
+                    editor = Some(MessageEditor::new(http.clone(), message, PREVIEW_INTERVAL))
+                }
+                Err(why) => {
+                    log::warn!("failed to send reply preview: {why:?}");
+                    return None;
+                }
+            },
+        }
+    }
+
+    match editor?.finish().await {
+        Ok((_, message)) => Some(message),
+        Err(why) => {
+            log::warn!("failed to finish reply preview: {why:?}");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    #[async_trait]
+    impl EditTarget for Recorder {
+        async fn edit(&mut self, content: &str) -> anyhow::Result<()> {
+            self.0.lock().unwrap().push(content.to_string());
+            Ok(())
+        }
+    }
+
+    impl Recorder {
+        fn edits(&self) -> Vec<String> {
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    #[test]
+    fn chunks_keep_escapes_whole() {
+        let text = format!("{}\\*{}", "a".repeat(1999), "b".repeat(10));
+        let chunks = chunk_string(&text);
+
+        assert_eq!(chunks.concat(), text);
+        assert!(chunks[1].starts_with("\\*"), "{:?}", &chunks[1][..4]);
+        assert!(chunks.iter().all(|chunk| chunk.len() <= 2000));
+    }
+
+    #[test]
+    fn chunks_never_cut_characters() {
+        let text = "é".repeat(1500);
+        let chunks = chunk_string(&text);
+
+        assert_eq!(chunks.concat(), text);
+        assert!(chunks.iter().all(|chunk| chunk.len() <= 2000));
+    }
+
+    #[tokio::test]
+    async fn message_editor_coalesces_rapid_updates() {
+        let recorder = Recorder::default();
+        let editor = MessageEditor::with_target(
+            recorder.clone(),
+            "start".to_string(),
+            Duration::from_millis(50),
+        );
+
+        for i in 0..100 {
+            editor.update(format!("update {i}"));
+        }
+        editor.finish().await.unwrap();
+
+        assert_eq!(recorder.edits(), vec!["update 99"]);
+    }
+
+    #[tokio::test]
+    async fn message_editor_flushes_the_latest_update() {
+        let recorder = Recorder::default();
+        let editor = MessageEditor::with_target(
+            recorder.clone(),
+            "start".to_string(),
+            Duration::from_millis(200),
+        );
+
+        editor.update("first");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        // still waiting out the interval after the first edit
+        editor.update("second");
+        editor.update("third");
+        editor.finish().await.unwrap();
+
+        assert_eq!(recorder.edits(), vec!["first", "third"]);
+    }
+
+    #[tokio::test]
+    async fn message_editor_skips_empty_and_unchanged_content() {
+        let recorder = Recorder::default();
+        let editor = MessageEditor::with_target(
+            recorder.clone(),
+            "start".to_string(),
+            Duration::from_millis(10),
+        );
+
+        editor.update("   ");
+        editor.update("start");
+        editor.finish().await.unwrap();
+
+        assert!(recorder.edits().is_empty());
+    }
+
+    #[tokio::test]
+    async fn empty_edits_never_reach_discord() {
+        let mut message = Message::default();
+        message.content = "hello".to_string();
+
+        // a request would fail differently, there's no token
+        let why = edit_message(
+            &Http::new(""),
+            &mut message,
+            EditMessage::new().content(" "),
+        )
+        .await
+        .unwrap_err();
+
+        assert!(why.to_string().contains("empty content"));
+    }
+
+    fn editor(content: &str) -> MessageEditor {
+        let mut message = Message::default();
+        message.content = content.to_string();
+
+        MessageEditor::new(Arc::new(Http::new("")), message, Duration::from_millis(10))
+    }
+
+    #[tokio::test]
+    async fn empty_updates_are_refused() {
+        let editor = editor("hello");
+
+        editor.update("  \n");
+
+        assert_eq!(*editor.sender.borrow(), "hello");
+    }
+
+    #[tokio::test]
+    async fn finishing_without_changes_edits_nothing() {
+        // there's no discord to edit here, so an edit would fail the finish
+        let (_, message) = editor("hello").finish().await.unwrap();
+
+        assert_eq!(message.content, "hello");
+    }
+
+    #[test]
+    fn only_the_first_chunk_is_a_reply() {
+        let chunks = vec![
+            CreateMessage::new().content("first"),
+            CreateMessage::new().content("second"),
+        ];
+
+        let chunks = reply_to(chunks, (ChannelId::new(1), MessageId::new(2)))
+            .into_iter()
+            .map(|chunk| serde_json::to_value(chunk).unwrap())
+            .collect::<Vec<_>>();
+
+        let reference = &chunks[0]["message_reference"];
+        assert_eq!(reference["message_id"], "2");
+        assert_eq!(reference["fail_if_not_exists"], false);
+        assert!(chunks[1].get("message_reference").is_none());
+    }
+
+    fn labels(state: ButtonStates) -> Vec<String> {
+        let message = serde_json::to_value(&chunk_message("hi", state).unwrap()[0]).unwrap();
+
+        message["components"][0]["components"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|button| button["label"].as_str().map(str::to_string))
+            .collect()
+    }
+
+    #[test]
+    fn the_branch_position_only_shows_with_several_branches() {
+        let state = |branch| ButtonStates {
+            prev_disabled: false,
+            regen_or_next: RegenOrNext::Regen,
+            model: None,
+            branch,
+        };
+
+        assert!(labels(state(Some((2, 3)))).contains(&"2/3".to_string()));
+        assert!(!labels(state(Some((1, 1)))).contains(&"1/1".to_string()));
+    }
+
+    fn user() -> User {
+        let mut user = User::default();
+        user.name = "alice_1".to_string();
+        user.global_name = Some("Alice".to_string());
+        user
+    }
+
+    #[test]
+    fn nicknames_are_used_in_guilds() {
+        let guild = Some(GuildId::new(1));
+
+        assert_eq!(author_name(&user(), guild, Some("Ally")), "Ally");
+        assert_eq!(author_name(&user(), guild, None), "Alice");
+        assert_eq!(author_name(&user(), guild, None), "Alice");
+    }
+
+    #[test]
+    fn direct_messages_use_the_display_name() {
+        assert_eq!(author_name(&user(), None, Some("Ally")), "Alice");
+    }
+
+    #[test]
+    fn footnotes_shorten_each_memory() {
+        let footnote = memory_footnote(&["likes\ntea".to_string(), "a".repeat(100)]);
+
+        assert_eq!(
+            footnote,
+            format!("\n-# (recalled: likes tea; {}…)", "a".repeat(60))
+        );
+    }
+}
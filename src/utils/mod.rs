@@ -1,5 +1,6 @@
 pub mod log;
 pub mod macros;
 pub mod misc;
+pub mod random;
 
 pub use misc::time_to_string;
@@ -0,0 +1,86 @@
+use std::sync::{Mutex, OnceLock};
+
+use rand::{
+    Rng, RngCore, SeedableRng,
+    distr::{
+        Distribution, StandardUniform,
+        uniform::{SampleRange, SampleUniform},
+    },
+    rngs::StdRng,
+};
+
+/// Seeded generator used instead of the thread rng once deterministic mode is on.
+static SEEDED: OnceLock<Mutex<StdRng>> = OnceLock::new();
+
+/// Makes every following random value come from a generator seeded with `seed`,
+/// so runs with the same inputs make the same choices. Only meant for testing.
+pub fn seed(seed: u64) {
+    if SEEDED.set(Mutex::new(StdRng::seed_from_u64(seed))).is_err() {
+        log::warn!("random generator was already seeded, ignoring seed {seed}");
+    }
+}
+
+/// Whether the random generator was seeded, i.e. deterministic mode is on.
+pub fn is_seeded() -> bool {
+    SEEDED.get().is_some()
+}
+
+fn with_rng<T>(f: impl FnOnce(&mut dyn RngCore) -> T) -> T {
+    draw(SEEDED.get(), f)
+}
+
+/// Draws from `seeded` if there is one, or from the thread rng otherwise.
+fn draw<T>(seeded: Option<&Mutex<StdRng>>, f: impl FnOnce(&mut dyn RngCore) -> T) -> T {
+    match seeded {
+        Some(seeded) => {
+            let mut rng = seeded
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            f(&mut *rng)
+        }
+        None => f(&mut rand::rng()),
+    }
+}
+
+pub fn random<T>() -> T
+where
+    StandardUniform: Distribution<T>,
+{
+    with_rng(|rng| rng.random())
+}
+
+pub fn random_range<T, R>(range: R) -> T
+where
+    T: SampleUniform,
+    R: SampleRange<T>,
+{
+    with_rng(|rng| rng.random_range(range))
+}
+
+pub fn random_bool(p: f64) -> bool {
+    with_rng(|rng| rng.random_bool(p))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The rolls freewill makes for a series of growing probabilities.
+    fn freewill_rolls(seeded: &Mutex<StdRng>) -> Vec<bool> {
+        (1..=50)
+            .map(|step| draw(Some(seeded), |rng| rng.random_bool(step as f64 / 50.0)))
+            .collect()
+    }
+
+    #[test]
+    fn the_same_seed_rolls_the_same_way() {
+        let first = Mutex::new(StdRng::seed_from_u64(7));
+        let second = Mutex::new(StdRng::seed_from_u64(7));
+        let other = Mutex::new(StdRng::seed_from_u64(8));
+
+        let rolls = freewill_rolls(&first);
+
+        assert_eq!(rolls, freewill_rolls(&second));
+        assert_ne!(rolls, freewill_rolls(&other));
+    }
+}